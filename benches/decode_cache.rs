@@ -0,0 +1,48 @@
+//! Compares running the bundled test ROMs with and without the decode cache
+//! Run with `cargo bench --bench decode_cache`
+
+use crust_8::io::headless_io::HeadlessIO;
+use crust_8::machine::Machine;
+use crust_8::{random, settings, timer};
+use std::fs;
+use std::time::{Duration, Instant};
+
+const ROMS: &[&str] = &["test_roms/test_opcode.ch8", "test_roms/c8_test.c8"];
+const STEPS: usize = 2_000_000;
+
+fn run(rom: &str, decode_mode: settings::DecodeMode) -> Duration {
+    let settings = settings::Settings::default().with_decode_mode(decode_mode);
+    let mut machine = Machine::new(
+        HeadlessIO::new(),
+        random::FixedRandomSource::new(vec![0x5A, 0xA5]),
+        timer::InstructionTimer::new(),
+        settings,
+    );
+
+    machine
+        .load_program(fs::File::open(rom).unwrap())
+        .expect("test ROM should be readable");
+
+    let start = Instant::now();
+    for _ in 0..STEPS {
+        machine.step_program().expect("test ROM should not fault");
+    }
+
+    start.elapsed()
+}
+
+fn main() {
+    for rom in ROMS {
+        let uncached = run(rom, settings::DecodeMode::Uncached);
+        let cached = run(rom, settings::DecodeMode::Cached);
+
+        println!(
+            "{}: {} steps uncached {:?}, cached {:?} ({:.2}x)",
+            rom,
+            STEPS,
+            uncached,
+            cached,
+            uncached.as_secs_f64() / cached.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,543 @@
+use crate::instruction::{Instruction, InstructionError};
+use crate::memory::Address;
+use crate::metadata::Flow;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io;
+use std::io::Write;
+
+/// A straight-line run of instructions with a single entry and a single exit
+#[derive(Debug, PartialEq)]
+pub struct BasicBlock {
+    pub start: Address,
+    pub instructions: Vec<(Address, Instruction)>,
+    // Set when the block runs into bytes that are not a valid instruction
+    pub error: Option<InstructionError>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum EdgeKind {
+    // Execution continues into the next block
+    Fallthrough,
+    Jump,
+    // Taken when the skip instruction skips
+    Skip,
+    Call,
+    // From a block that returns to the instruction after a call of its subroutine
+    Return,
+}
+
+#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Edge {
+    pub from: Address,
+    pub to: Address,
+    pub kind: EdgeKind,
+}
+
+/// Control-flow graph of the code reachable from a ROM's entry point
+pub struct ControlFlowGraph {
+    pub entry: Address,
+    pub blocks: BTreeMap<Address, BasicBlock>,
+    pub edges: BTreeSet<Edge>,
+    // Start addresses of the blocks in each subroutine, keyed by the subroutine's entry
+    // The entry point of the program is treated as a subroutine
+    pub subroutines: BTreeMap<Address, BTreeSet<Address>>,
+    // Addresses of BNNN jumps, whose targets depend on V0
+    pub indirect_jumps: BTreeSet<Address>,
+}
+
+struct Rom<'a> {
+    bytes: &'a [u8],
+    load_address: Address,
+}
+
+impl<'a> Rom<'a> {
+    fn decode(&self, address: Address) -> Option<Result<Instruction, InstructionError>> {
+        let offset = address.checked_sub(self.load_address)? as usize;
+        let bytes = self.bytes.get(offset..offset + 2)?;
+
+        Some(Instruction::from_bytes(bytes))
+    }
+}
+
+impl ControlFlowGraph {
+    /// Build the graph of a ROM loaded at the given address, starting at its first instruction
+    pub fn from_rom(bytes: &[u8], load_address: Address) -> ControlFlowGraph {
+        let rom = Rom {
+            bytes,
+            load_address,
+        };
+        let entry = load_address;
+
+        // Find every reachable instruction and the addresses that must start a block
+        let mut decoded = BTreeMap::new();
+        let mut leaders = BTreeSet::from([entry]);
+        let mut call_targets = BTreeSet::from([entry]);
+        let mut indirect_jumps = BTreeSet::new();
+        let mut pending = VecDeque::from([entry]);
+
+        while let Some(address) = pending.pop_front() {
+            if decoded.contains_key(&address) {
+                continue;
+            }
+
+            let instruction = match rom.decode(address) {
+                Some(instruction) => instruction,
+                // Running off the end of the ROM ends the path
+                None => continue,
+            };
+            decoded.insert(address, instruction.clone());
+
+            let instruction = match instruction {
+                Ok(instruction) => instruction,
+                Err(_) => continue,
+            };
+
+            let next = address.wrapping_add(2);
+            let successors = match instruction.flow() {
+                Flow::Next => vec![next],
+                Flow::Skip => vec![next, next.wrapping_add(2)],
+                Flow::Jump(target) => vec![target],
+                Flow::Call(target) => {
+                    call_targets.insert(target);
+                    vec![target, next]
+                }
+                Flow::JumpIndirect(_) => {
+                    indirect_jumps.insert(address);
+                    vec![]
+                }
+                Flow::Return => vec![],
+            };
+
+            if instruction.flow() != Flow::Next {
+                leaders.insert(next);
+                leaders.extend(successors.iter().copied());
+            }
+
+            pending.extend(successors);
+        }
+
+        let blocks = build_blocks(&decoded, &leaders);
+
+        let mut graph = ControlFlowGraph {
+            entry,
+            blocks,
+            edges: BTreeSet::new(),
+            subroutines: BTreeMap::new(),
+            indirect_jumps,
+        };
+
+        graph.add_local_edges();
+        for target in call_targets {
+            if graph.blocks.contains_key(&target) {
+                let members = graph.subroutine_blocks(target);
+                graph.subroutines.insert(target, members);
+            }
+        }
+        graph.add_return_edges();
+
+        graph
+    }
+
+    fn add_local_edges(&mut self) {
+        for block in self.blocks.values() {
+            let (address, instruction) = match block.instructions.last() {
+                Some(last) if block.error.is_none() => *last,
+                _ => continue,
+            };
+
+            let next = address.wrapping_add(2);
+            let mut add_edge = |to: Address, kind: EdgeKind| {
+                if self.blocks.contains_key(&to) {
+                    self.edges.insert(Edge {
+                        from: block.start,
+                        to,
+                        kind,
+                    });
+                }
+            };
+
+            match instruction.flow() {
+                Flow::Next => add_edge(next, EdgeKind::Fallthrough),
+                Flow::Skip => {
+                    add_edge(next, EdgeKind::Fallthrough);
+                    add_edge(next.wrapping_add(2), EdgeKind::Skip);
+                }
+                Flow::Jump(target) => add_edge(target, EdgeKind::Jump),
+                Flow::Call(target) => add_edge(target, EdgeKind::Call),
+                Flow::JumpIndirect(_) | Flow::Return => {}
+            }
+        }
+    }
+
+    // Blocks reachable from a subroutine's entry without following calls into other subroutines
+    fn subroutine_blocks(&self, entry: Address) -> BTreeSet<Address> {
+        let mut members = BTreeSet::new();
+        let mut pending = vec![entry];
+
+        while let Some(start) = pending.pop() {
+            if !members.insert(start) {
+                continue;
+            }
+
+            for edge in self.edges.iter().filter(|edge| edge.from == start) {
+                if edge.kind != EdgeKind::Call {
+                    pending.push(edge.to);
+                }
+            }
+
+            // A call continues at its return site once the callee returns
+            if let Some(return_site) = self.return_site(start) {
+                if self.blocks.contains_key(&return_site) {
+                    pending.push(return_site);
+                }
+            }
+        }
+
+        members
+    }
+
+    // The address execution resumes at when the call that ends this block returns
+    fn return_site(&self, start: Address) -> Option<Address> {
+        let block = self.blocks.get(&start)?;
+
+        match block.instructions.last() {
+            Some((address, Instruction::CallNNN { .. })) => Some(address.wrapping_add(2)),
+            _ => None,
+        }
+    }
+
+    fn add_return_edges(&mut self) {
+        let mut return_edges = Vec::new();
+
+        for (entry, members) in &self.subroutines {
+            let returning = members.iter().filter(|start| {
+                matches!(
+                    self.blocks[start].instructions.last(),
+                    Some((_, Instruction::Return))
+                )
+            });
+
+            let callers = self
+                .edges
+                .iter()
+                .filter(|edge| edge.kind == EdgeKind::Call && edge.to == *entry);
+
+            for from in returning {
+                for caller in callers.clone() {
+                    if let Some(return_site) = self.return_site(caller.from) {
+                        if self.blocks.contains_key(&return_site) {
+                            return_edges.push(Edge {
+                                from: *from,
+                                to: return_site,
+                                kind: EdgeKind::Return,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        self.edges.extend(return_edges);
+    }
+
+    /// Write the whole program as a single DOT graph
+    pub fn write_dot<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "digraph rom {{")?;
+        writeln!(writer, "    node [shape=box, fontname=monospace];")?;
+
+        for block in self.blocks.values() {
+            self.write_node(writer, block)?;
+        }
+
+        for edge in &self.edges {
+            write_edge(writer, edge)?;
+        }
+
+        writeln!(writer, "}}")
+    }
+
+    /// Write one DOT graph per subroutine
+    /// Calls out of the subroutine and returns into it are drawn as edges to plain nodes
+    pub fn write_dot_per_subroutine<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (entry, members) in &self.subroutines {
+            writeln!(writer, "digraph sub_{:03X} {{", entry)?;
+            writeln!(writer, "    label=\"subroutine {:#05X}\";", entry)?;
+            writeln!(writer, "    node [shape=box, fontname=monospace];")?;
+
+            for start in members {
+                self.write_node(writer, &self.blocks[start])?;
+            }
+
+            for edge in self
+                .edges
+                .iter()
+                .filter(|edge| members.contains(&edge.from) && edge.kind != EdgeKind::Return)
+            {
+                if !members.contains(&edge.to) {
+                    writeln!(
+                        writer,
+                        "    \"{:#05X}\" [shape=plaintext, label=\"sub {:#05X}\"];",
+                        edge.to, edge.to
+                    )?;
+                }
+                write_edge(writer, edge)?;
+            }
+
+            writeln!(writer, "}}")?;
+        }
+
+        Ok(())
+    }
+
+    fn write_node<W: Write>(&self, writer: &mut W, block: &BasicBlock) -> io::Result<()> {
+        let mut label = String::new();
+        for (address, instruction) in &block.instructions {
+            label += &format!("{:#05X}: {}\\l", address, instruction);
+        }
+        if let Some(error) = &block.error {
+            label += &format!("{}\\l", error);
+        }
+        if self.indirect_jumps.contains(&block.last_address()) {
+            label += "(target depends on V0)\\l";
+        }
+
+        let style = if block.start == self.entry {
+            ", style=bold"
+        } else if block.error.is_some() {
+            ", color=red"
+        } else {
+            ""
+        };
+
+        writeln!(
+            writer,
+            "    \"{:#05X}\" [label=\"{}\"{}];",
+            block.start, label, style
+        )
+    }
+}
+
+impl BasicBlock {
+    fn last_address(&self) -> Address {
+        self.instructions
+            .last()
+            .map_or(self.start, |(address, _)| *address)
+    }
+}
+
+fn write_edge<W: Write>(writer: &mut W, edge: &Edge) -> io::Result<()> {
+    let attributes = match edge.kind {
+        EdgeKind::Fallthrough => "",
+        EdgeKind::Jump => " [label=\"jump\"]",
+        EdgeKind::Skip => " [label=\"skip\"]",
+        EdgeKind::Call => " [label=\"call\", style=dashed]",
+        EdgeKind::Return => " [label=\"return\", style=dotted]",
+    };
+
+    writeln!(
+        writer,
+        "    \"{:#05X}\" -> \"{:#05X}\"{};",
+        edge.from, edge.to, attributes
+    )
+}
+
+fn build_blocks(
+    decoded: &BTreeMap<Address, Result<Instruction, InstructionError>>,
+    leaders: &BTreeSet<Address>,
+) -> BTreeMap<Address, BasicBlock> {
+    let mut blocks = BTreeMap::new();
+
+    for leader in leaders {
+        if !decoded.contains_key(leader) {
+            continue;
+        }
+
+        let mut block = BasicBlock {
+            start: *leader,
+            instructions: Vec::new(),
+            error: None,
+        };
+        let mut address = *leader;
+
+        while let Some(instruction) = decoded.get(&address) {
+            match instruction {
+                Ok(instruction) => block.instructions.push((address, *instruction)),
+                Err(error) => {
+                    block.error = Some(error.clone());
+                    break;
+                }
+            }
+
+            address = address.wrapping_add(2);
+            let ends_block = block.instructions.last().unwrap().1.flow() != Flow::Next;
+
+            if ends_block || leaders.contains(&address) {
+                break;
+            }
+        }
+
+        blocks.insert(*leader, block);
+    }
+
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction::*;
+
+    fn graph_of(program: &[Instruction]) -> ControlFlowGraph {
+        let bytes: Vec<u8> = program.iter().flat_map(|i| i.to_bytes()).collect();
+        ControlFlowGraph::from_rom(&bytes, 0x200)
+    }
+
+    fn edge(from: Address, to: Address, kind: EdgeKind) -> Edge {
+        Edge { from, to, kind }
+    }
+
+    #[test]
+    fn straight_line() {
+        let graph = graph_of(&[
+            ClearScreen,
+            StoreNNN { value: 0x300 },
+            JumpNNN { address: 0x204 },
+        ]);
+
+        assert_eq!(2, graph.blocks.len());
+        assert_eq!(2, graph.blocks[&0x200].instructions.len());
+        assert_eq!(
+            BTreeSet::from([
+                edge(0x200, 0x204, EdgeKind::Fallthrough),
+                edge(0x204, 0x204, EdgeKind::Jump),
+            ]),
+            graph.edges
+        );
+    }
+
+    #[test]
+    fn skips() {
+        let graph = graph_of(&[
+            SkipEqXNN {
+                register: 0,
+                value: 1,
+            },
+            StoreXNN {
+                register: 1,
+                value: 2,
+            },
+            JumpNNN { address: 0x204 },
+        ]);
+
+        assert_eq!(
+            vec![0x200, 0x202, 0x204],
+            graph.blocks.keys().copied().collect::<Vec<_>>()
+        );
+        assert!(graph
+            .edges
+            .contains(&edge(0x200, 0x202, EdgeKind::Fallthrough)));
+        assert!(graph.edges.contains(&edge(0x200, 0x204, EdgeKind::Skip)));
+        assert!(graph
+            .edges
+            .contains(&edge(0x202, 0x204, EdgeKind::Fallthrough)));
+    }
+
+    #[test]
+    fn calls_and_returns() {
+        let graph = graph_of(&[
+            CallNNN { address: 0x208 },
+            CallNNN { address: 0x208 },
+            JumpNNN { address: 0x204 },
+            // Unreachable data between the routines
+            StoreXNN {
+                register: 0xF,
+                value: 0xFF,
+            },
+            ClearScreen,
+            Return,
+        ]);
+
+        assert!(!graph.blocks.contains_key(&0x206));
+        assert_eq!(
+            BTreeSet::from([
+                edge(0x200, 0x208, EdgeKind::Call),
+                edge(0x202, 0x208, EdgeKind::Call),
+                edge(0x204, 0x204, EdgeKind::Jump),
+                edge(0x208, 0x202, EdgeKind::Return),
+                edge(0x208, 0x204, EdgeKind::Return),
+            ]),
+            graph.edges
+        );
+
+        assert_eq!(
+            BTreeSet::from([0x200, 0x202, 0x204]),
+            graph.subroutines[&0x200]
+        );
+        assert_eq!(BTreeSet::from([0x208]), graph.subroutines[&0x208]);
+    }
+
+    #[test]
+    fn invalid_and_indirect() {
+        let graph = graph_of(&[
+            SkipEqXNN {
+                register: 0,
+                value: 0,
+            },
+            JumpV0 { address: 0x300 },
+        ]);
+
+        // Skipping the jump runs into the zeroes past the end of the ROM
+        assert_eq!(BTreeSet::from([0x202]), graph.indirect_jumps);
+        assert!(!graph.blocks.contains_key(&0x204));
+
+        let graph = ControlFlowGraph::from_rom(&[0x00, 0xE0, 0xF1, 0x31], 0x200);
+        assert_eq!(1, graph.blocks.len());
+        assert_eq!(
+            Some(InstructionError::UnsupportedInstruction(0xF131)),
+            graph.blocks[&0x200].error
+        );
+    }
+
+    #[test]
+    fn dot_output() {
+        let graph = graph_of(&[
+            CallNNN { address: 0x204 },
+            JumpNNN { address: 0x200 },
+            Return,
+        ]);
+
+        let mut dot = Vec::new();
+        graph.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+
+        assert!(dot.starts_with("digraph rom {\n"));
+        assert!(dot.contains("    \"0x200\" [label=\"0x200: CALL 0x204\\l\", style=bold];\n"));
+        assert!(dot.contains("    \"0x200\" -> \"0x204\" [label=\"call\", style=dashed];\n"));
+        assert!(dot.contains("    \"0x204\" -> \"0x202\" [label=\"return\", style=dotted];\n"));
+        assert!(dot.ends_with("}\n"));
+
+        let mut dot = Vec::new();
+        graph.write_dot_per_subroutine(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+
+        assert_eq!(2, dot.matches("digraph").count());
+        assert!(dot.contains("digraph sub_204 {\n"));
+        assert!(dot.contains("    \"0x204\" [shape=plaintext, label=\"sub 0x204\"];\n"));
+    }
+
+    #[test]
+    fn test_roms() {
+        for rom in ["test_roms/test_opcode.ch8", "test_roms/c8_test.c8"] {
+            let bytes = std::fs::read(rom).unwrap();
+            let graph = ControlFlowGraph::from_rom(&bytes, 0x200);
+
+            assert!(!graph.blocks.is_empty());
+
+            // Every edge connects two blocks of the graph
+            for edge in &graph.edges {
+                assert!(graph.blocks.contains_key(&edge.from));
+                assert!(graph.blocks.contains_key(&edge.to));
+            }
+        }
+    }
+}
@@ -0,0 +1,708 @@
+use crate::analysis::cfg::ControlFlowGraph;
+use crate::instruction::Instruction;
+use crate::io::graphics::Resolution;
+use crate::memory::Address;
+use crate::metadata::Flow;
+use crate::platform::{Platform, ALL_PLATFORMS};
+use crate::settings::{BitShiftMode, MemoryMode, Settings, StackLocation};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+
+// Longest path followed when looking for the instruction that depends on a quirk
+const MAX_SEARCH_DEPTH: usize = 32;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LintKind {
+    // 8XY6 or 8XYE with X and Y different
+    Shift,
+    // I is used after FX55 or FX65 without being set again
+    MemoryIncrement,
+    // BNNN, which SUPER-CHIP reads as BXNN
+    JumpV0,
+    // VF is read after 8XY1, 8XY2 or 8XY3 without being set again
+    LogicFlag,
+    // A sprite drawn at a known position crosses or starts past the edge of the screen
+    EdgeDraw,
+    // FX33 or FX55 writes over reachable instructions
+    SelfModifying,
+    // 0NNN, which calls a machine-language routine of the original hardware
+    MachineCall,
+    // Bytes reached by execution that are not a known instruction
+    UnknownOpcode,
+}
+
+/// A quirk-sensitive pattern found in a ROM
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub address: Address,
+    pub kind: LintKind,
+    pub message: String,
+}
+
+/// The findings for a ROM and the platform it most likely targets
+pub struct Report {
+    pub findings: Vec<Finding>,
+    pub platform: Platform,
+    pub settings: Settings,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#05X}: {}", self.address, self.message)
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.findings.is_empty() {
+            writeln!(f, "No quirk-sensitive patterns found")?;
+        }
+
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Most likely platform: {}", self.platform)?;
+        // Only the settings that describe the platform, not the ones for running it
+        let settings = &self.settings;
+        writeln!(f, "Suggested settings:")?;
+        writeln!(f, "    bit_shift_mode: {:?}", settings.bit_shift_mode)?;
+        writeln!(f, "    memory_mode: {:?}", settings.memory_mode)?;
+        writeln!(f, "    draw_mode: {:?}", settings.draw_mode)?;
+        writeln!(f, "    machine_call_mode: {:?}", settings.machine_call_mode)?;
+        writeln!(f, "    memory_size: {:?}", settings.memory_size)?;
+        writeln!(f, "    program_start: {:#05X}", settings.program_start)?;
+        writeln!(f, "    font: {:?}", settings.font)?;
+        writeln!(f, "    font_address: {:#05X}", settings.font_address)?;
+        writeln!(f, "    resolution: {:?}", settings.resolution)?;
+        writeln!(f, "    stack_depth: {:?}", settings.stack_depth)?;
+        match settings.stack_location {
+            StackLocation::Registers => writeln!(f, "    stack_location: Registers")?,
+            StackLocation::Memory { address } => {
+                writeln!(f, "    stack_location: Memory at {:#05X}", address)?
+            }
+        }
+        write!(f, "    stack_overflow: {:?}", settings.stack_overflow)
+    }
+}
+
+// Evidence for the quirks of each platform, gathered while linting
+#[derive(Default)]
+struct Votes {
+    one_register_shift: usize,
+    two_register_shift: usize,
+    memory_increment: usize,
+    logic_flag: usize,
+}
+
+enum Search {
+    Continue,
+    Found,
+    Stop,
+}
+
+struct Linter {
+    graph: ControlFlowGraph,
+    // Every reachable instruction by address
+    instructions: BTreeMap<Address, Instruction>,
+    findings: Vec<Finding>,
+    votes: Votes,
+}
+
+/// Look for quirk-sensitive patterns in the code reachable from a ROM's entry point
+pub fn lint(bytes: &[u8], load_address: Address) -> Report {
+    let graph = ControlFlowGraph::from_rom(bytes, load_address);
+    let instructions = graph
+        .blocks
+        .values()
+        .flat_map(|block| block.instructions.iter().copied())
+        .collect();
+
+    let mut linter = Linter {
+        graph,
+        instructions,
+        findings: Vec::new(),
+        votes: Votes::default(),
+    };
+
+    linter.lint_instructions();
+    linter.lint_blocks();
+    linter.findings.sort_by_key(|finding| finding.address);
+
+    let platform = linter.suggest_platform();
+    Report {
+        findings: linter.findings,
+        platform,
+        settings: Settings::for_platform(platform),
+    }
+}
+
+impl Linter {
+    fn lint_instructions(&mut self) {
+        let written_v = self
+            .instructions
+            .values()
+            .fold(0, |mask, instruction| mask | instruction.writes().v);
+
+        let instructions: Vec<_> = self.instructions.iter().map(|(a, i)| (*a, *i)).collect();
+        for (address, instruction) in instructions {
+            match instruction {
+                Instruction::ShrXY { target, source } | Instruction::ShlXY { target, source }
+                    if target != source =>
+                {
+                    // A source register the program never sets suggests the source is ignored
+                    let source_written = written_v & (1 << source) != 0;
+                    if source_written {
+                        self.votes.two_register_shift += 1;
+                    } else {
+                        self.votes.one_register_shift += 1;
+                    }
+
+                    self.report(
+                        address,
+                        LintKind::Shift,
+                        format!(
+                            "{} shifts V{:X} on the COSMAC VIP but V{:X} on CHIP-48 and SUPER-CHIP",
+                            instruction, source, target
+                        ),
+                    );
+                }
+                Instruction::WriteToMemory { .. } | Instruction::ReadFromMemory { .. } => {
+                    let uses_i = self.search_forward(address, |next| {
+                        if next.reads().i {
+                            Search::Found
+                        } else if next.writes().i {
+                            Search::Stop
+                        } else {
+                            Search::Continue
+                        }
+                    });
+
+                    if uses_i {
+                        self.votes.memory_increment += 1;
+                        self.report(
+                            address,
+                            LintKind::MemoryIncrement,
+                            format!(
+                                "{} is followed by a use of I that depends on I advancing",
+                                instruction
+                            ),
+                        );
+                    }
+                }
+                Instruction::JumpV0 { address: target } => self.report(
+                    address,
+                    LintKind::JumpV0,
+                    format!(
+                        "{} jumps relative to V{:X} instead of V0 on SUPER-CHIP",
+                        instruction,
+                        target >> 8
+                    ),
+                ),
+                Instruction::OrXY { .. }
+                | Instruction::AndXY { .. }
+                | Instruction::XorXY { .. } => {
+                    let reads_flag = self.search_forward(address, |next| {
+                        if next.reads().uses_v(0xF) {
+                            Search::Found
+                        } else if next.writes().uses_v(0xF) {
+                            Search::Stop
+                        } else {
+                            Search::Continue
+                        }
+                    });
+
+                    if reads_flag {
+                        self.votes.logic_flag += 1;
+                        self.report(
+                            address,
+                            LintKind::LogicFlag,
+                            format!(
+                                "{} is followed by a read of VF, which the COSMAC VIP resets",
+                                instruction
+                            ),
+                        );
+                    }
+                }
+                Instruction::Sys { .. } => self.report(
+                    address,
+                    LintKind::MachineCall,
+                    format!(
+                        "{} calls a machine-language routine that only exists on the original hardware",
+                        instruction
+                    ),
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    // Track constant register values through each block to find draws and writes at known addresses
+    fn lint_blocks(&mut self) {
+        let mut findings = Vec::new();
+
+        for block in self.graph.blocks.values() {
+            let mut v: [Option<u8>; 0x10] = [None; 0x10];
+            let mut i: Option<Address> = None;
+
+            for (address, instruction) in &block.instructions {
+                match *instruction {
+                    Instruction::DrawXYN {
+                        x_register,
+                        y_register,
+                        bytes,
+                    } => {
+                        if let (Some(x), Some(y)) = (v[x_register as usize], v[y_register as usize])
+                        {
+                            let (x, y) = (x as usize, y as usize);
+                            let (width, height) =
+                                (Resolution::Low.width(), Resolution::Low.height());
+                            let message = if x >= width || y >= height {
+                                Some("starts off screen, which wraps on some platforms and not others")
+                            } else if x + 8 > width || y + bytes as usize > height {
+                                Some("crosses the edge of the screen, which clips on some platforms and wraps on others")
+                            } else {
+                                None
+                            };
+
+                            if let Some(message) = message {
+                                findings.push(Finding {
+                                    address: *address,
+                                    kind: LintKind::EdgeDraw,
+                                    message: format!(
+                                        "{} at ({}, {}) {}",
+                                        instruction, x, y, message
+                                    ),
+                                });
+                            }
+                        }
+                    }
+                    Instruction::StoreDecimal { .. } | Instruction::WriteToMemory { .. } => {
+                        let length = match *instruction {
+                            Instruction::WriteToMemory { max_register } => {
+                                max_register as Address + 1
+                            }
+                            _ => 3,
+                        };
+
+                        if let Some(start) = i {
+                            let end = start.wrapping_add(length);
+                            let overwrites_code = self
+                                .instructions
+                                .keys()
+                                .any(|code| *code < end && start < code.wrapping_add(2));
+
+                            if overwrites_code {
+                                findings.push(Finding {
+                                    address: *address,
+                                    kind: LintKind::SelfModifying,
+                                    message: format!(
+                                        "{} writes over instructions at {:#05X}..{:#05X}",
+                                        instruction, start, end
+                                    ),
+                                });
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+
+                // Update the known values after the instruction runs
+                match *instruction {
+                    Instruction::StoreXNN { register, value } => v[register as usize] = Some(value),
+                    Instruction::AddXNN { register, value } => {
+                        v[register as usize] = v[register as usize].map(|x| x.wrapping_add(value))
+                    }
+                    Instruction::StoreXY { target, source } => {
+                        v[target as usize] = v[source as usize]
+                    }
+                    Instruction::StoreNNN { value } => i = Some(value),
+                    Instruction::AddIX { register } => {
+                        i = i
+                            .zip(v[register as usize])
+                            .map(|(i, x)| i.wrapping_add(x as Address))
+                    }
+                    _ => {
+                        let writes = instruction.writes();
+                        for register in writes.v_registers() {
+                            v[register as usize] = None;
+                        }
+                        if writes.i {
+                            i = None;
+                        }
+                    }
+                }
+            }
+
+            if let Some(error) = &block.error {
+                // Addresses wrap around the end of memory like the graph's do
+                let address = block
+                    .start
+                    .wrapping_add(2 * block.instructions.len() as Address);
+                findings.push(Finding {
+                    address,
+                    kind: LintKind::UnknownOpcode,
+                    message: format!("execution reaches an {}", error),
+                });
+            }
+        }
+
+        self.findings.extend(findings);
+    }
+
+    // Follow every path after an instruction until the visitor finds what it is looking for or gives up
+    fn search_forward(&self, from: Address, visit: impl Fn(&Instruction) -> Search) -> bool {
+        let mut visited = BTreeSet::new();
+        let mut pending = self.successors(from);
+        let mut depth = 0;
+
+        while !pending.is_empty() && depth < MAX_SEARCH_DEPTH {
+            let mut next = Vec::new();
+
+            for address in pending {
+                if !visited.insert(address) {
+                    continue;
+                }
+
+                let instruction = match self.instructions.get(&address) {
+                    Some(instruction) => instruction,
+                    None => continue,
+                };
+
+                match visit(instruction) {
+                    Search::Found => return true,
+                    Search::Stop => {}
+                    Search::Continue => next.extend(self.successors(address)),
+                }
+            }
+
+            pending = next;
+            depth += 1;
+        }
+
+        false
+    }
+
+    fn successors(&self, address: Address) -> Vec<Address> {
+        let next = address.wrapping_add(2);
+
+        match self.instructions.get(&address).map(Instruction::flow) {
+            Some(Flow::Next) => vec![next],
+            Some(Flow::Skip) => vec![next, next.wrapping_add(2)],
+            Some(Flow::Jump(target)) => vec![target],
+            Some(Flow::Call(target)) => vec![target, next],
+            Some(Flow::JumpIndirect(_)) | Some(Flow::Return) | None => vec![],
+        }
+    }
+
+    fn report(&mut self, address: Address, kind: LintKind, message: String) {
+        self.findings.push(Finding {
+            address,
+            kind,
+            message,
+        });
+    }
+
+    // Pick the platform whose quirks explain the most findings among those that support every instruction
+    fn suggest_platform(&self) -> Platform {
+        let votes = &self.votes;
+
+        // Each platform is credited with the evidence its own preset agrees with
+        let score = |platform: Platform| {
+            let settings = Settings::for_platform(platform);
+            let shift = match settings.bit_shift_mode {
+                BitShiftMode::TwoRegister => votes.two_register_shift,
+                BitShiftMode::OneRegister => votes.one_register_shift,
+            };
+            let memory = match settings.memory_mode {
+                MemoryMode::Advance => votes.memory_increment,
+                MemoryMode::NoAdvance => 0,
+            };
+            // No setting covers the VF reset, which only the 1802 interpreters do
+            let logic = match platform {
+                Platform::CosmacVip | Platform::Eti660 => votes.logic_flag,
+                _ => 0,
+            };
+
+            shift + memory + logic
+        };
+
+        let supported = |platform: &Platform| {
+            self.instructions
+                .values()
+                .all(|instruction| instruction.supported_on(*platform))
+        };
+
+        // Reversed so that ties go to the platform listed first
+        ALL_PLATFORMS
+            .iter()
+            .copied()
+            .filter(supported)
+            .rev()
+            .max_by_key(|platform| score(*platform))
+            .unwrap_or(Platform::CosmacVip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction::*;
+
+    fn assemble(program: &[Instruction]) -> Vec<u8> {
+        program.iter().flat_map(|i| i.to_bytes()).collect()
+    }
+
+    fn kinds(report: &Report) -> Vec<(Address, LintKind)> {
+        report
+            .findings
+            .iter()
+            .map(|finding| (finding.address, finding.kind))
+            .collect()
+    }
+
+    #[test]
+    fn clean_program() {
+        let rom = assemble(&[
+            StoreXNN {
+                register: 0,
+                value: 1,
+            },
+            JumpNNN { address: 0x202 },
+        ]);
+
+        let report = lint(&rom, 0x200);
+
+        assert!(report.findings.is_empty());
+        assert_eq!(Platform::CosmacVip, report.platform);
+    }
+
+    #[test]
+    fn shifts_of_an_unset_register() {
+        let rom = assemble(&[
+            ShrXY {
+                target: 1,
+                source: 2,
+            },
+            ShlXY {
+                target: 3,
+                source: 3,
+            },
+            JumpNNN { address: 0x204 },
+        ]);
+
+        let report = lint(&rom, 0x200);
+
+        assert_eq!(vec![(0x200, LintKind::Shift)], kinds(&report));
+        assert_eq!(Platform::Chip48, report.platform);
+        assert!(matches!(
+            report.settings.bit_shift_mode,
+            BitShiftMode::OneRegister
+        ));
+    }
+
+    #[test]
+    fn memory_increment_and_logic_flag() {
+        let rom = assemble(&[
+            StoreNNN { value: 0x300 },
+            WriteToMemory { max_register: 1 },
+            // Reads the bytes after the ones just written
+            ReadFromMemory { max_register: 1 },
+            StoreNNN { value: 0x300 },
+            ReadFromMemory { max_register: 1 },
+            OrXY {
+                target: 0,
+                source: 1,
+            },
+            SkipEqXNN {
+                register: 0xF,
+                value: 0,
+            },
+            JumpNNN { address: 0x200 },
+            JumpNNN { address: 0x200 },
+        ]);
+
+        let report = lint(&rom, 0x200);
+
+        assert_eq!(
+            vec![
+                (0x202, LintKind::MemoryIncrement),
+                (0x20A, LintKind::LogicFlag),
+            ],
+            kinds(&report)
+        );
+        assert_eq!(Platform::CosmacVip, report.platform);
+        assert!(matches!(report.settings.memory_mode, MemoryMode::Advance));
+    }
+
+    #[test]
+    fn memory_increment_with_one_register_shifts() {
+        // CHIP-48 advances I like the COSMAC VIP but shifts like SUPER-CHIP
+        let rom = assemble(&[
+            ShrXY {
+                target: 1,
+                source: 2,
+            },
+            StoreNNN { value: 0x300 },
+            ReadFromMemory { max_register: 1 },
+            ReadFromMemory { max_register: 1 },
+            JumpNNN { address: 0x208 },
+        ]);
+
+        let report = lint(&rom, 0x200);
+
+        assert_eq!(
+            vec![(0x200, LintKind::Shift), (0x204, LintKind::MemoryIncrement)],
+            kinds(&report)
+        );
+        assert_eq!(Platform::Chip48, report.platform);
+        assert!(matches!(report.settings.memory_mode, MemoryMode::Advance));
+    }
+
+    #[test]
+    fn report_lists_the_platform_settings() {
+        let rom = assemble(&[JumpNNN { address: 0x200 }]);
+
+        let report = lint(&rom, 0x200).to_string();
+
+        assert!(report.starts_with("No quirk-sensitive patterns found"));
+        assert!(report.contains("Most likely platform: COSMAC VIP"));
+        for setting in [
+            "bit_shift_mode: TwoRegister",
+            "memory_mode: Advance",
+            "draw_mode: Clip",
+            "program_start: 0x200",
+            "font: Vip",
+            "font_address: 0x000",
+            "stack_depth: Vip",
+            "stack_location: Memory at 0xEA0",
+            "stack_overflow: Wrap",
+        ] {
+            assert!(
+                report.contains(setting),
+                "{} is missing from {}",
+                setting,
+                report
+            );
+        }
+    }
+
+    #[test]
+    fn edge_draws_and_self_modification() {
+        let rom = assemble(&[
+            StoreXNN {
+                register: 0,
+                value: 60,
+            },
+            StoreXNN {
+                register: 1,
+                value: 10,
+            },
+            DrawXYN {
+                x_register: 0,
+                y_register: 1,
+                bytes: 5,
+            },
+            AddXNN {
+                register: 0,
+                value: 10,
+            },
+            DrawXYN {
+                x_register: 0,
+                y_register: 1,
+                bytes: 5,
+            },
+            StoreNNN { value: 0x200 },
+            WriteToMemory { max_register: 1 },
+            JumpV0 { address: 0x200 },
+        ]);
+
+        let report = lint(&rom, 0x200);
+
+        assert_eq!(
+            vec![
+                (0x204, LintKind::EdgeDraw),
+                (0x208, LintKind::EdgeDraw),
+                (0x20C, LintKind::SelfModifying),
+                (0x20E, LintKind::JumpV0),
+            ],
+            kinds(&report)
+        );
+    }
+
+    #[test]
+    fn machine_call() {
+        let rom = assemble(&[Sys { address: 0x1F0 }, JumpNNN { address: 0x200 }]);
+
+        let report = lint(&rom, 0x200);
+
+        assert_eq!(vec![(0x200, LintKind::MachineCall)], kinds(&report));
+    }
+
+    #[test]
+    fn unsupported_instructions_rule_out_platforms() {
+        // The shift quirk points to CHIP-48, which can't call machine-language routines
+        let rom = assemble(&[Sys { address: 0x1F0 }, JumpNNN { address: 0x202 }]);
+        let graph = ControlFlowGraph::from_rom(&rom, 0x200);
+        let linter = Linter {
+            instructions: graph
+                .blocks
+                .values()
+                .flat_map(|block| block.instructions.iter().copied())
+                .collect(),
+            graph,
+            findings: Vec::new(),
+            votes: Votes {
+                one_register_shift: 1,
+                ..Votes::default()
+            },
+        };
+
+        assert_eq!(Platform::CosmacVip, linter.suggest_platform());
+    }
+
+    #[test]
+    fn machine_calls_may_set_any_register() {
+        // The routine might set the shift's source, so the shift is no evidence of ignoring it
+        let rom = assemble(&[
+            Sys { address: 0x1F0 },
+            ShrXY {
+                target: 1,
+                source: 2,
+            },
+            JumpNNN { address: 0x204 },
+        ]);
+
+        let report = lint(&rom, 0x200);
+
+        assert_eq!(
+            vec![(0x200, LintKind::MachineCall), (0x202, LintKind::Shift)],
+            kinds(&report)
+        );
+        assert_eq!(Platform::CosmacVip, report.platform);
+    }
+
+    #[test]
+    fn block_at_the_top_of_memory() {
+        // The last instruction in 64 KiB of memory is unknown
+        let rom = vec![0x60, 0x01, 0x60, 0x02, 0xF1, 0x31];
+
+        let report = lint(&rom, 0xFFFA);
+
+        assert_eq!(vec![(0xFFFE, LintKind::UnknownOpcode)], kinds(&report));
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let rom = vec![0x60, 0x01, 0xF1, 0x31];
+
+        let report = lint(&rom, 0x200);
+
+        assert_eq!(vec![(0x202, LintKind::UnknownOpcode)], kinds(&report));
+        assert_eq!(
+            "0x202: execution reaches an unsupported instruction 0xF131",
+            report.findings[0].to_string()
+        );
+    }
+}
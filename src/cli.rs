@@ -1,21 +1,224 @@
-use crate::io::piston_io;
-use clap::{ArgEnum, Parser};
-use std::fs;
+use crate::font::FontSet;
+use crate::io::color::{self, Color, ColorError, ColorScheme, Palettes};
+use crate::io::graphics::Resolution;
+use crate::io::persistence::PersistenceMode;
+use crate::io::piston_io::WindowOptions;
+use crate::memory::Address;
+use crate::platform::Platform;
+use crate::profiler;
+use crate::settings::{Engine, MachineCallMode};
+use clap::{ArgEnum, Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::{error, fs};
+
+// Keys that control the emulator rather than the CHIP-8 keypad
+const HOTKEYS: &str = "WINDOW HOTKEYS:
+    F8     Show or hide the debug overlay
+    F9     Switch to the next color scheme (also in the terminal)
+    F10    Start recording a GIF, or save the recording
+    F11    Switch between a window and fullscreen
+    F12    Save a screenshot";
 
 #[derive(Debug, Parser)]
 #[clap(
     author = "Austin Bourgerie (austin@bourg.me)",
     about = "A Chip8 emulator written entirely in Rust",
-    version
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    after_help = HOTKEYS
 )]
 pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
     /// Path to a ROM to load
+    #[clap(parse(try_from_str = open_file), required = true)]
+    pub rom: Option<fs::File>,
+
+    /// Hardware to emulate
+    #[clap(long, arg_enum, default_value_t = BackendName::Chip8)]
+    pub backend: BackendName,
+
+    /// CHIP-8 interpreter to load at 0x000 for the VIP backend, which runs the ROM as 1802 code without it
+    /// Also the source of the code page for --random vip
+    #[clap(long)]
+    pub vip_interpreter: Option<PathBuf>,
+
+    /// Monitor ROM for the VIP backend
+    #[clap(long)]
+    pub vip_monitor: Option<PathBuf>,
+
+    /// Where to show the display and read the keypad from
+    #[clap(long, arg_enum, default_value_t = FrontendName::Window)]
+    pub frontend: FrontendName,
+
+    /// Window pixels per display pixel, which sets the starting window size
+    #[clap(long, default_value_t = 10)]
+    pub scale: u32,
+
+    /// Starting window size as WIDTHxHEIGHT, overriding the size from the scale
+    #[clap(long, parse(try_from_str = parse_window_size))]
+    pub window_size: Option<[u32; 2]>,
+
+    /// Start in fullscreen, which F11 toggles
+    #[clap(long)]
+    pub fullscreen: bool,
+
+    /// Color scheme for the display: jazz, black-on-white, white-on-black or one from the palette file
+    #[clap(short, long, default_value = "jazz")]
+    pub color_scheme: String,
+
+    /// File of extra color schemes, one per line as NAME = BACKGROUND FOREGROUND [SECOND_PLANE BOTH_PLANES] in RRGGBB hexadecimal
+    #[clap(long)]
+    pub palette_file: Option<PathBuf>,
+
+    /// Foreground color in RRGGBB hexadecimal, overriding the color scheme's
+    #[clap(long, parse(try_from_str = color::parse_hex))]
+    pub fg: Option<Color>,
+
+    /// Background color in RRGGBB hexadecimal, overriding the color scheme's
+    #[clap(long, parse(try_from_str = color::parse_hex))]
+    pub bg: Option<Color>,
+
+    /// Hide the flicker of sprites being erased and redrawn
+    #[clap(long, arg_enum, default_value_t = PersistenceName::Off)]
+    pub persistence: PersistenceName,
+
+    /// Number of frames to average with blend persistence
+    #[clap(long, default_value_t = 3)]
+    pub blend_frames: usize,
+
+    /// Brightness a pixel keeps each frame after it turns off with phosphor persistence, from 0 to 1
+    #[clap(long, default_value_t = 0.5)]
+    pub phosphor_decay: f32,
+
+    /// Emulate the quirks of a platform's interpreter
+    #[clap(short, long, arg_enum)]
+    pub platform: Option<PlatformName>,
+
+    /// Address to load the program at and start running from, in hexadecimal
+    #[clap(long, parse(try_from_str = parse_address))]
+    pub load_address: Option<Address>,
+
+    /// Run each instruction for as long as it takes on the COSMAC VIP instead of a fixed time
+    #[clap(long)]
+    pub vip_timing: bool,
+
+    /// How the chip8 backend runs instructions
+    #[clap(long, arg_enum, default_value_t = EngineName::Interpreter)]
+    pub engine: EngineName,
+
+    /// Keep decoded instructions until the memory under them is written
+    #[clap(long)]
+    pub decode_cache: bool,
+
+    /// How to handle 0NNN machine-language calls, where native stops unless a routine is registered for the address
+    #[clap(long, arg_enum)]
+    pub machine_calls: Option<MachineCallName>,
+
+    /// Random number generator used by CXNN, where vip reads the code page of --vip-interpreter
+    #[clap(
+        long,
+        arg_enum,
+        default_value_t = RandomName::Seeded,
+        requires_if("vip", "vip-interpreter")
+    )]
+    pub random: RandomName,
+
+    /// Seed for the random number generator, or R9 for the VIP's, chosen at random and printed if not given
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    /// Built-in font to load into interpreter memory
+    #[clap(long, arg_enum)]
+    pub font: Option<FontName>,
+
+    /// Address of the font in memory, in hexadecimal [default: the platform's font address, or 0]
+    #[clap(long, parse(try_from_str = parse_address))]
+    pub font_address: Option<Address>,
+
+    /// Path to a font file starting with the 16 five-byte glyphs for 0-F, replacing the built-in font
+    #[clap(long)]
+    pub font_file: Option<PathBuf>,
+
+    /// Display resolution to start in
+    #[clap(long, arg_enum)]
+    pub resolution: Option<ResolutionName>,
+
+    /// Run without a window
+    #[clap(long)]
+    pub headless: bool,
+
+    /// Save a screenshot and exit once the timers have ticked this many frames
+    #[clap(long, requires = "headless")]
+    pub screenshot_at_frame: Option<u64>,
+
+    /// File to save the screenshot to, as PNG, PBM or PPM by its extension
+    #[clap(long, default_value = "screenshot.png")]
+    pub screenshot_output: PathBuf,
+
+    /// Size of the square each pixel is scaled up to in screenshots
+    #[clap(long, default_value_t = 10)]
+    pub screenshot_scale: usize,
+
+    /// Record the first frames of a headless run to an animated GIF
+    #[clap(long, requires_all = &["headless", "record-frames"])]
+    pub record: Option<PathBuf>,
+
+    /// Number of frames to record
+    #[clap(long, requires = "record")]
+    pub record_frames: Option<u64>,
+
+    /// Size of the square each pixel is scaled up to in recordings
+    #[clap(long, default_value_t = 4)]
+    pub record_scale: usize,
+
+    /// Profile the program and print a report when the emulator exits, with the chip8 backend only
+    #[clap(long, arg_enum)]
+    pub profile: Option<ProfileFormat>,
+
+    /// Write the profile report to a file instead of standard output
+    #[clap(long, requires = "profile")]
+    pub profile_output: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Write the control-flow graph of a ROM in Graphviz DOT format
+    Cfg(CfgArgs),
+    /// Look for quirk-sensitive patterns in a ROM and suggest a platform to run it on
+    Lint(LintArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct CfgArgs {
+    /// Path to a ROM to analyse
     #[clap(parse(try_from_str = open_file))]
     pub rom: fs::File,
 
-    /// Color scheme for the display
-    #[clap(short, long, arg_enum, default_value_t = ColorSchemeName::Jazz)]
-    pub color_scheme: ColorSchemeName,
+    /// Address the ROM is loaded at, in hexadecimal
+    #[clap(long, parse(try_from_str = parse_address), default_value = "200")]
+    pub load_address: Address,
+
+    /// Write one graph per subroutine instead of one graph for the whole program
+    #[clap(long)]
+    pub per_subroutine: bool,
+
+    /// Write the graph to a file instead of standard output
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct LintArgs {
+    /// Path to a ROM to analyse
+    #[clap(parse(try_from_str = open_file))]
+    pub rom: fs::File,
+
+    /// Address the ROM is loaded at, in hexadecimal
+    #[clap(long, parse(try_from_str = parse_address), default_value = "200")]
+    pub load_address: Address,
 }
 
 fn open_file(path: &str) -> Result<fs::File, String> {
@@ -23,18 +226,227 @@ fn open_file(path: &str) -> Result<fs::File, String> {
 }
 
 #[derive(ArgEnum, Clone, Debug)]
-pub enum ColorSchemeName {
-    BlackOnWhite,
-    WhiteOnBlack,
-    Jazz,
+pub enum BackendName {
+    // Interpret CHIP-8 instructions directly
+    Chip8,
+    // Emulate the COSMAC VIP's CPU, video and keypad
+    Vip,
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+pub enum FrontendName {
+    // An OpenGL window
+    Window,
+    // The terminal, drawn with half-block characters
+    Terminal,
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+pub enum PersistenceName {
+    // Show each frame as it is
+    Off,
+    // Average the last few frames
+    Blend,
+    // Fade pixels out after they turn off
+    Phosphor,
+    // Show every pixel that was on during the frame
+    AnyLit,
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+pub enum ResolutionName {
+    // 64x32
+    Low,
+    // 64x64
+    Tall,
+    // 128x64
+    High,
+    // 256x192
+    Mega,
+}
+
+impl From<ResolutionName> for Resolution {
+    fn from(name: ResolutionName) -> Self {
+        match name {
+            ResolutionName::Low => Resolution::Low,
+            ResolutionName::Tall => Resolution::Tall,
+            ResolutionName::High => Resolution::High,
+            ResolutionName::Mega => Resolution::Mega,
+        }
+    }
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+pub enum EngineName {
+    // Decode and run one instruction at a time
+    Interpreter,
+    // Translate runs of instructions into blocks of operations
+    Threaded,
+}
+
+impl From<EngineName> for Engine {
+    fn from(name: EngineName) -> Self {
+        match name {
+            EngineName::Interpreter => Engine::Interpreter,
+            EngineName::Threaded => Engine::Threaded,
+        }
+    }
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+pub enum MachineCallName {
+    // Skip the call
+    Ignore,
+    // Stop the emulator
+    Fault,
+    // Run the native routine for the called address
+    Native,
+}
+
+impl From<MachineCallName> for MachineCallMode {
+    fn from(name: MachineCallName) -> Self {
+        match name {
+            MachineCallName::Ignore => MachineCallMode::Ignore,
+            MachineCallName::Fault => MachineCallMode::Fault,
+            MachineCallName::Native => MachineCallMode::Native,
+        }
+    }
+}
+
+impl Cli {
+    /// The built-in palettes along with any from the palette file
+    pub fn palettes(&self) -> Result<Palettes, Box<dyn error::Error>> {
+        let mut palettes = Palettes::built_in();
+        if let Some(path) = &self.palette_file {
+            palettes.load(&fs::read_to_string(path)?)?;
+        }
+
+        Ok(palettes)
+    }
+
+    /// The chosen color scheme with any colors given on their own swapped in
+    /// Overriding the background or foreground fills in the plane colors between them again
+    pub fn color_scheme(&self, palettes: &Palettes) -> Result<ColorScheme, ColorError> {
+        let scheme = palettes.get(&self.color_scheme)?;
+
+        Ok(match (self.bg, self.fg) {
+            (None, None) => scheme,
+            (background, foreground) => ColorScheme::two_color(
+                background.unwrap_or(scheme.background),
+                foreground.unwrap_or(scheme.foreground),
+            ),
+        })
+    }
+
+    pub fn persistence_mode(&self) -> PersistenceMode {
+        match self.persistence {
+            PersistenceName::Off => PersistenceMode::Off,
+            PersistenceName::Blend => PersistenceMode::Blend {
+                frames: self.blend_frames,
+            },
+            PersistenceName::Phosphor => PersistenceMode::Phosphor {
+                decay: self.phosphor_decay,
+            },
+            PersistenceName::AnyLit => PersistenceMode::AnyLit,
+        }
+    }
+
+    pub fn window_options(&self) -> WindowOptions {
+        let options = WindowOptions::default()
+            .with_scale(self.scale)
+            .with_fullscreen(self.fullscreen);
+
+        match self.window_size {
+            Some(size) => options.with_size(size),
+            None => options,
+        }
+    }
+}
+
+fn parse_address(address: &str) -> Result<Address, String> {
+    let digits = address.trim_start_matches("0x").trim_start_matches("0X");
+
+    Address::from_str_radix(digits, 16).map_err(|e| e.to_string())
+}
+
+fn parse_window_size(size: &str) -> Result<[u32; 2], String> {
+    let (width, height) = size
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, but was {}", size))?;
+
+    Ok([
+        width.parse().map_err(|e| format!("invalid width: {}", e))?,
+        height
+            .parse()
+            .map_err(|e| format!("invalid height: {}", e))?,
+    ])
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+pub enum FontName {
+    Standard,
+    Vip,
+    Dream6800,
+    Eti660,
+    FishNChips,
+    Schip,
+}
+
+impl From<FontName> for FontSet {
+    fn from(name: FontName) -> Self {
+        match name {
+            FontName::Standard => FontSet::Standard,
+            FontName::Vip => FontSet::Vip,
+            FontName::Dream6800 => FontSet::Dream6800,
+            FontName::Eti660 => FontSet::Eti660,
+            FontName::FishNChips => FontSet::FishNChips,
+            FontName::Schip => FontSet::SuperChip,
+        }
+    }
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+pub enum PlatformName {
+    Vip,
+    Eti660,
+    Chip48,
+    Schip,
+    XoChip,
+}
+
+impl From<PlatformName> for Platform {
+    fn from(name: PlatformName) -> Self {
+        match name {
+            PlatformName::Vip => Platform::CosmacVip,
+            PlatformName::Eti660 => Platform::Eti660,
+            PlatformName::Chip48 => Platform::Chip48,
+            PlatformName::Schip => Platform::SuperChip,
+            PlatformName::XoChip => Platform::XoChip,
+        }
+    }
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+pub enum RandomName {
+    // A seeded pseudo-random generator
+    Seeded,
+    // The COSMAC VIP interpreter's generator
+    Vip,
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+pub enum ProfileFormat {
+    Text,
+    Csv,
+    Json,
 }
 
-impl Into<piston_io::ColorScheme> for ColorSchemeName {
-    fn into(self) -> piston_io::ColorScheme {
-        match self {
-            ColorSchemeName::BlackOnWhite => piston_io::BLACK_ON_WHITE,
-            ColorSchemeName::WhiteOnBlack => piston_io::WHITE_ON_BLACK,
-            ColorSchemeName::Jazz => piston_io::JAZZ_COLORS,
+impl From<ProfileFormat> for profiler::ReportFormat {
+    fn from(format: ProfileFormat) -> Self {
+        match format {
+            ProfileFormat::Text => profiler::ReportFormat::Text,
+            ProfileFormat::Csv => profiler::ReportFormat::Csv,
+            ProfileFormat::Json => profiler::ReportFormat::Json,
         }
     }
 }
@@ -0,0 +1,541 @@
+/// Memory and peripherals attached to an 1802
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+
+    fn write(&mut self, address: u16, value: u8);
+
+    /// Value put on the data bus by the device selected with INP 1-7
+    fn input(&mut self, port: u8) -> u8;
+
+    /// Value taken from the data bus by the device selected with OUT 1-7
+    fn output(&mut self, port: u8, value: u8);
+
+    /// Whether external flag EF1-EF4 is asserted
+    fn flag(&mut self, flag: u8) -> bool;
+}
+
+/// RCA CDP1802 CPU
+/// Timing is counted in machine cycles of 8 clock pulses each
+#[derive(Clone, Debug)]
+pub struct Cdp1802 {
+    pub r: [u16; 0x10],
+    pub d: u8,
+    pub df: bool,
+    // Index of the register used as the program counter
+    pub p: u8,
+    // Index of the register used as the data pointer
+    pub x: u8,
+    // X and P saved by an interrupt or MARK
+    pub t: u8,
+    pub ie: bool,
+    pub q: bool,
+    // Set by IDL until the next DMA or interrupt
+    pub idle: bool,
+}
+
+// Most instructions take a fetch and an execute cycle, long branches and skips take two execute cycles
+const SHORT_CYCLES: u32 = 2;
+const LONG_CYCLES: u32 = 3;
+
+impl Cdp1802 {
+    /// A CPU in its reset state, which starts fetching from address 0 with R0 as the program counter
+    pub fn new() -> Cdp1802 {
+        Cdp1802 {
+            r: [0; 0x10],
+            d: 0,
+            df: false,
+            p: 0,
+            x: 0,
+            t: 0,
+            ie: true,
+            q: false,
+            idle: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.r[0] = 0;
+        self.p = 0;
+        self.x = 0;
+        self.q = false;
+        self.ie = true;
+        self.idle = false;
+    }
+
+    /// Take an interrupt if interrupts are enabled
+    /// Returns the machine cycles used
+    pub fn interrupt(&mut self) -> u32 {
+        if !self.ie {
+            return 0;
+        }
+
+        self.t = self.x << 4 | self.p;
+        self.p = 1;
+        self.x = 2;
+        self.ie = false;
+        self.idle = false;
+        1
+    }
+
+    /// Read the byte at R0 for an output DMA cycle and advance R0
+    pub fn dma_out(&mut self, bus: &mut impl Bus) -> u8 {
+        let value = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        value
+    }
+
+    /// Run one instruction, or one idle cycle after IDL
+    /// Returns the machine cycles used
+    pub fn step(&mut self, bus: &mut impl Bus) -> u32 {
+        if self.idle {
+            return 1;
+        }
+
+        let opcode = self.fetch(bus);
+        let n = (opcode & 0xF) as usize;
+
+        match opcode >> 4 {
+            0x0 if n == 0 => self.idle = true,
+            // LDN
+            0x0 => self.d = bus.read(self.r[n]),
+            // INC
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            // DEC
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            0x3 => {
+                let condition = self.short_condition(bus, n as u8);
+                self.short_branch(bus, condition);
+            }
+            // LDA
+            0x4 => {
+                self.d = bus.read(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            }
+            // STR
+            0x5 => bus.write(self.r[n], self.d),
+            // IRX
+            0x6 if n == 0 => self.inc_x(),
+            // OUT 1-7
+            0x6 if n < 8 => {
+                let value = bus.read(self.rx());
+                bus.output(n as u8, value);
+                self.inc_x();
+            }
+            // INP 1-7, with 68 selecting no device
+            0x6 => {
+                self.d = bus.input(n as u8 - 8);
+                bus.write(self.rx(), self.d);
+            }
+            0x7 => self.execute_7(bus, n as u8),
+            // GLO
+            0x8 => self.d = self.r[n] as u8,
+            // GHI
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            // PLO
+            0xA => self.r[n] = self.r[n] & 0xFF00 | self.d as u16,
+            // PHI
+            0xB => self.r[n] = self.r[n] & 0x00FF | (self.d as u16) << 8,
+            0xC => {
+                self.execute_long(bus, n as u8);
+                return LONG_CYCLES;
+            }
+            // SEP
+            0xD => self.p = n as u8,
+            // SEX
+            0xE => self.x = n as u8,
+            _ => self.execute_f(bus, n as u8),
+        }
+
+        SHORT_CYCLES
+    }
+
+    fn execute_7(&mut self, bus: &mut impl Bus, n: u8) {
+        match n {
+            // RET and DIS
+            0x0 | 0x1 => {
+                let value = bus.read(self.rx());
+                self.inc_x();
+                self.x = value >> 4;
+                self.p = value & 0xF;
+                self.ie = n == 0;
+            }
+            // LDXA
+            0x2 => {
+                self.d = bus.read(self.rx());
+                self.inc_x();
+            }
+            // STXD
+            0x3 => {
+                bus.write(self.rx(), self.d);
+                self.r[self.x as usize] = self.rx().wrapping_sub(1);
+            }
+            // ADC
+            0x4 => {
+                let value = bus.read(self.rx());
+                self.add(value, self.df);
+            }
+            // SDB
+            0x5 => {
+                let value = bus.read(self.rx());
+                self.subtract(value, self.d, self.df);
+            }
+            // SHRC
+            0x6 => {
+                let carry = self.d & 1 != 0;
+                self.d = self.d >> 1 | (self.df as u8) << 7;
+                self.df = carry;
+            }
+            // SMB
+            0x7 => {
+                let value = bus.read(self.rx());
+                self.subtract(self.d, value, self.df);
+            }
+            // SAV
+            0x8 => bus.write(self.rx(), self.t),
+            // MARK
+            0x9 => {
+                self.t = self.x << 4 | self.p;
+                bus.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            // REQ and SEQ
+            0xA | 0xB => self.q = n == 0xB,
+            // ADCI
+            0xC => {
+                let value = self.fetch(bus);
+                self.add(value, self.df);
+            }
+            // SDBI
+            0xD => {
+                let value = self.fetch(bus);
+                self.subtract(value, self.d, self.df);
+            }
+            // SHLC
+            0xE => {
+                let carry = self.d & 0x80 != 0;
+                self.d = self.d << 1 | self.df as u8;
+                self.df = carry;
+            }
+            // SMBI
+            _ => {
+                let value = self.fetch(bus);
+                self.subtract(self.d, value, self.df);
+            }
+        }
+    }
+
+    fn execute_long(&mut self, bus: &mut impl Bus, n: u8) {
+        let test = match n & 0x3 {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            _ => self.df,
+        };
+
+        match n {
+            // LBR, LBQ, LBZ, LBDF and their inverses LSKP, LBNQ, LBNZ, LBNF
+            0x0..=0x3 | 0x8..=0xB => {
+                if test == (n < 0x8) {
+                    let high = bus.read(self.pc());
+                    let low = bus.read(self.pc().wrapping_add(1));
+                    self.r[self.p as usize] = u16::from_be_bytes([high, low]);
+                } else {
+                    self.skip(2);
+                }
+            }
+            // NOP
+            0x4 => {}
+            // LSNQ, LSNZ and LSNF
+            0x5..=0x7 => {
+                if !test {
+                    self.skip(2);
+                }
+            }
+            // LSIE
+            0xC => {
+                if self.ie {
+                    self.skip(2);
+                }
+            }
+            // LSQ, LSZ and LSDF
+            _ => {
+                if test {
+                    self.skip(2);
+                }
+            }
+        }
+    }
+
+    fn execute_f(&mut self, bus: &mut impl Bus, n: u8) {
+        // SHR and SHL have no operand
+        if n & 0x7 == 0x6 {
+            if n == 0xE {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            } else {
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+            }
+            return;
+        }
+
+        // F8-FF take their operand from the instruction stream instead of M(R(X))
+        let value = if n < 0x8 {
+            bus.read(self.rx())
+        } else {
+            self.fetch(bus)
+        };
+
+        match n & 0x7 {
+            // LDX and LDI
+            0x0 => self.d = value,
+            // OR and ORI
+            0x1 => self.d |= value,
+            // AND and ANI
+            0x2 => self.d &= value,
+            // XOR and XRI
+            0x3 => self.d ^= value,
+            // ADD and ADI
+            0x4 => self.add(value, false),
+            // SD and SDI
+            0x5 => self.subtract(value, self.d, true),
+            // SM and SMI
+            _ => self.subtract(self.d, value, true),
+        }
+    }
+
+    fn short_condition(&mut self, bus: &mut impl Bus, n: u8) -> bool {
+        let test = match n & 0x7 {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            flag => bus.flag(flag - 3),
+        };
+
+        // 38-3F are the inverses of 30-37, where 38 skips the following byte
+        test == (n < 0x8)
+    }
+
+    fn short_branch(&mut self, bus: &mut impl Bus, condition: bool) {
+        if condition {
+            let low = bus.read(self.pc());
+            self.r[self.p as usize] = self.pc() & 0xFF00 | low as u16;
+        } else {
+            self.skip(1);
+        }
+    }
+
+    fn fetch(&mut self, bus: &mut impl Bus) -> u8 {
+        let value = bus.read(self.pc());
+        self.skip(1);
+        value
+    }
+
+    fn add(&mut self, value: u8, carry: bool) {
+        let sum = self.d as u16 + value as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    // DF is set when there is no borrow, and a clear DF borrows one more
+    fn subtract(&mut self, minuend: u8, subtrahend: u8, no_borrow: bool) {
+        let difference = minuend as i16 - subtrahend as i16 - !no_borrow as i16;
+        self.d = difference as u8;
+        self.df = difference >= 0;
+    }
+
+    fn skip(&mut self, bytes: u16) {
+        self.r[self.p as usize] = self.pc().wrapping_add(bytes);
+    }
+
+    fn inc_x(&mut self) {
+        self.r[self.x as usize] = self.rx().wrapping_add(1);
+    }
+
+    fn pc(&self) -> u16 {
+        self.r[self.p as usize]
+    }
+
+    fn rx(&self) -> u16 {
+        self.r[self.x as usize]
+    }
+}
+
+impl Default for Cdp1802 {
+    fn default() -> Self {
+        Cdp1802::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestBus {
+        memory: Vec<u8>,
+        outputs: Vec<(u8, u8)>,
+        flags: [bool; 4],
+    }
+
+    impl TestBus {
+        fn new(program: &[u8]) -> TestBus {
+            let mut memory = vec![0; 0x100];
+            memory[..program.len()].copy_from_slice(program);
+
+            TestBus {
+                memory,
+                outputs: Vec::new(),
+                flags: [false; 4],
+            }
+        }
+    }
+
+    impl Bus for TestBus {
+        fn read(&mut self, address: u16) -> u8 {
+            self.memory[address as usize % 0x100]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.memory[address as usize % 0x100] = value;
+        }
+
+        fn input(&mut self, port: u8) -> u8 {
+            0x10 + port
+        }
+
+        fn output(&mut self, port: u8, value: u8) {
+            self.outputs.push((port, value));
+        }
+
+        fn flag(&mut self, flag: u8) -> bool {
+            self.flags[flag as usize - 1]
+        }
+    }
+
+    fn run(cpu: &mut Cdp1802, bus: &mut TestBus, instructions: usize) -> u32 {
+        (0..instructions).map(|_| cpu.step(bus)).sum()
+    }
+
+    #[test]
+    fn registers() {
+        let mut cpu = Cdp1802::new();
+        let mut bus = TestBus::new(&[
+            0xF8, 0x12, // LDI 12
+            0xB5, // PHI 5
+            0xF8, 0xFF, // LDI FF
+            0xA5, // PLO 5
+            0x15, // INC 5
+            0x95, // GHI 5
+            0x25, // DEC 5
+            0x85, // GLO 5
+        ]);
+
+        assert_eq!(16, run(&mut cpu, &mut bus, 8));
+        assert_eq!(0x12FF, cpu.r[5]);
+        assert_eq!(0xFF, cpu.d);
+        assert_eq!(0x000A, cpu.r[0]);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut cpu = Cdp1802::new();
+        let mut bus = TestBus::new(&[
+            0xF8, 0xF0, // LDI F0
+            0xFC, 0x20, // ADI 20
+            0x7C, 0x00, // ADCI 00
+            0xFF, 0x12, // SMI 12
+            0xFD, 0x00, // SDI 00
+            0x7F, 0x7F, // SMBI 7F
+            0xFE, // SHL
+            0x76, // SHRC
+        ]);
+
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!((0x10, true), (cpu.d, cpu.df));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((0x11, false), (cpu.d, cpu.df));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((0xFF, false), (cpu.d, cpu.df));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((0x01, false), (cpu.d, cpu.df));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((0x81, false), (cpu.d, cpu.df));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((0x02, true), (cpu.d, cpu.df));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((0x81, false), (cpu.d, cpu.df));
+        assert_eq!(0x000E, cpu.r[0]);
+    }
+
+    #[test]
+    fn branches() {
+        let mut cpu = Cdp1802::new();
+        let mut bus = TestBus::new(&[
+            0x7B, // SEQ
+            0x31, 0x05, // BQ 05
+            0x00, 0x00, // Skipped
+            0xC9, 0x00, 0x00, // LBNQ 0000, not taken
+            0x3C, 0x20, // BN1 20
+        ]);
+
+        assert_eq!(2 + 2 + 3 + 2, run(&mut cpu, &mut bus, 4));
+        assert_eq!(0x0020, cpu.r[0]);
+
+        cpu.r[0] = 0x0008;
+        bus.flags[0] = true;
+        cpu.step(&mut bus);
+        assert_eq!(0x000A, cpu.r[0]);
+    }
+
+    #[test]
+    fn memory_and_io() {
+        let mut cpu = Cdp1802::new();
+        let mut bus = TestBus::new(&[
+            0xF8, 0x80, // LDI 80
+            0xA2, // PLO 2
+            0xE2, // SEX 2
+            0x73, // STXD
+            0x60, // IRX
+            0x62, // OUT 2
+            0x6B, // INP 3
+            0xF0, // LDX
+        ]);
+
+        run(&mut cpu, &mut bus, 8);
+        assert_eq!(vec![(2, 0x80)], bus.outputs);
+        assert_eq!(0x13, bus.memory[0x81]);
+        assert_eq!(0x13, cpu.d);
+        assert_eq!(0x0081, cpu.r[2]);
+    }
+
+    #[test]
+    fn interrupts_and_subroutines() {
+        let mut cpu = Cdp1802::new();
+        let mut bus = TestBus::new(&[0xC4, 0x00]);
+        cpu.r[1] = 0x0040;
+        cpu.r[2] = 0x00F0;
+        bus.memory[0x40..0x43].copy_from_slice(&[0x22, 0x78, 0x70]);
+
+        cpu.step(&mut bus);
+        assert_eq!(1, cpu.interrupt());
+        assert_eq!((1, 2, 0x00, false), (cpu.p, cpu.x, cpu.t, cpu.ie));
+        assert_eq!(0, cpu.interrupt());
+
+        // DEC 2, SAV and RET return to the interrupted program
+        run(&mut cpu, &mut bus, 3);
+        assert_eq!((0, 0, true), (cpu.p, cpu.x, cpu.ie));
+        assert_eq!(0x0001, cpu.r[0]);
+        assert_eq!(0x00F0, cpu.r[2]);
+
+        // IDL waits until a DMA cycle
+        cpu.step(&mut bus);
+        assert!(cpu.idle);
+        assert_eq!(1, cpu.step(&mut bus));
+        cpu.r[0] = 0x0040;
+        assert_eq!(0x22, cpu.dma_out(&mut bus));
+        assert!(!cpu.idle);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod cpu;
+pub mod video;
+pub mod vip;
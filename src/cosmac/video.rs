@@ -0,0 +1,136 @@
+use std::ops::Range;
+
+/// Machine cycles in one line of the display
+pub const LINE_CYCLES: u32 = 14;
+/// Lines in one frame, including the blanking interval
+pub const FRAME_LINES: u32 = 262;
+/// Machine cycles in one frame, about 1/60 of a second at the VIP's 1.76 MHz clock
+pub const FRAME_CYCLES: u32 = LINE_CYCLES * FRAME_LINES;
+
+/// Bytes fetched by DMA for each line, one bit per pixel
+pub const LINE_BYTES: usize = 8;
+/// Lines of the frame that show pixels
+pub const DISPLAY_LINES: Range<u32> = 80..208;
+
+// The interrupt is raised two lines before the display starts
+const INTERRUPT_LINES: Range<u32> = 78..80;
+// EF1 is asserted for four lines before the display starts and before it ends
+const FLAG_LINES: [Range<u32>; 2] = [76..80, 204..208];
+// DMA for a line starts 29 machine cycles after the interrupt, so one cycle into the line
+const DMA_OFFSET: u32 = 1;
+
+/// RCA CDP1861 video display controller
+/// The CPU feeds it 8 bytes per line by DMA while the display is on
+pub struct Cdp1861 {
+    pub enabled: bool,
+    // Machine cycles since the start of the frame
+    cycle: u32,
+    // Next display line waiting for its DMA
+    next_dma_line: u32,
+    // The pixels of every display line in the frame so far
+    pub lines: [[u8; LINE_BYTES]; (DISPLAY_LINES.end - DISPLAY_LINES.start) as usize],
+}
+
+impl Cdp1861 {
+    pub fn new() -> Cdp1861 {
+        Cdp1861 {
+            enabled: false,
+            cycle: 0,
+            next_dma_line: DISPLAY_LINES.start,
+            lines: [[0; LINE_BYTES]; (DISPLAY_LINES.end - DISPLAY_LINES.start) as usize],
+        }
+    }
+
+    pub fn line(&self) -> u32 {
+        self.cycle / LINE_CYCLES
+    }
+
+    /// Whether the interrupt request is asserted
+    pub fn interrupt(&self) -> bool {
+        self.enabled && INTERRUPT_LINES.contains(&self.line())
+    }
+
+    /// Whether EF1 is asserted
+    pub fn flag(&self) -> bool {
+        let line = self.line();
+        self.enabled && FLAG_LINES.iter().any(|lines| lines.contains(&line))
+    }
+
+    /// The display line that is due a DMA transfer, if any
+    pub fn dma_line(&self) -> Option<u32> {
+        let line = self.line();
+        let due = self.cycle % LINE_CYCLES >= DMA_OFFSET && line >= self.next_dma_line;
+
+        if self.enabled && DISPLAY_LINES.contains(&line) && due {
+            Some(line)
+        } else {
+            None
+        }
+    }
+
+    /// Store the bytes fetched for a line
+    pub fn store_line(&mut self, line: u32, bytes: [u8; LINE_BYTES]) {
+        self.lines[(line - DISPLAY_LINES.start) as usize] = bytes;
+        self.next_dma_line = line + 1;
+    }
+
+    /// Advance by a number of machine cycles
+    /// Returns true when a frame was completed
+    pub fn advance(&mut self, cycles: u32) -> bool {
+        self.cycle += cycles;
+
+        if self.cycle < FRAME_CYCLES {
+            return false;
+        }
+
+        self.cycle -= FRAME_CYCLES;
+        self.next_dma_line = DISPLAY_LINES.start;
+        true
+    }
+
+    /// Blank the frame, as a display that is off shows nothing
+    pub fn blank(&mut self) {
+        for line in self.lines.iter_mut() {
+            *line = [0; LINE_BYTES];
+        }
+    }
+}
+
+impl Default for Cdp1861 {
+    fn default() -> Self {
+        Cdp1861::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_timing() {
+        let mut video = Cdp1861::new();
+        video.enabled = true;
+
+        assert!(!video.interrupt());
+        assert!(!video.advance(76 * LINE_CYCLES));
+        assert!(video.flag());
+        assert!(!video.interrupt());
+
+        video.advance(2 * LINE_CYCLES);
+        assert!(video.interrupt());
+        assert_eq!(None, video.dma_line());
+
+        video.advance(2 * LINE_CYCLES + DMA_OFFSET);
+        assert!(!video.interrupt());
+        assert!(!video.flag());
+        assert_eq!(Some(80), video.dma_line());
+
+        // A line is only fetched once
+        video.store_line(80, [0xFF; LINE_BYTES]);
+        assert_eq!(None, video.dma_line());
+        assert_eq!([0xFF; LINE_BYTES], video.lines[0]);
+
+        assert!(video.advance(FRAME_CYCLES - 80 * LINE_CYCLES));
+        assert_eq!(DMA_OFFSET, video.cycle);
+    }
+}
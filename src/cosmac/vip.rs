@@ -0,0 +1,379 @@
+use super::cpu::{Bus, Cdp1802};
+use super::video::{Cdp1861, DISPLAY_LINES, LINE_BYTES};
+use crate::io::chip8_io::Chip8IO;
+use crate::io::input::MapKey;
+use crate::settings::{DrawMode, MemorySize};
+use crate::timer;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Address the monitor ROM is mapped at, mirrored through the top half of the address space
+pub const MONITOR_ADDRESS: u16 = 0x8000;
+/// Size of the monitor ROM
+pub const MONITOR_SIZE: usize = 0x200;
+
+// The frontend shows the display at CHIP-8's low resolution
+const WIDTH_PX: usize = 64;
+const HEIGHT_PX: usize = 32;
+// Each row of the frontend shows the first of four display lines, as the CHIP-8 interpreter repeats every row
+const LINES_PER_ROW: usize = (DISPLAY_LINES.end - DISPLAY_LINES.start) as usize / HEIGHT_PX;
+const ROW_BYTES: usize = WIDTH_PX / 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VipError {
+    // Bytes that run past the end of RAM when loaded at the address
+    DoesNotFit { address: u16, size: usize },
+    InvalidMonitorSize(usize),
+}
+
+impl Display for VipError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VipError::DoesNotFit { address, size } => {
+                write!(f, "{} bytes do not fit in memory at {:#06X}", size, address)
+            }
+            VipError::InvalidMonitorSize(size_bytes) => write!(
+                f,
+                "the monitor ROM must be at most {} bytes, but was {} bytes",
+                MONITOR_SIZE, size_bytes
+            ),
+        }
+    }
+}
+
+impl Error for VipError {}
+
+/// Everything on the VIP's bus besides the CPU
+struct Board<G: Chip8IO> {
+    ram: Vec<u8>,
+    // Empty when no monitor ROM is installed
+    monitor: Vec<u8>,
+    // Set at reset so the CPU starts in the monitor, until the monitor first addresses its own page
+    monitor_at_zero: bool,
+    video: Cdp1861,
+    // Key selected by OUT 2, reported on EF3 while it is held down
+    key_latch: u8,
+    graphics: G,
+}
+
+impl<G: Chip8IO> Bus for Board<G> {
+    fn read(&mut self, address: u16) -> u8 {
+        if address >= MONITOR_ADDRESS {
+            self.monitor_at_zero = false;
+        }
+
+        if address >= MONITOR_ADDRESS || self.monitor_at_zero {
+            let offset = address as usize % MONITOR_SIZE;
+            self.monitor.get(offset).copied().unwrap_or(0)
+        } else {
+            self.ram[address as usize % self.ram.len()]
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address >= MONITOR_ADDRESS {
+            self.monitor_at_zero = false;
+        } else if !self.monitor_at_zero {
+            let size = self.ram.len();
+            self.ram[address as usize % size] = value;
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        // INP 1 turns the display on
+        if port == 1 {
+            self.video.enabled = true;
+        }
+
+        0
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            // OUT 1 turns the display off
+            1 => self.video.enabled = false,
+            2 => self.key_latch = value & 0xF,
+            _ => {}
+        }
+    }
+
+    fn flag(&mut self, flag: u8) -> bool {
+        match flag {
+            1 => self.video.flag(),
+            3 => match self.key_latch.map_key() {
+                Some(key) => self.graphics.key_pressed(key),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// COSMAC VIP computer, which runs the original CHIP-8 interpreter and 1802 machine code
+pub struct Vip<G: Chip8IO, T: timer::Timer> {
+    cpu: Cdp1802,
+    board: Board<G>,
+    timer: T,
+    // The pixels the frontend is showing, which it can only change by drawing over them
+    shown: [u8; ROW_BYTES * HEIGHT_PX],
+}
+
+impl<G, T> Vip<G, T>
+where
+    G: Chip8IO,
+    T: timer::Timer,
+{
+    pub fn new(graphics: G, timer: T, memory_size: MemorySize) -> Vip<G, T> {
+        let mut vip = Vip {
+            cpu: Cdp1802::new(),
+            board: Board {
+                ram: vec![0; memory_size.bytes().min(MONITOR_ADDRESS as usize)],
+                monitor: Vec::new(),
+                monitor_at_zero: false,
+                video: Cdp1861::new(),
+                key_latch: 0,
+                graphics,
+            },
+            timer,
+            shown: [0; ROW_BYTES * HEIGHT_PX],
+        };
+
+        vip.reset();
+        vip
+    }
+
+    /// Copy bytes into RAM, such as the CHIP-8 interpreter at 0x000 and a program at 0x200
+    pub fn load(&mut self, address: u16, bytes: &[u8]) -> Result<(), VipError> {
+        let start = address as usize;
+        let end = start + bytes.len();
+
+        if end > self.board.ram.len() {
+            return Err(VipError::DoesNotFit {
+                address,
+                size: bytes.len(),
+            });
+        }
+
+        self.board.ram[start..end].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Install a monitor ROM, which runs from reset and hands over to the program in RAM
+    pub fn load_monitor(&mut self, monitor: &[u8]) -> Result<(), VipError> {
+        if monitor.len() > MONITOR_SIZE {
+            return Err(VipError::InvalidMonitorSize(monitor.len()));
+        }
+
+        self.board.monitor = monitor.to_vec();
+        self.board.monitor.resize(MONITOR_SIZE, 0);
+        self.reset();
+        Ok(())
+    }
+
+    /// Reset the CPU, starting in the monitor if there is one or at 0x000 in RAM if there is not
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.board.monitor_at_zero = !self.board.monitor.is_empty();
+        self.board.video.enabled = false;
+
+        // The monitor hands over with the last page of RAM in R1.1, where the interpreter keeps the display
+        if !self.board.monitor_at_zero {
+            let last_page = (self.board.ram.len() - 1) >> 8;
+            self.cpu.r[1] = (last_page as u16) << 8;
+        }
+    }
+
+    pub fn graphics(&self) -> &G {
+        &self.board.graphics
+    }
+
+    /// Run frames forever, waiting for the timer between frames
+    pub fn run(&mut self) {
+        loop {
+            self.run_frame();
+            self.timer.wait_for_tick();
+        }
+    }
+
+    /// Run the CPU until the video chip finishes a frame and show the frame
+    pub fn run_frame(&mut self) {
+        loop {
+            let video = &self.board.video;
+
+            let cycles = if let Some(line) = video.dma_line() {
+                let mut bytes = [0; LINE_BYTES];
+                for byte in bytes.iter_mut() {
+                    *byte = self.cpu.dma_out(&mut self.board);
+                }
+
+                self.board.video.store_line(line, bytes);
+                LINE_BYTES as u32
+            } else if video.interrupt() && self.cpu.ie {
+                self.cpu.interrupt()
+            } else {
+                self.cpu.step(&mut self.board)
+            };
+
+            if self.board.video.advance(cycles) {
+                break;
+            }
+        }
+
+        if !self.board.video.enabled {
+            self.board.video.blank();
+        }
+        self.show_frame();
+        self.board.graphics.end_frame();
+    }
+
+    // Bring the frontend up to date by drawing the difference from what it shows
+    fn show_frame(&mut self) {
+        for y in 0..HEIGHT_PX {
+            let line = &self.board.video.lines[y * LINES_PER_ROW];
+
+            for (x, byte) in line.iter().enumerate() {
+                let shown = &mut self.shown[y * ROW_BYTES + x];
+                let changed = byte ^ *shown;
+
+                if changed != 0 {
+                    self.board
+                        .graphics
+                        .draw((x * 8) as u8, y as u8, &[changed], DrawMode::Clip);
+                    *shown = *byte;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::headless_io::HeadlessIO;
+    use crate::io::input::Key;
+
+    type TestVip = Vip<HeadlessIO, timer::InstructionTimer>;
+
+    fn new_vip(program: &[u8]) -> TestVip {
+        let mut vip = Vip::new(
+            HeadlessIO::new(),
+            timer::InstructionTimer::new(),
+            MemorySize::Standard,
+        );
+        vip.load(0x000, program).unwrap();
+        vip
+    }
+
+    #[test]
+    fn display() {
+        // Leave R0 free for DMA by running the program with R3 as the program counter
+        let mut vip = new_vip(&[
+            0xF8, 0x08, // LDI 08
+            0xA3, // PLO 3
+            0xD3, // SEP 3
+            0x00, 0x00, 0x00, 0x00, // Unused
+            0xF8, 0x20, // LDI 20
+            0xA1, // PLO 1
+            0xF8, 0xFF, // LDI FF
+            0xA2, // PLO 2
+            0xE2, // SEX 2
+            0x69, // INP 1
+            0x30, 0x10, // BR 10
+        ]);
+        vip.cpu.r[1] = 0x0000;
+
+        // The interrupt routine points DMA at 0x100 and returns to the program
+        vip.load(
+            0x01F,
+            &[
+                0x70, // RET
+                0x22, // DEC 2
+                0x78, // SAV
+                0xF8, 0x01, // LDI 01
+                0xB0, // PHI 0
+                0xF8, 0x00, // LDI 00
+                0xA0, // PLO 0
+                0x30, 0x1F, // BR 1F
+            ],
+        )
+        .unwrap();
+        vip.load(0x100, &[0xFF, 0x00, 0x81]).unwrap();
+        let row_5 = 0x100 + (5 * LINES_PER_ROW * LINE_BYTES) as u16;
+        vip.load(row_5 + 7, &[0x0F]).unwrap();
+
+        vip.run_frame();
+
+        let graphics = &vip.board.graphics.graphics_buffer;
+        assert_eq!(Some(true), graphics.get_pixel(0, 0));
+        assert_eq!(Some(false), graphics.get_pixel(8, 0));
+        assert_eq!(Some(true), graphics.get_pixel(16, 0));
+        assert_eq!(Some(true), graphics.get_pixel(23, 0));
+        assert_eq!(Some(false), graphics.get_pixel(0, 1));
+        assert_eq!(Some(true), graphics.get_pixel(63, 5));
+        assert_eq!(Some(false), graphics.get_pixel(59, 5));
+
+        // Turning the display off blanks it
+        vip.load(0x100, &[0x00]).unwrap();
+        vip.board.video.enabled = false;
+        vip.run_frame();
+        assert_eq!(
+            Some(false),
+            vip.board.graphics.graphics_buffer.get_pixel(0, 0)
+        );
+        assert_eq!(
+            Some(false),
+            vip.board.graphics.graphics_buffer.get_pixel(63, 5)
+        );
+    }
+
+    #[test]
+    fn keypad() {
+        let mut vip = new_vip(&[
+            0xF8, 0x10, // LDI 10
+            0xA2, // PLO 2
+            0xE2, // SEX 2
+            0x62, // OUT 2
+            0x36, 0x09, // B3 09
+            0x7A, // REQ
+            0x00, // IDL
+            0x7B, // SEQ
+            0x00, // IDL
+        ]);
+        vip.load(0x010, &[0x0A]).unwrap();
+
+        vip.board.graphics.keypad.press(Key::A);
+        vip.run_frame();
+        assert!(vip.cpu.q);
+
+        vip.reset();
+        vip.board.graphics.keypad.release(&Key::A);
+        vip.run_frame();
+        assert!(!vip.cpu.q);
+    }
+
+    #[test]
+    fn monitor() {
+        let mut vip = new_vip(&[0x7B, 0x00]); // SEQ, IDL
+
+        // The monitor starts from reset and jumps to its own page, which uncovers RAM at 0x000
+        vip.load_monitor(&[0xC0, 0x80, 0x03, 0xC0, 0x00, 0x00])
+            .unwrap();
+        assert_eq!(0xC0, vip.board.read(0x0000));
+        vip.run_frame();
+        assert!(vip.cpu.q);
+        assert_eq!(0x7B, vip.board.read(0x0000));
+        assert_eq!(0xC0, vip.board.read(0xFE00));
+
+        assert_eq!(
+            Err(VipError::InvalidMonitorSize(0x201)),
+            vip.load_monitor(&[0; 0x201])
+        );
+        assert_eq!(
+            Err(VipError::DoesNotFit {
+                address: 0xFFF,
+                size: 2
+            }),
+            vip.load(0xFFF, &[0, 0])
+        );
+    }
+}
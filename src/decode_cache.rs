@@ -0,0 +1,83 @@
+use crate::instruction::Instruction;
+use crate::memory::Address;
+use std::ops::Range;
+
+/// Decoded instructions indexed by the address they were fetched from
+pub struct DecodeCache {
+    entries: Vec<Option<Instruction>>,
+}
+
+impl DecodeCache {
+    pub fn new(memory_size: usize) -> DecodeCache {
+        DecodeCache {
+            entries: vec![None; memory_size],
+        }
+    }
+
+    pub fn get(&self, address: Address) -> Option<Instruction> {
+        self.entries.get(address as usize).copied().flatten()
+    }
+
+    pub fn insert(&mut self, address: Address, instruction: Instruction) {
+        if let Some(entry) = self.entries.get_mut(address as usize) {
+            *entry = Some(instruction);
+        }
+    }
+
+    /// Forget every instruction that overlaps the written bytes
+    pub fn invalidate(&mut self, written: Range<usize>) {
+        // An instruction starting one byte before the write has its second byte overwritten
+        let start = written.start.saturating_sub(1).min(self.entries.len());
+        let end = written.end.min(self.entries.len());
+
+        for entry in &mut self.entries[start..end] {
+            *entry = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|entry| *entry = None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction::*;
+
+    #[test]
+    fn insert_and_get() {
+        let mut cache = DecodeCache::new(0x1000);
+        assert_eq!(None, cache.get(0x200));
+
+        cache.insert(0x200, ClearScreen);
+        assert_eq!(Some(ClearScreen), cache.get(0x200));
+        assert_eq!(None, cache.get(0x202));
+
+        // Addresses outside of memory are never cached
+        cache.insert(0x1000, Return);
+        assert_eq!(None, cache.get(0x1000));
+
+        cache.clear();
+        assert_eq!(None, cache.get(0x200));
+    }
+
+    #[test]
+    fn invalidate_overlapping() {
+        let mut cache = DecodeCache::new(0x1000);
+        for address in (0x200..0x210).step_by(2) {
+            cache.insert(address, Return);
+        }
+
+        // Writing 0x205..0x208 touches the instructions at 0x204 and 0x206
+        cache.invalidate(0x205..0x208);
+
+        assert_eq!(Some(Return), cache.get(0x202));
+        assert_eq!(None, cache.get(0x204));
+        assert_eq!(None, cache.get(0x206));
+        assert_eq!(Some(Return), cache.get(0x208));
+
+        // Ranges running past the end of memory are tolerated
+        cache.invalidate(0xFF0..0x1010);
+    }
+}
@@ -3,13 +3,16 @@ use std::error::Error;
 use std::fmt::{Display, Formatter};
 use Instruction::*;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Instruction {
     // 00E0
     ClearScreen,
     // 00EE
     Return,
-    // 0NNN Intentionally unimplemented
+    // 0NNN
+    Sys {
+        address: memory::Address,
+    },
     // 1NNN
     JumpNNN {
         address: memory::Address,
@@ -160,10 +163,14 @@ pub enum Instruction {
 
 type InstructionBytes = [u8; 2];
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum InstructionError {
     InvalidSize(usize),
     UnsupportedInstruction(u16),
+    // A call at the address when the stack was full
+    StackOverflow(memory::Address),
+    // A return at the address when the stack was empty
+    StackUnderflow(memory::Address),
 }
 
 impl InstructionError {
@@ -184,6 +191,12 @@ impl Display for InstructionError {
             InstructionError::UnsupportedInstruction(instruction) => {
                 write!(f, "unsupported instruction {:#06X}", instruction,)
             }
+            InstructionError::StackOverflow(address) => {
+                write!(f, "stack overflow calling from {:#05X}", address)
+            }
+            InstructionError::StackUnderflow(address) => {
+                write!(f, "stack underflow returning from {:#05X}", address)
+            }
         }
     }
 }
@@ -208,7 +221,9 @@ impl Instruction {
                 } else if left == 0 && right == 0xEE {
                     Ok(Return)
                 } else {
-                    Err(InstructionError::unsupported_instruction(left, right))
+                    Ok(Sys {
+                        address: (((left & 0xF) as u16) << 8) + right as u16,
+                    })
                 }
             }
             1 => Ok(JumpNNN {
@@ -329,6 +344,7 @@ impl Instruction {
         match self {
             ClearScreen => [0x00, 0xE0],
             Return => [0x00, 0xEE],
+            Sys { address } => from_u12(0x0, *address),
             JumpNNN { address } => from_u12(0x1, *address),
             CallNNN { address } => from_u12(0x2, *address),
             SkipEqXNN { register, value } => [u4_to_u8(0x3, *register), *value],
@@ -430,6 +446,8 @@ mod tests {
     static CASES: &[(u16, Instruction)] = &[
         (0x00E0, ClearScreen),
         (0x00EE, Return),
+        (0x0123, Sys { address: 0x123 }),
+        (0x0000, Sys { address: 0x000 }),
         (0x1CDC, JumpNNN { address: 0xCDC }),
         (0x2EDC, CallNNN { address: 0xEDC }),
         (
@@ -1,10 +1,20 @@
-use crate::io::graphics::SpriteData;
+use crate::io::graphics::{Resolution, SpriteData};
 use crate::io::input::Key;
+use crate::settings::DrawMode;
 
 pub trait Chip8IO {
     fn clear(&mut self);
 
-    fn draw(&mut self, x: u8, y: u8, sprite: &SpriteData) -> bool;
+    fn draw(&mut self, x: u8, y: u8, sprite: &SpriteData, mode: DrawMode) -> bool;
+
+    /// Switch the display to another resolution, which clears it
+    fn set_resolution(&mut self, resolution: Resolution);
+
+    /// Called once the display is complete for a 60 Hz frame
+    fn end_frame(&mut self);
+
+    /// Counter that advances every time the display changes
+    fn display_generation(&self) -> u64;
 
     fn key_pressed(&mut self, key: Key) -> bool;
 
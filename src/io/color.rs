@@ -0,0 +1,303 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// RGBA color with each channel between 0 and 1
+pub type Color = [f32; 4];
+
+/// Colors for each combination of the display's planes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ColorScheme {
+    // Pixels that are off in every plane
+    pub background: Color,
+    // Pixels that are on in the first plane, the only plane most programs draw to
+    pub foreground: Color,
+    // Pixels that are on in only the second plane
+    pub second_plane: Color,
+    // Pixels that are on in both planes
+    pub both_planes: Color,
+}
+
+impl ColorScheme {
+    /// A scheme with two colors, filling in the plane colors between them
+    pub fn two_color(background: Color, foreground: Color) -> ColorScheme {
+        ColorScheme {
+            background,
+            foreground,
+            second_plane: mix(background, foreground, 2.0 / 3.0),
+            both_planes: mix(background, foreground, 1.0 / 3.0),
+        }
+    }
+
+    /// The color for a pixel given which planes it is on in, with the first plane in the lowest bit
+    pub fn plane_color(&self, planes: u8) -> Color {
+        match planes & 0b11 {
+            0b00 => self.background,
+            0b01 => self.foreground,
+            0b10 => self.second_plane,
+            _ => self.both_planes,
+        }
+    }
+}
+
+pub const BLACK_ON_WHITE: ColorScheme = ColorScheme {
+    background: [1.0, 1.0, 1.0, 1.0],
+    foreground: [0.0, 0.0, 0.0, 1.0],
+    second_plane: [2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 1.0],
+    both_planes: [1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0, 1.0],
+};
+
+pub const WHITE_ON_BLACK: ColorScheme = ColorScheme {
+    background: [0.0, 0.0, 0.0, 1.0],
+    foreground: [1.0, 1.0, 1.0, 1.0],
+    second_plane: [1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0, 1.0],
+    both_planes: [2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 1.0],
+};
+
+pub const JAZZ_COLORS: ColorScheme = ColorScheme {
+    background: [19.0 / 256.0, 4.0 / 256.0, 28.0 / 256.0, 1.0],
+    foreground: [155.0 / 256.0, 199.0 / 256.0, 232.0 / 256.0, 1.0],
+    second_plane: [232.0 / 256.0, 121.0 / 256.0, 182.0 / 256.0, 1.0],
+    both_planes: [244.0 / 256.0, 228.0 / 256.0, 164.0 / 256.0, 1.0],
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColorError {
+    // Not a color in RRGGBB hexadecimal
+    InvalidColor(String),
+    // A palette file line that isn't a name followed by two or four colors
+    InvalidPalette { line: usize },
+    UnknownPalette(String),
+}
+
+impl Display for ColorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorError::InvalidColor(color) => {
+                write!(f, "{} is not a color in RRGGBB hexadecimal", color)
+            }
+            ColorError::InvalidPalette { line } => write!(
+                f,
+                "line {} of the palette file should be a name, = and two or four colors",
+                line
+            ),
+            ColorError::UnknownPalette(name) => write!(f, "there is no palette named {}", name),
+        }
+    }
+}
+
+impl Error for ColorError {}
+
+/// Parse a color written as RRGGBB hexadecimal, with or without a leading #
+pub fn parse_hex(color: &str) -> Result<Color, ColorError> {
+    let digits = color.strip_prefix('#').unwrap_or(color);
+    let invalid = || ColorError::InvalidColor(String::from(color));
+
+    if digits.len() != 6 || !digits.is_ascii() {
+        return Err(invalid());
+    }
+
+    let channel = |start: usize| {
+        u8::from_str_radix(&digits[start..start + 2], 16)
+            .map(|value| value as f32 / 255.0)
+            .map_err(|_| invalid())
+    };
+
+    Ok([channel(0)?, channel(2)?, channel(4)?, 1.0])
+}
+
+/// Named color schemes, in the order they are cycled through
+pub struct Palettes {
+    schemes: Vec<(String, ColorScheme)>,
+}
+
+impl Palettes {
+    pub fn built_in() -> Palettes {
+        Palettes {
+            schemes: vec![
+                (String::from("jazz"), JAZZ_COLORS),
+                (String::from("black-on-white"), BLACK_ON_WHITE),
+                (String::from("white-on-black"), WHITE_ON_BLACK),
+            ],
+        }
+    }
+
+    /// Add the palettes from a palette file, replacing any with the same names
+    ///
+    /// Each line is a name, = and then two or four colors in RRGGBB hexadecimal:
+    /// background, foreground and optionally the second plane and both planes colors
+    /// Blank lines and lines starting with ; are ignored
+    /// Nothing is added if any line is invalid
+    pub fn load(&mut self, text: &str) -> Result<(), ColorError> {
+        let mut loaded = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let invalid = ColorError::InvalidPalette { line: index + 1 };
+            let (name, colors) = line.split_once('=').ok_or_else(|| invalid.clone())?;
+            let colors = colors
+                .split_whitespace()
+                .map(parse_hex)
+                .collect::<Result<Vec<Color>, ColorError>>()?;
+
+            let scheme = match colors[..] {
+                [background, foreground] => ColorScheme::two_color(background, foreground),
+                [background, foreground, second_plane, both_planes] => ColorScheme {
+                    background,
+                    foreground,
+                    second_plane,
+                    both_planes,
+                },
+                _ => return Err(invalid),
+            };
+
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(invalid);
+            }
+            loaded.push((String::from(name), scheme));
+        }
+
+        for (name, scheme) in loaded {
+            match self
+                .schemes
+                .iter_mut()
+                .find(|(existing, _)| *existing == name)
+            {
+                Some((_, existing)) => *existing = scheme,
+                None => self.schemes.push((name, scheme)),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<ColorScheme, ColorError> {
+        self.schemes
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, scheme)| *scheme)
+            .ok_or_else(|| ColorError::UnknownPalette(String::from(name)))
+    }
+
+    pub fn schemes(&self) -> Vec<ColorScheme> {
+        self.schemes.iter().map(|(_, scheme)| *scheme).collect()
+    }
+}
+
+/// The scheme after the current one, going back to the first after the last or if the current one isn't listed
+pub fn next_scheme(schemes: &[ColorScheme], current: &ColorScheme) -> Option<ColorScheme> {
+    let next = match schemes.iter().position(|scheme| scheme == current) {
+        Some(index) => (index + 1) % schemes.len(),
+        None => 0,
+    };
+
+    schemes.get(next).copied()
+}
+
+/// The red, green and blue channels of a color as bytes, dropping alpha
+pub fn to_rgb(color: Color) -> [u8; 3] {
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    [channel(color[0]), channel(color[1]), channel(color[2])]
+}
+
+fn mix(from: Color, to: Color, amount: f32) -> Color {
+    let mut mixed = from;
+    for (channel, target) in mixed.iter_mut().zip(to) {
+        *channel += (target - *channel) * amount;
+    }
+    mixed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_colors() {
+        assert_eq!(Ok([1.0, 0.0, 0.2, 1.0]), parse_hex("#FF0033"));
+        assert_eq!([0x12, 0xab, 0xEF], to_rgb(parse_hex("12abEF").unwrap()));
+        for invalid in ["FFF", "#FF00GG", "FF00FF00", "", "ÿÿÿ"] {
+            assert_eq!(
+                Err(ColorError::InvalidColor(String::from(invalid))),
+                parse_hex(invalid)
+            );
+        }
+    }
+
+    #[test]
+    fn plane_colors() {
+        let scheme = ColorScheme::two_color([0.0; 4], [0.9, 0.6, 0.3, 1.0]);
+        assert_eq!([0.0; 4], scheme.plane_color(0));
+        assert_eq!([0.9, 0.6, 0.3, 1.0], scheme.plane_color(1));
+        assert_eq!([153, 102, 51], to_rgb(scheme.plane_color(2)));
+        assert_eq!(JAZZ_COLORS.both_planes, JAZZ_COLORS.plane_color(3));
+    }
+
+    #[test]
+    fn palette_files() {
+        let mut palettes = Palettes::built_in();
+        palettes
+            .load(
+                "; Palettes for testing\n\
+                 \n\
+                 amber = 1A0F00 #FFB000\n\
+                 jazz = 000000 FFFFFF FF0000 00FF00\n",
+            )
+            .unwrap();
+
+        assert_eq!(4, palettes.schemes().len());
+        assert_eq!(
+            [0xFF, 0xB0, 0x00],
+            to_rgb(palettes.get("amber").unwrap().foreground)
+        );
+        assert_eq!(
+            [0, 0xFF, 0],
+            to_rgb(palettes.get("jazz").unwrap().both_planes)
+        );
+        assert_eq!(
+            Err(ColorError::UnknownPalette(String::from("sepia"))),
+            palettes.get("sepia")
+        );
+
+        // A file with an invalid line adds none of its palettes
+        assert_eq!(
+            Err(ColorError::InvalidPalette { line: 2 }),
+            palettes.load("ok = 000000 FFFFFF\nthree = 000000 FFFFFF 888888")
+        );
+        assert_eq!(
+            Err(ColorError::InvalidColor(String::from("red"))),
+            palettes.load("amber = 000000 FFFFFF\n\nfour = 000000 FFFFFF red 00FF00")
+        );
+        assert_eq!(4, palettes.schemes().len());
+        assert_eq!(
+            Err(ColorError::UnknownPalette(String::from("ok"))),
+            palettes.get("ok")
+        );
+        assert_eq!(
+            [0xFF, 0xB0, 0x00],
+            to_rgb(palettes.get("amber").unwrap().foreground)
+        );
+        assert_eq!(
+            Err(ColorError::InvalidPalette { line: 1 }),
+            palettes.load("000000 FFFFFF")
+        );
+        assert_eq!(
+            Err(ColorError::InvalidColor(String::from("black"))),
+            palettes.load("named = black white")
+        );
+    }
+
+    #[test]
+    fn cycling() {
+        let schemes = Palettes::built_in().schemes();
+        assert_eq!(Some(BLACK_ON_WHITE), next_scheme(&schemes, &JAZZ_COLORS));
+        assert_eq!(Some(JAZZ_COLORS), next_scheme(&schemes, &WHITE_ON_BLACK));
+
+        let custom = ColorScheme::two_color([0.0; 4], [0.5; 4]);
+        assert_eq!(Some(JAZZ_COLORS), next_scheme(&schemes, &custom));
+        assert_eq!(None, next_scheme(&[], &custom));
+    }
+}
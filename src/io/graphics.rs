@@ -1,82 +1,215 @@
+use crate::settings::DrawMode;
+use std::ops::Range;
+
 pub type Pixel = bool;
 pub type SpriteData = [u8];
 
-const WIDTH_PX: usize = 64;
-const HEIGHT_PX: usize = 32;
+// Each row is packed into words with the leftmost pixel in the most significant bit
+type Word = u64;
+const WORD_BITS: usize = Word::BITS as usize;
+
+/// Display resolutions used across the CHIP-8 family
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Resolution {
+    // 64x32, the original CHIP-8 display
+    Low,
+    // 64x64, the COSMAC VIP's two-page hi-res CHIP-8
+    Tall,
+    // 128x64, the high resolution mode of SUPER-CHIP and XO-CHIP
+    High,
+    // 256x192, MegaChip's display
+    Mega,
+}
+
+impl Resolution {
+    pub fn width(&self) -> usize {
+        match self {
+            Resolution::Low | Resolution::Tall => 64,
+            Resolution::High => 128,
+            Resolution::Mega => 256,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match self {
+            Resolution::Low => 32,
+            Resolution::Tall | Resolution::High => 64,
+            Resolution::Mega => 192,
+        }
+    }
+}
+
+/// Area of the display holding every pixel changed since it was last taken
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DirtyRect {
+    pub columns: Range<usize>,
+    pub rows: Range<usize>,
+}
+
+impl DirtyRect {
+    fn include(&mut self, columns: Range<usize>, row: usize) {
+        self.columns = self.columns.start.min(columns.start)..self.columns.end.max(columns.end);
+        self.rows = self.rows.start.min(row)..self.rows.end.max(row + 1);
+    }
+}
 
 pub struct GraphicsBuffer {
-    buffer: [bool; WIDTH_PX * HEIGHT_PX],
+    resolution: Resolution,
+    words_per_row: usize,
+    rows: Vec<Word>,
+    // Advances every time a pixel changes
+    generation: u64,
+    dirty: Option<DirtyRect>,
 }
 
 impl GraphicsBuffer {
     pub fn new() -> GraphicsBuffer {
+        GraphicsBuffer::with_resolution(Resolution::Low)
+    }
+
+    pub fn with_resolution(resolution: Resolution) -> GraphicsBuffer {
+        let words_per_row = resolution.width() / WORD_BITS;
+
         GraphicsBuffer {
-            buffer: [false; WIDTH_PX * HEIGHT_PX],
+            resolution,
+            words_per_row,
+            rows: vec![0; words_per_row * resolution.height()],
+            generation: 0,
+            dirty: None,
         }
     }
 
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    /// Switch to another resolution, which clears the display
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        let generation = self.generation;
+
+        *self = GraphicsBuffer::with_resolution(resolution);
+        self.generation = generation + 1;
+        self.mark_all_dirty();
+    }
+
     pub fn width(&self) -> usize {
-        WIDTH_PX
+        self.resolution.width()
     }
 
     pub fn height(&self) -> usize {
-        HEIGHT_PX
+        self.resolution.height()
     }
 
     pub fn clear(&mut self) {
-        for pixel in self.buffer.iter_mut() {
-            *pixel = false;
+        if self.rows.iter().any(|word| *word != 0) {
+            self.rows.fill(0);
+            self.generation += 1;
+            self.mark_all_dirty();
         }
     }
 
-    pub fn draw(&mut self, canvas_x: u8, canvas_y: u8, sprite: &[u8]) -> bool {
+    /// Counter that advances every time the display changes
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The area changed since the last call, or None if nothing has changed
+    pub fn take_dirty(&mut self) -> Option<DirtyRect> {
+        self.dirty.take()
+    }
+
+    /// XOR a sprite onto the display, wrapping the starting position onto the screen
+    /// The mode decides whether pixels past the edges are clipped or wrap around
+    /// Returns whether any pixel was turned off
+    pub fn draw(&mut self, canvas_x: u8, canvas_y: u8, sprite: &[u8], mode: DrawMode) -> bool {
+        let x = canvas_x as usize % self.width();
+        let start_y = canvas_y as usize % self.height();
+
+        let word = x / WORD_BITS;
+        let offset = x % WORD_BITS;
+        let next_word = match mode {
+            DrawMode::Clip => word + 1,
+            DrawMode::Wrap => (word + 1) % self.words_per_row,
+        };
         let mut flipped_pixel = false;
+        let mut changed = false;
 
         for (sprite_y, sprite_line) in sprite.iter().enumerate() {
-            // Handle the bits backwards so the rightmost bit is the leftmost pixel
-            let sprite_y = sprite_y as u8;
-            let sprite_line = sprite_line.reverse_bits();
+            let mut y = start_y + sprite_y;
+            if y >= self.height() {
+                match mode {
+                    DrawMode::Clip => break,
+                    DrawMode::Wrap => y %= self.height(),
+                }
+            }
 
-            // Each bit of the u8 is one column
-            for sprite_x in 0..8 {
-                // Draw the pixel if that bit of the sprite is on
-                let pixel = (sprite_line & (1 << sprite_x)) != 0;
+            // Line the sprite up with x across the word it starts in and the one after
+            let wide = (*sprite_line as u128) << (2 * WORD_BITS - 8 - offset);
+            let parts = [
+                (word, (wide >> WORD_BITS) as Word),
+                (next_word, wide as Word),
+            ];
 
-                if pixel {
-                    let target_x = canvas_x.wrapping_add(sprite_x);
-                    let target_y = canvas_y.wrapping_add(sprite_y);
+            for (index, bits) in parts {
+                if bits == 0 {
+                    continue;
+                }
 
-                    flipped_pixel = self.flip_pixel(target_x, target_y) || flipped_pixel;
+                if let Some(target) = self.row_mut(y).get_mut(index) {
+                    flipped_pixel |= *target & bits != 0;
+                    *target ^= bits;
+
+                    let first = index * WORD_BITS + bits.leading_zeros() as usize;
+                    let last = (index + 1) * WORD_BITS - bits.trailing_zeros() as usize;
+                    self.mark_dirty(first..last, y);
+                    changed = true;
                 }
             }
         }
 
+        if changed {
+            self.generation += 1;
+        }
+
         flipped_pixel
     }
 
     pub fn get_pixel(&self, x: u8, y: u8) -> Option<Pixel> {
-        GraphicsBuffer::index_pixel(x, y).map(|index| self.buffer[index])
+        let (x, y) = (x as usize, y as usize);
+
+        if x >= self.width() || y >= self.height() {
+            return None;
+        }
+
+        let word = self.row(y)[x / WORD_BITS];
+        Some(word & (1 << (WORD_BITS - 1 - x % WORD_BITS)) != 0)
     }
 
-    fn flip_pixel(&mut self, x: u8, y: u8) -> bool {
-        GraphicsBuffer::index_pixel(x, y)
-            .map(|index| {
-                let previous = self.buffer[index];
-                self.buffer[index] = !previous;
-                return previous;
-            })
-            .unwrap_or(false)
+    fn mark_dirty(&mut self, columns: Range<usize>, row: usize) {
+        match &mut self.dirty {
+            Some(dirty) => dirty.include(columns, row),
+            None => {
+                self.dirty = Some(DirtyRect {
+                    columns,
+                    rows: row..row + 1,
+                })
+            }
+        }
     }
 
-    fn index_pixel(x: u8, y: u8) -> Option<usize> {
-        let x = x as usize;
-        let y = y as usize;
+    fn mark_all_dirty(&mut self) {
+        self.dirty = Some(DirtyRect {
+            columns: 0..self.width(),
+            rows: 0..self.height(),
+        });
+    }
 
-        if x >= WIDTH_PX || y >= HEIGHT_PX {
-            None
-        } else {
-            Some(x + WIDTH_PX * y)
-        }
+    fn row(&self, y: usize) -> &[Word] {
+        &self.rows[y * self.words_per_row..(y + 1) * self.words_per_row]
+    }
+
+    fn row_mut(&mut self, y: usize) -> &mut [Word] {
+        &mut self.rows[y * self.words_per_row..(y + 1) * self.words_per_row]
     }
 }
 
@@ -84,18 +217,40 @@ impl GraphicsBuffer {
 mod tests {
     use super::*;
 
+    fn row_pixels(graphics: &GraphicsBuffer, y: u8, xs: std::ops::Range<u8>) -> Vec<bool> {
+        xs.map(|x| graphics.get_pixel(x, y).unwrap()).collect()
+    }
+
+    fn is_blank(graphics: &GraphicsBuffer) -> bool {
+        graphics.rows.iter().all(|word| *word == 0)
+    }
+
     #[test]
-    fn test_index_pixel() {
-        assert_eq!(None, GraphicsBuffer::index_pixel(32, 63));
-        assert_eq!(None, GraphicsBuffer::index_pixel(31, 64));
-        assert_eq!(None, GraphicsBuffer::index_pixel(32, 64));
+    fn resolutions() {
+        for resolution in [
+            Resolution::Low,
+            Resolution::Tall,
+            Resolution::High,
+            Resolution::Mega,
+        ] {
+            let mut graphics = GraphicsBuffer::with_resolution(resolution);
+            let (right, bottom) = (resolution.width() - 1, resolution.height() - 1);
+
+            assert_eq!(Some(false), graphics.get_pixel(right as u8, bottom as u8));
+            assert_eq!(None, graphics.get_pixel(0, resolution.height() as u8));
+
+            graphics.draw(right as u8, bottom as u8, &[0xFF], DrawMode::Clip);
+            assert_eq!(Some(true), graphics.get_pixel(right as u8, bottom as u8));
+        }
 
-        assert_eq!(0, GraphicsBuffer::index_pixel(0, 0).unwrap());
-        assert_eq!(5, GraphicsBuffer::index_pixel(5, 0).unwrap());
-        assert_eq!(63, GraphicsBuffer::index_pixel(63, 0).unwrap());
-        assert_eq!(64, GraphicsBuffer::index_pixel(0, 1).unwrap());
-        assert_eq!(140, GraphicsBuffer::index_pixel(12, 2).unwrap());
-        assert_eq!(2047, GraphicsBuffer::index_pixel(63, 31).unwrap());
+        assert_eq!(None, GraphicsBuffer::new().get_pixel(64, 0));
+
+        // Changing resolution clears the display
+        let mut graphics = GraphicsBuffer::new();
+        graphics.draw(0, 0, &[0x80], DrawMode::Clip);
+        graphics.set_resolution(Resolution::High);
+        assert_eq!(128, graphics.width());
+        assert!(is_blank(&graphics));
     }
 
     #[test]
@@ -104,35 +259,32 @@ mod tests {
 
         // Should be off to start
         assert_eq!(Some(false), graphics.get_pixel(6, 1));
-        graphics.buffer[70] = true;
+        graphics.draw(6, 1, &[0x80], DrawMode::Clip);
 
         // Looking up the pixel by coordinate should be true now
         assert_eq!(Some(true), graphics.get_pixel(6, 1));
 
         // Flipping the pixel should return true because it was flipped off
-        assert_eq!(true, graphics.flip_pixel(6, 1));
+        assert_eq!(true, graphics.draw(6, 1, &[0x80], DrawMode::Clip));
 
         // And the underlying pixel should now be false
         assert_eq!(Some(false), graphics.get_pixel(6, 1));
 
         // Flipping it back on should return false because nothing was turned off
-        assert_eq!(false, graphics.flip_pixel(6, 1));
-        assert_eq!(true, graphics.flip_pixel(6, 1));
+        assert_eq!(false, graphics.draw(6, 1, &[0x80], DrawMode::Clip));
+        assert_eq!(true, graphics.draw(6, 1, &[0x80], DrawMode::Clip));
 
-        assert_eq!([false; 2048], graphics.buffer);
+        assert!(is_blank(&graphics));
     }
 
     #[test]
     fn test_headless_graphics() {
         let mut graphics = GraphicsBuffer::new();
 
-        // There should be 2048 boolean cells in the graphics buffer
-        assert_eq!([false; 2048], graphics.buffer);
-
         // Drawing an empty sprite should not affect the buffer or indicate a flip
-        let flipped = graphics.draw(0, 0, &[]);
+        let flipped = graphics.draw(0, 0, &[], DrawMode::Clip);
         assert_eq!(false, flipped);
-        assert_eq!([false; 2048], graphics.buffer);
+        assert!(is_blank(&graphics));
 
         // This sprite forms a checkerboard pattern
         let sprite_positive = [0xAA, 0x55, 0xAA, 0x55];
@@ -143,30 +295,47 @@ mod tests {
         let pixels_00 = [false; 8];
         let pixels_ff = [true; 8];
 
-        let flipped = graphics.draw(0, 0, &sprite_positive);
+        let flipped = graphics.draw(0, 0, &sprite_positive, DrawMode::Clip);
         assert_eq!(false, flipped);
 
         // Check the whole draw area
-        assert_eq!(pixels_aa, graphics.buffer[0..8]);
-        assert_eq!(pixels_55, graphics.buffer[64..72]);
-        assert_eq!(pixels_aa, graphics.buffer[128..136]);
-        assert_eq!(pixels_55, graphics.buffer[192..200]);
+        assert_eq!(pixels_aa, row_pixels(&graphics, 0, 0..8)[..]);
+        assert_eq!(pixels_55, row_pixels(&graphics, 1, 0..8)[..]);
+        assert_eq!(pixels_aa, row_pixels(&graphics, 2, 0..8)[..]);
+        assert_eq!(pixels_55, row_pixels(&graphics, 3, 0..8)[..]);
 
         // Check a few things outside the draw area
-        assert_eq!(pixels_00, graphics.buffer[8..16]);
-        assert_eq!(pixels_00, graphics.buffer[200..208]);
+        assert_eq!(pixels_00, row_pixels(&graphics, 0, 8..16)[..]);
+        assert_eq!(pixels_00, row_pixels(&graphics, 4, 0..8)[..]);
 
         // Draw the checkerboard's inverse
-        let flipped = graphics.draw(0, 0, &sprite_negative);
+        let flipped = graphics.draw(0, 0, &sprite_negative, DrawMode::Clip);
         assert_eq!(false, flipped);
         for y in 0..4 {
-            assert_eq!(pixels_ff, graphics.buffer[y * 64..y * 64 + 8])
+            assert_eq!(pixels_ff, row_pixels(&graphics, y, 0..8)[..])
         }
 
         // Flip both checkerboards off again, should reset the board
-        assert_eq!(true, graphics.draw(0, 0, &sprite_positive));
-        assert_eq!(true, graphics.draw(0, 0, &sprite_negative));
-        assert_eq!([false; 2048], graphics.buffer);
+        assert_eq!(true, graphics.draw(0, 0, &sprite_positive, DrawMode::Clip));
+        assert_eq!(true, graphics.draw(0, 0, &sprite_negative, DrawMode::Clip));
+        assert!(is_blank(&graphics));
+    }
+
+    #[test]
+    fn sprites_across_words() {
+        let mut graphics = GraphicsBuffer::with_resolution(Resolution::High);
+
+        // A sprite at x = 60 straddles the first and second words of the row
+        assert!(!graphics.draw(60, 0, &[0xFF], DrawMode::Clip));
+        assert_eq!(vec![true; 8], row_pixels(&graphics, 0, 60..68));
+        assert_eq!(vec![false; 2], row_pixels(&graphics, 0, 58..60));
+        assert_eq!(Some(false), graphics.get_pixel(68, 0));
+
+        // A collision in either word is reported
+        assert!(graphics.draw(66, 0, &[0x80], DrawMode::Clip));
+        assert!(graphics.draw(63, 0, &[0x80], DrawMode::Clip));
+        assert_eq!(Some(false), graphics.get_pixel(63, 0));
+        assert_eq!(Some(false), graphics.get_pixel(66, 0));
     }
 
     #[test]
@@ -175,13 +344,105 @@ mod tests {
         let sprite = [0xFF; 8];
 
         let mut graphics = GraphicsBuffer::new();
+        let (width, height) = (graphics.width() as u8, graphics.height() as u8);
 
-        let flipped = graphics.draw(WIDTH_PX as u8 - 1, HEIGHT_PX as u8 - 1, &sprite);
+        let flipped = graphics.draw(width - 1, height - 1, &sprite, DrawMode::Clip);
         assert_eq!(false, flipped);
 
-        let mut expected = [false; 2048];
-        expected[2047] = true;
+        assert_eq!(Some(true), graphics.get_pixel(width - 1, height - 1));
+        assert_eq!(Some(false), graphics.get_pixel(width - 2, height - 1));
+        assert_eq!(Some(false), graphics.get_pixel(0, 0));
+        assert_eq!(
+            1,
+            graphics
+                .rows
+                .iter()
+                .map(|word| word.count_ones())
+                .sum::<u32>()
+        );
+
+        // A sprite that starts past the edge wraps onto the screen before clipping
+        assert!(!graphics.draw(width, height + 28, &sprite, DrawMode::Clip));
+        assert_eq!(Some(true), graphics.get_pixel(0, 28));
+        assert_eq!(Some(true), graphics.get_pixel(7, 31));
+        assert_eq!(Some(false), graphics.get_pixel(0, 0));
+        assert_eq!(
+            1 + 8 * 4,
+            graphics
+                .rows
+                .iter()
+                .map(|word| word.count_ones())
+                .sum::<u32>()
+        );
+    }
 
-        assert_eq!(expected, graphics.buffer);
+    #[test]
+    fn wrapping_draws() {
+        for resolution in [Resolution::Low, Resolution::High] {
+            let mut graphics = GraphicsBuffer::with_resolution(resolution);
+            let (right, bottom) = (resolution.width() as u8 - 1, resolution.height() as u8 - 1);
+
+            // Pixels that run past the bottom right corner come back in at the top left
+            assert!(!graphics.draw(right - 3, bottom, &[0xFF, 0x81], DrawMode::Wrap));
+            assert_eq!(
+                vec![true; 4],
+                row_pixels(&graphics, bottom, right - 3..right + 1)
+            );
+            assert_eq!(vec![true; 4], row_pixels(&graphics, bottom, 0..4));
+            assert_eq!(Some(false), graphics.get_pixel(4, bottom));
+            assert_eq!(Some(true), graphics.get_pixel(right - 3, 0));
+            assert_eq!(Some(true), graphics.get_pixel(3, 0));
+            assert_eq!(Some(false), graphics.get_pixel(2, 0));
+
+            // Collisions with wrapped pixels are reported
+            assert!(graphics.draw(0, 0, &[0x10], DrawMode::Wrap));
+            assert_eq!(Some(false), graphics.get_pixel(3, 0));
+        }
+    }
+
+    #[test]
+    fn change_tracking() {
+        let mut graphics = GraphicsBuffer::new();
+        assert_eq!(0, graphics.generation());
+        assert_eq!(None, graphics.take_dirty());
+
+        // Empty sprites and clearing a blank display change nothing
+        graphics.draw(0, 0, &[0x00, 0x00], DrawMode::Clip);
+        graphics.clear();
+        assert_eq!(0, graphics.generation());
+        assert_eq!(None, graphics.take_dirty());
+
+        // The dirty area covers the pixels drawn since it was last taken
+        graphics.draw(10, 3, &[0x18, 0x00, 0x80], DrawMode::Clip);
+        graphics.draw(20, 1, &[0x01], DrawMode::Clip);
+        assert_eq!(2, graphics.generation());
+        assert_eq!(
+            Some(DirtyRect {
+                columns: 10..28,
+                rows: 1..6
+            }),
+            graphics.take_dirty()
+        );
+        assert_eq!(None, graphics.take_dirty());
+
+        // Wrapped pixels widen the area to both edges
+        graphics.draw(62, 0, &[0xF0], DrawMode::Wrap);
+        assert_eq!(
+            Some(DirtyRect {
+                columns: 0..64,
+                rows: 0..1
+            }),
+            graphics.take_dirty()
+        );
+
+        graphics.clear();
+        assert_eq!(4, graphics.generation());
+        assert_eq!(
+            Some(DirtyRect {
+                columns: 0..64,
+                rows: 0..32
+            }),
+            graphics.take_dirty()
+        );
     }
 }
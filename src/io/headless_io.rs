@@ -1,6 +1,7 @@
 use crate::io::chip8_io::Chip8IO;
-use crate::io::graphics::{GraphicsBuffer, SpriteData};
+use crate::io::graphics::{GraphicsBuffer, Resolution, SpriteData};
 use crate::io::input::{Key, Keypad};
+use crate::settings::DrawMode;
 
 pub struct HeadlessIO {
     pub graphics_buffer: GraphicsBuffer,
@@ -23,8 +24,18 @@ impl Chip8IO for HeadlessIO {
         self.graphics_buffer.clear();
     }
 
-    fn draw(&mut self, x: u8, y: u8, sprite: &SpriteData) -> bool {
-        self.graphics_buffer.draw(x, y, sprite)
+    fn draw(&mut self, x: u8, y: u8, sprite: &SpriteData, mode: DrawMode) -> bool {
+        self.graphics_buffer.draw(x, y, sprite, mode)
+    }
+
+    fn set_resolution(&mut self, resolution: Resolution) {
+        self.graphics_buffer.set_resolution(resolution);
+    }
+
+    fn end_frame(&mut self) {}
+
+    fn display_generation(&self) -> u64 {
+        self.graphics_buffer.generation()
     }
 
     fn key_pressed(&mut self, key: Key) -> bool {
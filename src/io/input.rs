@@ -46,6 +46,13 @@ impl Keypad {
     pub fn is_pressed(&self, key: &Key) -> bool {
         self.pressed.contains(key)
     }
+
+    /// Keys that are down, in order of their values
+    pub fn pressed_keys(&self) -> Vec<Key> {
+        let mut keys: Vec<Key> = self.pressed.iter().copied().collect();
+        keys.sort_by_key(|key| *key as u8);
+        keys
+    }
 }
 
 /// Trait for things that can be mapped to a Chip8 key
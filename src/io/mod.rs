@@ -1,5 +1,11 @@
 pub mod chip8_io;
+pub mod color;
 pub mod graphics;
 pub mod headless_io;
 pub mod input;
+pub mod overlay;
+pub mod persistence;
 pub mod piston_io;
+pub mod recorder;
+pub mod screenshot;
+pub mod terminal_io;
@@ -0,0 +1,275 @@
+use crate::io::input::Key;
+use crate::monitor::MachineState;
+use std::time::{Duration, Instant};
+
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+// Space after each character and each line
+pub const CHARACTER_WIDTH: usize = GLYPH_WIDTH + 1;
+pub const LINE_HEIGHT: usize = GLYPH_HEIGHT + 1;
+
+// How long the rates are averaged over
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Frames drawn and instructions run per second, averaged over the last second
+#[derive(Default)]
+pub struct Rates {
+    frames: u64,
+    // When the current interval started, with the frame and instruction counts at the time
+    interval_start: Option<(Instant, u64, u64)>,
+    frames_per_second: f64,
+    instructions_per_second: f64,
+}
+
+impl Rates {
+    pub fn new() -> Rates {
+        Rates::default()
+    }
+
+    /// Count a frame drawn at the given time, when the machine had run the given number of instructions
+    pub fn frame(&mut self, now: Instant, instructions: u64) {
+        self.frames += 1;
+
+        let (start, start_frames, start_instructions) =
+            *self
+                .interval_start
+                .get_or_insert((now, self.frames, instructions));
+        let elapsed = now.saturating_duration_since(start);
+        if elapsed < RATE_INTERVAL {
+            return;
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        self.frames_per_second = (self.frames - start_frames) as f64 / seconds;
+        self.instructions_per_second =
+            instructions.saturating_sub(start_instructions) as f64 / seconds;
+        self.interval_start = Some((now, self.frames, instructions));
+    }
+
+    pub fn frames_per_second(&self) -> f64 {
+        self.frames_per_second
+    }
+
+    pub fn instructions_per_second(&self) -> f64 {
+        self.instructions_per_second
+    }
+}
+
+/// Text of the debug overlay, one line at a time
+pub fn lines(state: Option<&MachineState>, pressed: &[Key], rates: &Rates) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    match state {
+        Some(state) => {
+            lines.push(format!("PC {:03X}  I {:03X}", state.pc, state.i));
+            for (row, values) in state.v.chunks(4).enumerate() {
+                let registers: Vec<String> = values
+                    .iter()
+                    .enumerate()
+                    .map(|(column, value)| format!("V{:X} {:02X}", row * 4 + column, value))
+                    .collect();
+                lines.push(registers.join(" "));
+            }
+            lines.push(format!("DT {:02X}  ST {:02X}", state.dt, state.st));
+
+            let stack = state
+                .stack
+                .iter()
+                .map(|address| format!("{:03X}", address))
+                .collect::<Vec<String>>()
+                .join(" ");
+            lines.push(format!("SP {:X}  STACK {}", state.sp, stack));
+
+            match state.instruction {
+                Some(instruction) => lines.push(format!("NEXT {}", instruction)),
+                None => lines.push(String::from("NEXT -")),
+            }
+        }
+        None => lines.push(String::from("NO MACHINE STATE")),
+    }
+
+    let keys: Vec<String> = pressed
+        .iter()
+        .map(|key| format!("{:X}", *key as u8))
+        .collect();
+    lines.push(format!("KEYS {}", keys.join(" ")));
+
+    lines.push(format!(
+        "{:.0} FPS  {:.0} IPS",
+        rates.frames_per_second(),
+        rates.instructions_per_second()
+    ));
+
+    lines
+}
+
+/// Horizontal runs of lit pixels in a line of text as (x, y, length), in font pixels
+pub fn text_runs(text: &str) -> Vec<(usize, usize, usize)> {
+    let glyphs: Vec<[u8; GLYPH_HEIGHT]> = text.chars().map(glyph).collect();
+    let mut runs = Vec::new();
+
+    for y in 0..GLYPH_HEIGHT {
+        let mut run: Option<(usize, usize)> = None;
+
+        for (index, glyph) in glyphs.iter().enumerate() {
+            for column in 0..CHARACTER_WIDTH {
+                let x = index * CHARACTER_WIDTH + column;
+                let lit = column < GLYPH_WIDTH && glyph[y] & (0b100 >> column) != 0;
+
+                match (lit, &mut run) {
+                    (true, Some((_, length))) => *length += 1,
+                    (true, None) => run = Some((x, 1)),
+                    (false, Some((start, length))) => {
+                        runs.push((*start, y, *length));
+                        run = None;
+                    }
+                    (false, None) => {}
+                }
+            }
+        }
+    }
+
+    runs
+}
+
+// Rows of a character in a 3x5 font, with the leftmost pixel in the highest of the low 3 bits
+// Characters without a glyph are drawn as spaces
+fn glyph(character: char) -> [u8; GLYPH_HEIGHT] {
+    match character.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b111, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b010, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction;
+
+    #[test]
+    fn overlay_lines() {
+        let mut state = MachineState {
+            pc: 0x23A,
+            i: 0x5,
+            sp: 1,
+            stack: vec![0x204],
+            dt: 0x3C,
+            instruction: Some(Instruction::DrawXYN {
+                x_register: 0,
+                y_register: 1,
+                bytes: 5,
+            }),
+            ..MachineState::default()
+        };
+        state.v[0xF] = 1;
+
+        let lines = lines(Some(&state), &[Key::D1, Key::A], &Rates::new());
+        assert_eq!(
+            vec![
+                "PC 23A  I 005",
+                "V0 00 V1 00 V2 00 V3 00",
+                "V4 00 V5 00 V6 00 V7 00",
+                "V8 00 V9 00 VA 00 VB 00",
+                "VC 00 VD 00 VE 00 VF 01",
+                "DT 3C  ST 00",
+                "SP 1  STACK 204",
+                "NEXT DRW V0, V1, 5",
+                "KEYS 1 A",
+                "0 FPS  0 IPS",
+            ],
+            lines
+        );
+
+        state.sp = 0;
+        state.stack.clear();
+        let lines = super::lines(Some(&state), &[], &Rates::new());
+        assert_eq!("SP 0  STACK ", lines[6]);
+        assert_eq!("KEYS ", lines[8]);
+        assert_eq!(
+            vec!["NO MACHINE STATE", "KEYS ", "0 FPS  0 IPS"],
+            super::lines(None, &[], &Rates::new())
+        );
+    }
+
+    #[test]
+    fn rates() {
+        let mut rates = Rates::new();
+        let start = Instant::now();
+
+        // Rates are only worked out once a whole interval has passed
+        for frame in 0..30 {
+            rates.frame(start + Duration::from_millis(frame * 50), frame * 100);
+        }
+        assert_eq!(20.0, rates.frames_per_second());
+        assert_eq!(2000.0, rates.instructions_per_second());
+
+        // A machine waiting for a key runs no instructions while frames are still drawn
+        rates.frame(start + Duration::from_millis(2000), 2000);
+        assert_eq!(10.0, rates.frames_per_second());
+        assert_eq!(0.0, rates.instructions_per_second());
+    }
+
+    #[test]
+    fn runs() {
+        // The gap between characters ends runs, even where both characters are lit
+        assert_eq!(
+            vec![
+                (1, 0, 1),
+                (4, 0, 1),
+                (0, 1, 2),
+                (4, 1, 1),
+                (1, 2, 1),
+                (4, 2, 1),
+                (1, 3, 1),
+                (4, 3, 1),
+                (0, 4, 3),
+                (4, 4, 3),
+            ],
+            text_runs("1L")
+        );
+        assert_eq!(Vec::<(usize, usize, usize)>::new(), text_runs("  ?"));
+        assert_eq!(text_runs("ab"), text_runs("AB"));
+    }
+}
@@ -0,0 +1,248 @@
+use crate::io::graphics::GraphicsBuffer;
+use std::collections::VecDeque;
+
+/// How the display hides the flicker of sprites being erased and redrawn
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PersistenceMode {
+    // Show the display as it is at the end of each frame
+    Off,
+    // Average each pixel over the last few frames
+    Blend { frames: usize },
+    // Light a pixel fully while it is on and fade it by the decay factor every frame it is off
+    Phosphor { decay: f32 },
+    // Show a pixel as lit if it was on at any point during the frame
+    AnyLit,
+}
+
+/// Brightness of each pixel over time, worked out from the frames of a graphics buffer
+pub struct Persistence {
+    mode: PersistenceMode,
+    width: usize,
+    height: usize,
+    // Brightness of each pixel between 0 and 1 as of the last frame
+    intensity: Vec<f32>,
+    // The pixels at the end of each of the last few frames, newest last
+    history: VecDeque<Vec<bool>>,
+    // Pixels that have been on at some point in the current frame
+    lit: Vec<bool>,
+    // Generation of the buffer when it was last observed
+    observed_generation: Option<u64>,
+}
+
+impl Persistence {
+    pub fn new(mode: PersistenceMode) -> Persistence {
+        let mode = match mode {
+            PersistenceMode::Blend { frames } => PersistenceMode::Blend {
+                frames: frames.max(1),
+            },
+            PersistenceMode::Phosphor { decay } => PersistenceMode::Phosphor {
+                decay: decay.clamp(0.0, 1.0),
+            },
+            mode => mode,
+        };
+
+        Persistence {
+            mode,
+            width: 0,
+            height: 0,
+            intensity: Vec::new(),
+            history: VecDeque::new(),
+            lit: Vec::new(),
+            observed_generation: None,
+        }
+    }
+
+    pub fn mode(&self) -> PersistenceMode {
+        self.mode
+    }
+
+    /// Look at the display partway through a frame, such as after every draw
+    /// Only needed to catch pixels that are on for part of a frame
+    pub fn observe(&mut self, graphics: &GraphicsBuffer) {
+        if self.mode != PersistenceMode::AnyLit
+            || self.observed_generation == Some(graphics.generation())
+        {
+            return;
+        }
+
+        self.fit(graphics);
+        self.observed_generation = Some(graphics.generation());
+
+        for (lit, pixel) in self.lit.iter_mut().zip(pixels(graphics)) {
+            *lit |= pixel;
+        }
+    }
+
+    /// Finish a frame, updating the brightness of every pixel
+    pub fn end_frame(&mut self, graphics: &GraphicsBuffer) {
+        self.fit(graphics);
+        let current: Vec<bool> = pixels(graphics).collect();
+
+        match self.mode {
+            PersistenceMode::Off => {
+                for (intensity, pixel) in self.intensity.iter_mut().zip(&current) {
+                    *intensity = brightness(*pixel);
+                }
+            }
+            PersistenceMode::Blend { frames } => {
+                self.history.push_back(current);
+                while self.history.len() > frames {
+                    self.history.pop_front();
+                }
+
+                for (index, intensity) in self.intensity.iter_mut().enumerate() {
+                    let on = self.history.iter().filter(|frame| frame[index]).count();
+                    *intensity = on as f32 / self.history.len() as f32;
+                }
+            }
+            PersistenceMode::Phosphor { decay } => {
+                for (intensity, pixel) in self.intensity.iter_mut().zip(&current) {
+                    *intensity = if *pixel { 1.0 } else { *intensity * decay };
+                }
+            }
+            PersistenceMode::AnyLit => {
+                for ((intensity, lit), pixel) in
+                    self.intensity.iter_mut().zip(&mut self.lit).zip(&current)
+                {
+                    *intensity = brightness(*lit || *pixel);
+                    // The next frame starts with whatever is on now
+                    *lit = *pixel;
+                }
+            }
+        }
+    }
+
+    /// Brightness of a pixel between 0 and 1 as of the last frame
+    pub fn intensity(&self, x: usize, y: usize) -> f32 {
+        if x >= self.width || y >= self.height {
+            return 0.0;
+        }
+
+        self.intensity[y * self.width + x]
+    }
+
+    // Start over when the display changes size
+    fn fit(&mut self, graphics: &GraphicsBuffer) {
+        let (width, height) = (graphics.width(), graphics.height());
+        if (width, height) == (self.width, self.height) {
+            return;
+        }
+
+        self.width = width;
+        self.height = height;
+        self.intensity = vec![0.0; width * height];
+        self.history.clear();
+        self.lit = vec![false; width * height];
+        self.observed_generation = None;
+    }
+}
+
+fn pixels(graphics: &GraphicsBuffer) -> impl Iterator<Item = bool> + '_ {
+    (0..graphics.height()).flat_map(move |y| {
+        (0..graphics.width()).map(move |x| graphics.get_pixel(x as u8, y as u8) == Some(true))
+    })
+}
+
+fn brightness(on: bool) -> f32 {
+    if on {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::graphics::Resolution;
+    use crate::settings::DrawMode;
+
+    fn flicker(graphics: &mut GraphicsBuffer) {
+        graphics.draw(0, 0, &[0x80], DrawMode::Clip);
+    }
+
+    #[test]
+    fn off() {
+        let mut graphics = GraphicsBuffer::new();
+        let mut persistence = Persistence::new(PersistenceMode::Off);
+
+        flicker(&mut graphics);
+        persistence.end_frame(&graphics);
+        assert_eq!(1.0, persistence.intensity(0, 0));
+
+        flicker(&mut graphics);
+        persistence.end_frame(&graphics);
+        assert_eq!(0.0, persistence.intensity(0, 0));
+        assert_eq!(0.0, persistence.intensity(64, 0));
+    }
+
+    #[test]
+    fn blend() {
+        let mut graphics = GraphicsBuffer::new();
+        let mut persistence = Persistence::new(PersistenceMode::Blend { frames: 4 });
+
+        // A pixel that is on every other frame settles at half brightness
+        for _ in 0..3 {
+            flicker(&mut graphics);
+            persistence.end_frame(&graphics);
+        }
+        assert_eq!(2.0 / 3.0, persistence.intensity(0, 0));
+
+        flicker(&mut graphics);
+        persistence.end_frame(&graphics);
+        assert_eq!(0.5, persistence.intensity(0, 0));
+        assert_eq!(0.0, persistence.intensity(1, 0));
+
+        // At least one frame is always blended
+        assert_eq!(
+            PersistenceMode::Blend { frames: 1 },
+            Persistence::new(PersistenceMode::Blend { frames: 0 }).mode()
+        );
+    }
+
+    #[test]
+    fn phosphor() {
+        let mut graphics = GraphicsBuffer::new();
+        let mut persistence = Persistence::new(PersistenceMode::Phosphor { decay: 0.5 });
+
+        flicker(&mut graphics);
+        persistence.end_frame(&graphics);
+        assert_eq!(1.0, persistence.intensity(0, 0));
+
+        flicker(&mut graphics);
+        persistence.end_frame(&graphics);
+        persistence.end_frame(&graphics);
+        assert_eq!(0.25, persistence.intensity(0, 0));
+
+        flicker(&mut graphics);
+        persistence.end_frame(&graphics);
+        assert_eq!(1.0, persistence.intensity(0, 0));
+    }
+
+    #[test]
+    fn any_lit() {
+        let mut graphics = GraphicsBuffer::new();
+        let mut persistence = Persistence::new(PersistenceMode::AnyLit);
+
+        // Erased and redrawn elsewhere within the frame, both positions show
+        flicker(&mut graphics);
+        persistence.observe(&graphics);
+        flicker(&mut graphics);
+        graphics.draw(4, 0, &[0x80], DrawMode::Clip);
+        persistence.observe(&graphics);
+        persistence.end_frame(&graphics);
+        assert_eq!(1.0, persistence.intensity(0, 0));
+        assert_eq!(1.0, persistence.intensity(4, 0));
+
+        // The next frame only shows what was on during it
+        persistence.end_frame(&graphics);
+        assert_eq!(0.0, persistence.intensity(0, 0));
+        assert_eq!(1.0, persistence.intensity(4, 0));
+
+        // Changing resolution starts over
+        graphics.set_resolution(Resolution::High);
+        persistence.end_frame(&graphics);
+        assert_eq!(0.0, persistence.intensity(4, 0));
+        assert_eq!(0.0, persistence.intensity(127, 63));
+    }
+}
@@ -1,13 +1,25 @@
 use crate::io::chip8_io::Chip8IO;
-use crate::io::graphics::{GraphicsBuffer, SpriteData};
+use crate::io::color::{self, Color, ColorScheme};
+use crate::io::graphics::{GraphicsBuffer, Resolution, SpriteData};
 use crate::io::input::{Key as Chip8Key, Keypad, MapKey};
+use crate::io::overlay::{self, Rates, CHARACTER_WIDTH, LINE_HEIGHT};
+use crate::io::persistence::{Persistence, PersistenceMode};
+use crate::io::recorder::Recorder;
+use crate::io::screenshot;
+use crate::monitor::StateMonitor;
+use crate::settings::DrawMode;
+use glutin::window::Fullscreen;
 use glutin_window::OpenGL;
-use graphics::types;
+use graphics::math::Matrix2d;
 use opengl_graphics::GlGraphics;
 use piston::input::Key as PistonKey;
-use piston::{ButtonArgs, ButtonEvent, ButtonState, Event, RenderArgs, RenderEvent};
+use piston::{
+    Button, ButtonArgs, ButtonEvent, ButtonState, Event, PressEvent, RenderArgs, RenderEvent,
+};
+use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 // TODO actually need to play audio when ST > 1
 
@@ -16,46 +28,106 @@ use std::sync::{mpsc, Arc, Mutex};
 #[derive(Clone)]
 pub struct PistonIO {
     internal: Arc<Mutex<PistonIOInternal>>,
+    window_options: WindowOptions,
 }
 
-pub struct ColorScheme {
-    pub background: types::Color,
-    pub foreground: types::Color,
+/// Size and placement of the window
+#[derive(Copy, Clone, Debug)]
+pub struct WindowOptions {
+    // Window pixels per display pixel, which sets the starting window size
+    pub scale: u32,
+    // Starting window size, overriding the size from the scale
+    pub size: Option<[u32; 2]>,
+    pub fullscreen: bool,
 }
 
-pub const BLACK_ON_WHITE: ColorScheme = ColorScheme {
-    background: [1.0, 1.0, 1.0, 1.0],
-    foreground: [0.0, 0.0, 0.0, 1.0],
-};
+impl WindowOptions {
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = scale.max(1);
+        self
+    }
 
-pub const WHITE_ON_BLACK: ColorScheme = ColorScheme {
-    background: [0.0, 0.0, 0.0, 1.0],
-    foreground: [1.0, 1.0, 1.0, 1.0],
-};
+    pub fn with_size(mut self, size: [u32; 2]) -> Self {
+        self.size = Some(size);
+        self
+    }
 
-pub const JAZZ_COLORS: ColorScheme = ColorScheme {
-    background: [19.0 / 256.0, 4.0 / 256.0, 28.0 / 256.0, 1.0],
-    foreground: [155.0 / 256.0, 199.0 / 256.0, 232.0 / 256.0, 1.0],
-};
+    pub fn with_fullscreen(mut self, fullscreen: bool) -> Self {
+        self.fullscreen = fullscreen;
+        self
+    }
+}
+
+impl Default for WindowOptions {
+    fn default() -> Self {
+        WindowOptions {
+            scale: 10,
+            size: None,
+            fullscreen: false,
+        }
+    }
+}
+
+// Key that saves a screenshot, at the default window scale
+const SCREENSHOT_KEY: PistonKey = PistonKey::F12;
+const SCREENSHOT_SCALE: usize = 10;
+// Key that starts recording a GIF, and saves it when pressed again
+const RECORD_KEY: PistonKey = PistonKey::F10;
+// Key that switches between a window and fullscreen
+const FULLSCREEN_KEY: PistonKey = PistonKey::F11;
+// Key that switches to the next color scheme
+const PALETTE_KEY: PistonKey = PistonKey::F9;
+// Key that shows and hides the debug overlay
+const OVERLAY_KEY: PistonKey = PistonKey::F8;
+const OVERLAY_BACKGROUND: Color = [0.0, 0.0, 0.0, 0.75];
+const OVERLAY_TEXT: Color = [1.0, 1.0, 1.0, 1.0];
+// Color of the bars around the display when the window has a different shape
+const LETTERBOX_COLOR: Color = [0.0, 0.0, 0.0, 1.0];
+const RECORDING_SCALE: usize = 4;
 
 struct PistonIOInternal {
     color_scheme: ColorScheme,
+    // Schemes the palette key cycles through
+    palettes: Vec<ColorScheme>,
     graphics_buffer: GraphicsBuffer,
+    // Runs of lit pixels in each row as (start, length), rebuilt only for the rows that change
+    row_runs: Vec<Vec<(usize, usize)>>,
+    persistence: Persistence,
+    recorder: Option<Recorder>,
+    // Where the machine's state comes from, and the overlay's rates while it is shown
+    monitor: Option<StateMonitor>,
+    overlay: Option<Rates>,
     keypad: Keypad,
     interrupt_channel: Option<Sender<Chip8Key>>,
 }
 
 impl PistonIOInternal {
-    pub fn new(color_scheme: ColorScheme) -> Self {
+    pub fn new(color_scheme: ColorScheme, persistence: PersistenceMode) -> Self {
         PistonIOInternal {
             color_scheme,
+            palettes: Vec::new(),
             graphics_buffer: GraphicsBuffer::new(),
+            row_runs: Vec::new(),
+            persistence: Persistence::new(persistence),
+            recorder: None,
+            monitor: None,
+            overlay: None,
             keypad: Keypad::new(),
             interrupt_channel: None,
         }
     }
 
     fn handle_button_event(&mut self, args: ButtonArgs) {
+        if args.state == ButtonState::Press {
+            match args.button {
+                Button::Keyboard(SCREENSHOT_KEY) => return self.save_screenshot(),
+                Button::Keyboard(RECORD_KEY) => return self.toggle_recording(),
+                Button::Keyboard(PALETTE_KEY) => return self.next_palette(),
+                Button::Keyboard(OVERLAY_KEY) => return self.toggle_overlay(),
+                _ => {}
+            }
+        }
+
         match (args.state, args.button.map_key()) {
             (ButtonState::Press, Some(key)) => {
                 self.keypad.press(key);
@@ -72,6 +144,54 @@ impl PistonIOInternal {
         }
     }
 
+    fn next_palette(&mut self) {
+        if let Some(scheme) = color::next_scheme(&self.palettes, &self.color_scheme) {
+            self.color_scheme = scheme;
+        }
+    }
+
+    fn toggle_overlay(&mut self) {
+        self.overlay = match self.overlay {
+            Some(_) => None,
+            None => Some(Rates::new()),
+        };
+    }
+
+    fn save_screenshot(&self) {
+        let path = timestamped_path("png");
+
+        match screenshot::save_image(
+            &self.graphics_buffer,
+            &self.color_scheme,
+            SCREENSHOT_SCALE,
+            &path,
+        ) {
+            Ok(()) => println!("Saved screenshot to {}", path.display()),
+            Err(e) => println!("Failed to save screenshot: {}", e),
+        }
+    }
+
+    fn toggle_recording(&mut self) {
+        let recorder = match self.recorder.take() {
+            Some(recorder) => recorder,
+            None => {
+                println!("Recording started");
+                self.recorder = Some(Recorder::new());
+                return;
+            }
+        };
+
+        let path = timestamped_path("gif");
+        match recorder.save_gif(&self.color_scheme, RECORDING_SCALE, &path) {
+            Ok(()) => println!(
+                "Saved {} frames of recording to {}",
+                recorder.frames(),
+                path.display()
+            ),
+            Err(e) => println!("Failed to save recording: {}", e),
+        }
+    }
+
     pub fn handle_event(&mut self, e: Event, gl: &mut GlGraphics) {
         if let Some(button_args) = e.button_args() {
             self.handle_button_event(button_args);
@@ -82,26 +202,147 @@ impl PistonIOInternal {
         }
     }
 
-    fn render(&self, gl: &mut GlGraphics, args: &RenderArgs) {
+    fn update_runs(&mut self) {
+        let height = self.graphics_buffer.height();
+        if self.row_runs.len() != height {
+            self.row_runs = vec![Vec::new(); height];
+        }
+
+        let dirty = match self.graphics_buffer.take_dirty() {
+            Some(dirty) => dirty,
+            None => return,
+        };
+
+        for y in dirty.rows {
+            let runs = &mut self.row_runs[y];
+            runs.clear();
+
+            for x in 0..self.graphics_buffer.width() {
+                if self.graphics_buffer.get_pixel(x as u8, y as u8) != Some(true) {
+                    continue;
+                }
+
+                match runs.last_mut() {
+                    Some((start, length)) if *start + *length == x => *length += 1,
+                    _ => runs.push((x, 1)),
+                }
+            }
+        }
+    }
+
+    fn render(&mut self, gl: &mut GlGraphics, args: &RenderArgs) {
+        if self.persistence.mode() != PersistenceMode::Off {
+            self.persistence.end_frame(&self.graphics_buffer);
+            self.render_persistence(gl, args);
+        } else {
+            self.render_runs(gl, args);
+        }
+
+        if self.overlay.is_some() {
+            self.render_overlay(gl, args);
+        }
+    }
+
+    fn render_runs(&mut self, gl: &mut GlGraphics, args: &RenderArgs) {
+        self.update_runs();
+        let layout = Layout::letterbox(
+            args.window_size,
+            self.graphics_buffer.width(),
+            self.graphics_buffer.height(),
+        );
+
+        gl.draw(args.viewport(), |c, gl| {
+            layout.clear(self.color_scheme.background, c.transform, gl);
+
+            for (y, runs) in self.row_runs.iter().enumerate() {
+                for (x, length) in runs {
+                    let [start_x, start_y, size] = layout.cell(*x, y);
+
+                    graphics::rectangle(
+                        self.color_scheme.foreground,
+                        [start_x, start_y, size * *length as f64, size],
+                        c.transform,
+                        gl,
+                    );
+                }
+            }
+        });
+    }
+
+    // Draw the machine's state over the top left corner of the window
+    fn render_overlay(&mut self, gl: &mut GlGraphics, args: &RenderArgs) {
+        // Copying the state out only holds the monitor's lock for a moment
+        let state = self.monitor.as_ref().and_then(|monitor| monitor.latest());
+        let rates = match &mut self.overlay {
+            Some(rates) => rates,
+            None => return,
+        };
+        rates.frame(
+            Instant::now(),
+            state.as_ref().map_or(0, |state| state.instructions),
+        );
+        let lines = overlay::lines(state.as_ref(), &self.keypad.pressed_keys(), rates);
+
+        // Font pixels grow with the window, in whole window pixels
+        let size = (args.window_size[0].min(args.window_size[1]) / 160.0)
+            .floor()
+            .max(1.0);
+        let columns = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+        let panel = [
+            0.0,
+            0.0,
+            (columns * CHARACTER_WIDTH + 1) as f64 * size,
+            (lines.len() * LINE_HEIGHT + 1) as f64 * size,
+        ];
+
+        gl.draw(args.viewport(), |c, gl| {
+            graphics::rectangle(OVERLAY_BACKGROUND, panel, c.transform, gl);
+
+            for (row, line) in lines.iter().enumerate() {
+                for (x, y, length) in overlay::text_runs(line) {
+                    graphics::rectangle(
+                        OVERLAY_TEXT,
+                        [
+                            (x + 1) as f64 * size,
+                            (row * LINE_HEIGHT + y + 1) as f64 * size,
+                            length as f64 * size,
+                            size,
+                        ],
+                        c.transform,
+                        gl,
+                    );
+                }
+            }
+        });
+    }
+
+    // Draw every pixel with some brightness, mixing the foreground into the background
+    fn render_persistence(&self, gl: &mut GlGraphics, args: &RenderArgs) {
+        let (background, foreground) = (self.color_scheme.background, self.color_scheme.foreground);
+
+        let layout = Layout::letterbox(
+            args.window_size,
+            self.graphics_buffer.width(),
+            self.graphics_buffer.height(),
+        );
+
         gl.draw(args.viewport(), |c, gl| {
-            graphics::clear(self.color_scheme.background, gl);
+            layout.clear(background, c.transform, gl);
 
-            // TODO iterator over the pixels?
             for y in 0..self.graphics_buffer.height() {
                 for x in 0..self.graphics_buffer.width() {
-                    let pixel = self.graphics_buffer.get_pixel(x as u8, y as u8);
-
-                    if pixel == Some(true) {
-                        let start_x = 10.0 * x as f64;
-                        let start_y = 10.0 * y as f64;
-
-                        graphics::rectangle(
-                            self.color_scheme.foreground,
-                            [start_x, start_y, 10.0, 10.0],
-                            c.transform,
-                            gl,
-                        );
+                    let intensity = self.persistence.intensity(x, y);
+                    if intensity <= 0.0 {
+                        continue;
                     }
+
+                    let mut color = background;
+                    for (channel, target) in color.iter_mut().zip(foreground) {
+                        *channel += (target - *channel) * intensity;
+                    }
+
+                    let [start_x, start_y, size] = layout.cell(x, y);
+                    graphics::rectangle(color, [start_x, start_y, size, size], c.transform, gl);
                 }
             }
         });
@@ -113,12 +354,43 @@ impl Chip8IO for PistonIO {
         self.internal.lock().unwrap().graphics_buffer.clear();
     }
 
-    fn draw(&mut self, x: u8, y: u8, sprite: &SpriteData) -> bool {
+    fn draw(&mut self, x: u8, y: u8, sprite: &SpriteData, mode: DrawMode) -> bool {
+        let mut internal = self.internal.lock().unwrap();
+        let flipped = internal.graphics_buffer.draw(x, y, sprite, mode);
+
+        let PistonIOInternal {
+            graphics_buffer,
+            persistence,
+            ..
+        } = &mut *internal;
+        persistence.observe(graphics_buffer);
+
+        flipped
+    }
+
+    fn set_resolution(&mut self, resolution: Resolution) {
         self.internal
             .lock()
             .unwrap()
             .graphics_buffer
-            .draw(x, y, sprite)
+            .set_resolution(resolution);
+    }
+
+    fn end_frame(&mut self) {
+        let mut internal = self.internal.lock().unwrap();
+
+        let PistonIOInternal {
+            graphics_buffer,
+            recorder,
+            ..
+        } = &mut *internal;
+        if let Some(recorder) = recorder {
+            recorder.capture(graphics_buffer);
+        }
+    }
+
+    fn display_generation(&self) -> u64 {
+        self.internal.lock().unwrap().graphics_buffer.generation()
     }
 
     fn key_pressed(&mut self, key: Chip8Key) -> bool {
@@ -134,26 +406,54 @@ impl Chip8IO for PistonIO {
 }
 
 impl PistonIO {
-    pub fn new(color_scheme: ColorScheme) -> Self {
-        let internal = PistonIOInternal::new(color_scheme);
+    pub fn new(color_scheme: ColorScheme, persistence: PersistenceMode) -> Self {
+        let internal = PistonIOInternal::new(color_scheme, persistence);
 
         PistonIO {
             internal: Arc::new(Mutex::new(internal)),
+            window_options: WindowOptions::default(),
         }
     }
 
+    /// Where the debug overlay toggled by F8 gets the machine's state from
+    pub fn with_monitor(self, monitor: StateMonitor) -> Self {
+        self.internal.lock().unwrap().monitor = Some(monitor);
+        self
+    }
+
+    /// Color schemes that F9 cycles through
+    pub fn with_palettes(self, palettes: Vec<ColorScheme>) -> Self {
+        self.internal.lock().unwrap().palettes = palettes;
+        self
+    }
+
+    pub fn with_window_options(mut self, window_options: WindowOptions) -> Self {
+        self.window_options = window_options;
+        self
+    }
+
     pub fn open_window<F>(self, on_ready: F)
     where
         F: FnOnce(),
     {
         let opengl = OpenGL::V4_5;
 
-        let mut window: glutin_window::GlutinWindow =
-            piston::WindowSettings::new("crust-8", [640, 320])
-                .graphics_api(opengl)
-                .exit_on_esc(true)
-                .build()
-                .unwrap();
+        let options = self.window_options;
+        let size = options.size.unwrap_or_else(|| {
+            let graphics_buffer = &self.internal.lock().unwrap().graphics_buffer;
+            [
+                graphics_buffer.width() as u32 * options.scale,
+                graphics_buffer.height() as u32 * options.scale,
+            ]
+        });
+
+        let mut window: glutin_window::GlutinWindow = piston::WindowSettings::new("crust-8", size)
+            .graphics_api(opengl)
+            .exit_on_esc(true)
+            .fullscreen(options.fullscreen)
+            .build()
+            .unwrap();
+        let mut fullscreen = options.fullscreen;
 
         // TODO passing a lot around, maybe can be smarter about object structure
         let mut gl = GlGraphics::new(opengl);
@@ -165,6 +465,15 @@ impl PistonIO {
         }
 
         while let Some(e) = events.next(&mut window) {
+            if e.press_args() == Some(Button::Keyboard(FULLSCREEN_KEY)) {
+                fullscreen = !fullscreen;
+                window
+                    .ctx
+                    .window()
+                    .set_fullscreen(fullscreen.then(|| Fullscreen::Borderless(None)));
+                continue;
+            }
+
             // TODO can probably be smarter about not duplicating these checks
             // TODO look at the press and release implementations to see the underlying
             let mut internal = self.internal.lock().unwrap();
@@ -173,6 +482,64 @@ impl PistonIO {
     }
 }
 
+// Where the display goes in the window, as large as fits without changing its shape
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Layout {
+    left: f64,
+    top: f64,
+    // Size of one display pixel
+    cell: f64,
+    width: f64,
+    height: f64,
+}
+
+impl Layout {
+    fn letterbox(window_size: [f64; 2], width: usize, height: usize) -> Layout {
+        let fit = (window_size[0] / width as f64).min(window_size[1] / height as f64);
+        // Whole window pixels per display pixel keep every pixel the same size
+        let cell = if fit >= 1.0 { fit.floor() } else { fit };
+        let (display_width, display_height) = (cell * width as f64, cell * height as f64);
+
+        Layout {
+            left: ((window_size[0] - display_width) / 2.0).floor(),
+            top: ((window_size[1] - display_height) / 2.0).floor(),
+            cell,
+            width: display_width,
+            height: display_height,
+        }
+    }
+
+    // Position and size of a display pixel
+    fn cell(&self, x: usize, y: usize) -> [f64; 3] {
+        [
+            self.left + self.cell * x as f64,
+            self.top + self.cell * y as f64,
+            self.cell,
+        ]
+    }
+
+    // Fill the bars around the display and the display's background
+    fn clear(&self, background: Color, transform: Matrix2d, gl: &mut GlGraphics) {
+        graphics::clear(LETTERBOX_COLOR, gl);
+        graphics::rectangle(
+            background,
+            [self.left, self.top, self.width, self.height],
+            transform,
+            gl,
+        );
+    }
+}
+
+// A file next to where the emulator was started, named by the current time
+fn timestamped_path(extension: &str) -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or(0);
+
+    PathBuf::from(format!("crust-8-{}.{}", millis, extension))
+}
+
 impl MapKey for piston::input::Button {
     fn map_key(&self) -> Option<Chip8Key> {
         match self {
@@ -205,3 +572,32 @@ impl MapKey for PistonKey {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn letterbox() {
+        // The default window fits the display exactly
+        let layout = Layout::letterbox([640.0, 320.0], 64, 32);
+        assert_eq!([0.0, 0.0, 10.0], layout.cell(0, 0));
+        assert_eq!([630.0, 310.0, 10.0], layout.cell(63, 31));
+
+        // A taller window puts bars above and below, with whole pixels per cell
+        let layout = Layout::letterbox([650.0, 650.0], 64, 32);
+        assert_eq!(10.0, layout.cell);
+        assert_eq!((5.0, 165.0), (layout.left, layout.top));
+        assert_eq!((640.0, 320.0), (layout.width, layout.height));
+
+        // A wider window puts bars at the sides, and a higher resolution gets smaller cells
+        let layout = Layout::letterbox([1920.0, 1080.0], 128, 64);
+        assert_eq!(15.0, layout.cell);
+        assert_eq!((0.0, 60.0), (layout.left, layout.top));
+
+        // Windows smaller than the display still show all of it
+        let layout = Layout::letterbox([32.0, 32.0], 64, 32);
+        assert_eq!(0.5, layout.cell);
+        assert_eq!((0.0, 8.0), (layout.left, layout.top));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::io::color::{self, ColorScheme};
+use crate::io::graphics::GraphicsBuffer;
+use gif::{Encoder, EncodingError, Frame, Repeat};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// Frames are captured as the machine finishes each 60 Hz frame, and GIF delays count hundredths of a second
+const FRAMES_PER_SECOND: u64 = 60;
+const DELAY_UNITS_PER_SECOND: u64 = 100;
+
+// A run of identical frames
+struct Still {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+    frames: u64,
+}
+
+/// Records the display frame by frame and encodes the recording as an animated GIF
+pub struct Recorder {
+    stills: Vec<Still>,
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder { stills: Vec::new() }
+    }
+
+    /// Add the display as it was presented for one frame
+    pub fn capture(&mut self, graphics: &GraphicsBuffer) {
+        let (width, height) = (graphics.width(), graphics.height());
+        let pixels: Vec<bool> = (0..height)
+            .flat_map(|y| {
+                (0..width).map(move |x| graphics.get_pixel(x as u8, y as u8) == Some(true))
+            })
+            .collect();
+
+        // A frame that looks like the one before it just holds that one for longer
+        if let Some(last) = self.stills.last_mut() {
+            if (last.width, last.height) == (width, height) && last.pixels == pixels {
+                last.frames += 1;
+                return;
+            }
+        }
+
+        self.stills.push(Still {
+            width,
+            height,
+            pixels,
+            frames: 1,
+        });
+    }
+
+    /// Number of frames captured so far
+    pub fn frames(&self) -> u64 {
+        self.stills.iter().map(|still| still.frames).sum()
+    }
+
+    /// Number of distinct images the recording will hold
+    pub fn images(&self) -> usize {
+        self.stills.len()
+    }
+
+    /// Encode the recording as a looping GIF, with every pixel scaled up to a square of the given size
+    /// The image is as large as the largest display resolution used
+    pub fn write_gif<W: Write>(
+        &self,
+        color_scheme: &ColorScheme,
+        scale: usize,
+        output: W,
+    ) -> Result<(), EncodingError> {
+        let scale = scale.max(1);
+        let width = self
+            .stills
+            .iter()
+            .map(|still| still.width)
+            .max()
+            .unwrap_or(0)
+            * scale;
+        let height = self
+            .stills
+            .iter()
+            .map(|still| still.height)
+            .max()
+            .unwrap_or(0)
+            * scale;
+
+        let palette: Vec<u8> = [color_scheme.background, color_scheme.foreground]
+            .iter()
+            .flat_map(|color| color::to_rgb(*color))
+            .collect();
+        let mut encoder = Encoder::new(output, width as u16, height as u16, &palette)?;
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        // Delays are counted from the start of the recording so rounding doesn't drift
+        let mut elapsed = 0;
+        for still in &self.stills {
+            let start = elapsed * DELAY_UNITS_PER_SECOND / FRAMES_PER_SECOND;
+            elapsed += still.frames;
+            let end = elapsed * DELAY_UNITS_PER_SECOND / FRAMES_PER_SECOND;
+
+            let mut indices = vec![0; width * height];
+            for y in 0..still.height * scale {
+                for x in 0..still.width * scale {
+                    indices[y * width + x] =
+                        still.pixels[y / scale * still.width + x / scale] as u8;
+                }
+            }
+
+            let mut frame = Frame::from_indexed_pixels(width as u16, height as u16, indices, None);
+            frame.delay = (end - start).min(u16::MAX as u64) as u16;
+            encoder.write_frame(&frame)?;
+        }
+
+        Ok(())
+    }
+
+    /// Save the recording to a GIF file
+    pub fn save_gif(
+        &self,
+        color_scheme: &ColorScheme,
+        scale: usize,
+        path: &Path,
+    ) -> Result<(), EncodingError> {
+        let mut output = BufWriter::new(fs::File::create(path)?);
+        self.write_gif(color_scheme, scale, &mut output)?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Recorder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::color::BLACK_ON_WHITE;
+    use crate::settings::DrawMode;
+
+    #[test]
+    fn deduplicates_frames() {
+        let mut graphics = GraphicsBuffer::new();
+        let mut recorder = Recorder::new();
+
+        recorder.capture(&graphics);
+        recorder.capture(&graphics);
+
+        // Erasing and redrawing a sprite within a frame leaves the frame unchanged
+        graphics.draw(0, 0, &[0x80], DrawMode::Clip);
+        recorder.capture(&graphics);
+        graphics.draw(0, 0, &[0x80], DrawMode::Clip);
+        graphics.draw(0, 0, &[0x80], DrawMode::Clip);
+        recorder.capture(&graphics);
+
+        assert_eq!(4, recorder.frames());
+        assert_eq!(2, recorder.images());
+    }
+
+    #[test]
+    fn gif() {
+        let mut graphics = GraphicsBuffer::new();
+        let mut recorder = Recorder::new();
+
+        for _ in 0..3 {
+            recorder.capture(&graphics);
+        }
+        graphics.draw(1, 0, &[0x80], DrawMode::Clip);
+        for _ in 0..60 {
+            recorder.capture(&graphics);
+        }
+
+        let mut image = Vec::new();
+        recorder.write_gif(&BLACK_ON_WHITE, 2, &mut image).unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(&image[..]).unwrap();
+        assert_eq!((128, 64), (decoder.width(), decoder.height()));
+        assert_eq!(
+            Some(&[255, 255, 255, 0, 0, 0][..]),
+            decoder.global_palette()
+        );
+
+        // 3 frames at 60 Hz round to 5 hundredths of a second, and the next second takes the rest
+        let first = decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!(5, first.delay);
+        assert!(first.buffer.iter().all(|index| *index == 0));
+
+        let second = decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!(100, second.delay);
+        assert_eq!([0, 0, 1, 1, 0], second.buffer[0..5]);
+        assert_eq!([0, 0, 1, 1, 0], second.buffer[128..133]);
+        assert!(decoder.read_next_frame().unwrap().is_none());
+    }
+}
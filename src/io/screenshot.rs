@@ -0,0 +1,213 @@
+use crate::io::color::{self, ColorScheme};
+use crate::io::graphics::GraphicsBuffer;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Image formats a screenshot can be written in
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ImageFormat {
+    // Full color PNG
+    Png,
+    // Black and white portable bitmap, with the foreground in black
+    Pbm,
+    // Full color portable pixmap
+    Ppm,
+}
+
+impl ImageFormat {
+    /// Pick a format from a file extension, if it is one of the supported ones
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "pbm" => Some(ImageFormat::Pbm),
+            "ppm" => Some(ImageFormat::Ppm),
+            _ => None,
+        }
+    }
+}
+
+/// Write the display as an image, with every pixel scaled up to a square of the given size
+pub fn write_image<W: Write>(
+    graphics: &GraphicsBuffer,
+    color_scheme: &ColorScheme,
+    scale: usize,
+    format: ImageFormat,
+    output: W,
+) -> io::Result<()> {
+    let scale = scale.max(1);
+    let (width, height) = (graphics.width() * scale, graphics.height() * scale);
+    let lit =
+        |x: usize, y: usize| graphics.get_pixel((x / scale) as u8, (y / scale) as u8) == Some(true);
+
+    match format {
+        ImageFormat::Pbm => {
+            let mut output = output;
+            write!(output, "P4\n{} {}\n", width, height)?;
+
+            // Each row is padded out to a whole number of bytes
+            for y in 0..height {
+                let row: Vec<u8> = (0..width)
+                    .step_by(8)
+                    .map(|start| {
+                        (start..(start + 8).min(width))
+                            .filter(|x| lit(*x, y))
+                            .fold(0, |byte, x| byte | 0x80 >> (x - start))
+                    })
+                    .collect();
+                output.write_all(&row)?;
+            }
+
+            Ok(())
+        }
+        ImageFormat::Ppm | ImageFormat::Png => {
+            let (background, foreground) = (
+                color::to_rgb(color_scheme.background),
+                color::to_rgb(color_scheme.foreground),
+            );
+            let mut data = Vec::with_capacity(width * height * 3);
+
+            for y in 0..height {
+                for x in 0..width {
+                    data.extend(if lit(x, y) { foreground } else { background });
+                }
+            }
+
+            if format == ImageFormat::Ppm {
+                let mut output = output;
+                write!(output, "P6\n{} {}\n255\n", width, height)?;
+                return output.write_all(&data);
+            }
+
+            let mut encoder = png::Encoder::new(output, width as u32, height as u32);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&data)?;
+            Ok(())
+        }
+    }
+}
+
+/// Save the display to a file, in the format given by its extension
+pub fn save_image(
+    graphics: &GraphicsBuffer,
+    color_scheme: &ColorScheme,
+    scale: usize,
+    path: &Path,
+) -> io::Result<()> {
+    let format = ImageFormat::from_path(path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a .png, .pbm or .ppm file", path.display()),
+        )
+    })?;
+
+    let mut output = BufWriter::new(fs::File::create(path)?);
+    write_image(graphics, color_scheme, scale, format, &mut output)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::color::BLACK_ON_WHITE;
+    use crate::settings::DrawMode;
+
+    fn test_graphics() -> GraphicsBuffer {
+        let mut graphics = GraphicsBuffer::new();
+        graphics.draw(0, 0, &[0x80], DrawMode::Clip);
+        graphics.draw(63, 31, &[0x80], DrawMode::Clip);
+        graphics
+    }
+
+    #[test]
+    fn formats_from_paths() {
+        assert_eq!(
+            Some(ImageFormat::Png),
+            ImageFormat::from_path(Path::new("shot.PNG"))
+        );
+        assert_eq!(
+            Some(ImageFormat::Pbm),
+            ImageFormat::from_path(Path::new("shot.pbm"))
+        );
+        assert_eq!(
+            Some(ImageFormat::Ppm),
+            ImageFormat::from_path(Path::new("dir/shot.ppm"))
+        );
+        assert_eq!(None, ImageFormat::from_path(Path::new("shot.gif")));
+        assert_eq!(None, ImageFormat::from_path(Path::new("shot")));
+    }
+
+    #[test]
+    fn pbm() {
+        let mut image = Vec::new();
+        write_image(
+            &test_graphics(),
+            &BLACK_ON_WHITE,
+            2,
+            ImageFormat::Pbm,
+            &mut image,
+        )
+        .unwrap();
+
+        let header = b"P4\n128 64\n";
+        assert_eq!(header, &image[..header.len()]);
+
+        let rows = &image[header.len()..];
+        assert_eq!(16 * 64, rows.len());
+        assert_eq!([0xC0, 0x00], rows[0..2]);
+        assert_eq!(0xC0, rows[16]);
+        assert_eq!(0x00, rows[32]);
+        assert_eq!(0x03, rows[16 * 64 - 1]);
+    }
+
+    #[test]
+    fn ppm() {
+        let mut image = Vec::new();
+        write_image(
+            &test_graphics(),
+            &BLACK_ON_WHITE,
+            1,
+            ImageFormat::Ppm,
+            &mut image,
+        )
+        .unwrap();
+
+        let header = b"P6\n64 32\n255\n";
+        assert_eq!(header, &image[..header.len()]);
+
+        let pixels = &image[header.len()..];
+        assert_eq!(64 * 32 * 3, pixels.len());
+        assert_eq!([0, 0, 0], pixels[0..3]);
+        assert_eq!([255, 255, 255], pixels[3..6]);
+        assert_eq!([0, 0, 0], pixels[pixels.len() - 3..]);
+    }
+
+    #[test]
+    fn png() {
+        let mut image = Vec::new();
+        write_image(
+            &test_graphics(),
+            &BLACK_ON_WHITE,
+            3,
+            ImageFormat::Png,
+            &mut image,
+        )
+        .unwrap();
+
+        let decoder = png::Decoder::new(&image[..]);
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+
+        assert_eq!((192, 96), (info.width, info.height));
+        assert_eq!(png::ColorType::Rgb, info.color_type);
+        assert_eq!([0, 0, 0], pixels[0..3]);
+        assert_eq!([0, 0, 0], pixels[6..9]);
+        assert_eq!([255, 255, 255], pixels[9..12]);
+    }
+}
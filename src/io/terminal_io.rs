@@ -0,0 +1,402 @@
+use crate::io::chip8_io::Chip8IO;
+use crate::io::color::{self, ColorScheme};
+use crate::io::graphics::{GraphicsBuffer, Resolution, SpriteData};
+use crate::io::input::{Key as Chip8Key, Keypad, MapKey};
+use crate::settings::DrawMode;
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, execute, queue, terminal};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::mpsc::Sender;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Most terminals only report presses, so a key counts as held until its auto-repeat stops
+// Terminals wait up to about 600ms before the first repeat and then repeat much faster
+const FIRST_REPEAT_DELAY: Duration = Duration::from_millis(600);
+const KEY_HOLD_TIME: Duration = Duration::from_millis(150);
+// How long to wait for input before drawing the next frame, about 60 frames a second
+const FRAME_TIME: Duration = Duration::from_micros(16667);
+// Key that switches to the next color scheme
+const PALETTE_KEY: KeyCode = KeyCode::F(9);
+
+/// Frontend that draws the display in a terminal, two pixels to a character cell
+#[derive(Clone)]
+pub struct TerminalIO {
+    internal: Arc<Mutex<TerminalIOInternal>>,
+}
+
+struct TerminalIOInternal {
+    color_scheme: ColorScheme,
+    // Schemes the palette key cycles through
+    palettes: Vec<ColorScheme>,
+    graphics_buffer: GraphicsBuffer,
+    keypad: Keypad,
+    // Keys held on terminals that don't report releases
+    held_keys: HashMap<Chip8Key, HeldKey>,
+    reports_releases: bool,
+    interrupt_channel: Option<Sender<Chip8Key>>,
+}
+
+struct HeldKey {
+    last_reported: Instant,
+    // Whether the key has been reported again since it was first pressed
+    repeating: bool,
+}
+
+impl HeldKey {
+    fn hold_time(&self) -> Duration {
+        if self.repeating {
+            KEY_HOLD_TIME
+        } else {
+            FIRST_REPEAT_DELAY
+        }
+    }
+}
+
+impl TerminalIOInternal {
+    fn handle_key_event(&mut self, event: KeyEvent) {
+        let key = match event.code.map_key() {
+            Some(key) => key,
+            None => return,
+        };
+
+        match event.kind {
+            KeyEventKind::Press | KeyEventKind::Repeat => {
+                self.keypad.press(key);
+                let repeating = self.held_keys.contains_key(&key);
+                self.held_keys.insert(
+                    key,
+                    HeldKey {
+                        last_reported: Instant::now(),
+                        repeating,
+                    },
+                );
+
+                if let Some(channel) = &mut self.interrupt_channel {
+                    // Ignore a failed send
+                    channel.send(key).unwrap_or(());
+                    self.interrupt_channel = None;
+                }
+            }
+            KeyEventKind::Release => {
+                self.keypad.release(&key);
+                self.held_keys.remove(&key);
+            }
+        }
+    }
+
+    fn release_expired_keys(&mut self) {
+        if self.reports_releases {
+            return;
+        }
+
+        let now = Instant::now();
+        let keypad = &mut self.keypad;
+        self.held_keys.retain(|key, held_key| {
+            let held = now.duration_since(held_key.last_reported) < held_key.hold_time();
+            if !held {
+                keypad.release(key);
+            }
+            held
+        });
+    }
+}
+
+impl Chip8IO for TerminalIO {
+    fn clear(&mut self) {
+        self.internal.lock().unwrap().graphics_buffer.clear();
+    }
+
+    fn draw(&mut self, x: u8, y: u8, sprite: &SpriteData, mode: DrawMode) -> bool {
+        self.internal
+            .lock()
+            .unwrap()
+            .graphics_buffer
+            .draw(x, y, sprite, mode)
+    }
+
+    fn set_resolution(&mut self, resolution: Resolution) {
+        self.internal
+            .lock()
+            .unwrap()
+            .graphics_buffer
+            .set_resolution(resolution);
+    }
+
+    fn end_frame(&mut self) {}
+
+    fn display_generation(&self) -> u64 {
+        self.internal.lock().unwrap().graphics_buffer.generation()
+    }
+
+    fn key_pressed(&mut self, key: Chip8Key) -> bool {
+        let mut internal = self.internal.lock().unwrap();
+        internal.release_expired_keys();
+        internal.keypad.is_pressed(&key)
+    }
+
+    fn block_for_key(&mut self) -> Option<Chip8Key> {
+        let (tx, rx) = mpsc::channel();
+
+        self.internal.lock().unwrap().interrupt_channel = Some(tx);
+        rx.recv().ok()
+    }
+}
+
+impl TerminalIO {
+    pub fn new(color_scheme: ColorScheme) -> Self {
+        let internal = TerminalIOInternal {
+            color_scheme,
+            palettes: Vec::new(),
+            graphics_buffer: GraphicsBuffer::new(),
+            keypad: Keypad::new(),
+            held_keys: HashMap::new(),
+            reports_releases: false,
+            interrupt_channel: None,
+        };
+
+        TerminalIO {
+            internal: Arc::new(Mutex::new(internal)),
+        }
+    }
+
+    /// Color schemes that F9 cycles through
+    pub fn with_palettes(self, palettes: Vec<ColorScheme>) -> Self {
+        self.internal.lock().unwrap().palettes = palettes;
+        self
+    }
+
+    /// Take over the terminal and draw the display until Escape or Ctrl+C is pressed
+    pub fn open_terminal<F>(self, on_ready: F) -> io::Result<()>
+    where
+        F: FnOnce(),
+    {
+        let mut stdout = io::stdout();
+
+        terminal::enable_raw_mode()?;
+        execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+        // Ask for key releases where the terminal can report them
+        let reports_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_releases {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        self.internal.lock().unwrap().reports_releases = reports_releases;
+
+        on_ready();
+        let result = self.event_loop(&mut stdout);
+
+        // Put the terminal back however the loop ended
+        if reports_releases {
+            execute!(stdout, PopKeyboardEnhancementFlags)?;
+        }
+        execute!(
+            stdout,
+            ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        )?;
+        terminal::disable_raw_mode()?;
+
+        result
+    }
+
+    fn event_loop(&self, stdout: &mut io::Stdout) -> io::Result<()> {
+        let mut drawn_generation = None;
+
+        loop {
+            if event::poll(FRAME_TIME)? {
+                match event::read()? {
+                    Event::Key(key_event) if is_exit(&key_event) => return Ok(()),
+                    Event::Key(key_event)
+                        if key_event.code == PALETTE_KEY
+                            && key_event.kind == KeyEventKind::Press =>
+                    {
+                        let mut internal = self.internal.lock().unwrap();
+                        if let Some(scheme) =
+                            color::next_scheme(&internal.palettes, &internal.color_scheme)
+                        {
+                            internal.color_scheme = scheme;
+                            drawn_generation = None;
+                        }
+                    }
+                    Event::Key(key_event) => {
+                        self.internal.lock().unwrap().handle_key_event(key_event)
+                    }
+                    // Everything has to be drawn again after a resize
+                    Event::Resize(_, _) => drawn_generation = None,
+                    _ => {}
+                }
+            }
+
+            let internal = self.internal.lock().unwrap();
+            let generation = internal.graphics_buffer.generation();
+
+            if drawn_generation != Some(generation) {
+                let frame = render(&internal.graphics_buffer, &internal.color_scheme);
+                drop(internal);
+
+                stdout.write_all(&frame)?;
+                stdout.flush()?;
+                drawn_generation = Some(generation);
+            }
+        }
+    }
+}
+
+fn is_exit(event: &KeyEvent) -> bool {
+    let ctrl_c =
+        event.code == KeyCode::Char('c') && event.modifiers.contains(KeyModifiers::CONTROL);
+    event.kind == KeyEventKind::Press && (event.code == KeyCode::Esc || ctrl_c)
+}
+
+// Draw the whole display as rows of upper half blocks
+// The foreground color is the top pixel of a cell and the background color is the bottom one
+fn render(graphics: &GraphicsBuffer, color_scheme: &ColorScheme) -> Vec<u8> {
+    let [background, foreground] =
+        [color_scheme.background, color_scheme.foreground].map(|color| {
+            let [r, g, b] = color::to_rgb(color);
+            Color::Rgb { r, g, b }
+        });
+    let color = |x: usize, y: usize| {
+        if graphics.get_pixel(x as u8, y as u8) == Some(true) {
+            foreground
+        } else {
+            background
+        }
+    };
+
+    // Writing to a Vec can't fail
+    let mut frame = Vec::new();
+    for row in 0..graphics.height().div_ceil(2) {
+        queue!(frame, cursor::MoveTo(0, row as u16)).unwrap();
+
+        // Only change colors when they differ from the cell before
+        let mut colors = None;
+        for x in 0..graphics.width() {
+            let (top, bottom) = (color(x, 2 * row), color(x, 2 * row + 1));
+
+            if colors != Some((top, bottom)) {
+                queue!(frame, SetForegroundColor(top), SetBackgroundColor(bottom)).unwrap();
+                colors = Some((top, bottom));
+            }
+            queue!(frame, Print('▀')).unwrap();
+        }
+
+        queue!(frame, ResetColor).unwrap();
+    }
+
+    frame
+}
+
+impl MapKey for KeyCode {
+    fn map_key(&self) -> Option<Chip8Key> {
+        match self {
+            KeyCode::Char(c) => match c.to_ascii_lowercase() {
+                '1' => Some(Chip8Key::D1),
+                '2' => Some(Chip8Key::D2),
+                '3' => Some(Chip8Key::D3),
+                '4' => Some(Chip8Key::C),
+                'q' => Some(Chip8Key::D4),
+                'w' => Some(Chip8Key::D5),
+                'e' => Some(Chip8Key::D6),
+                'r' => Some(Chip8Key::D),
+                'a' => Some(Chip8Key::D7),
+                's' => Some(Chip8Key::D8),
+                'd' => Some(Chip8Key::D9),
+                'f' => Some(Chip8Key::E),
+                'z' => Some(Chip8Key::A),
+                'x' => Some(Chip8Key::D0),
+                'c' => Some(Chip8Key::B),
+                'v' => Some(Chip8Key::F),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::color::BLACK_ON_WHITE;
+
+    #[test]
+    fn key_layout() {
+        assert_eq!(Some(Chip8Key::D1), KeyCode::Char('1').map_key());
+        assert_eq!(Some(Chip8Key::C), KeyCode::Char('4').map_key());
+        assert_eq!(Some(Chip8Key::D5), KeyCode::Char('W').map_key());
+        assert_eq!(Some(Chip8Key::F), KeyCode::Char('v').map_key());
+        assert_eq!(None, KeyCode::Char('5').map_key());
+        assert_eq!(None, KeyCode::Enter.map_key());
+    }
+
+    #[test]
+    fn half_blocks() {
+        let mut graphics = GraphicsBuffer::new();
+        graphics.draw(0, 0, &[0x80, 0xC0], DrawMode::Clip);
+
+        let frame = String::from_utf8(render(&graphics, &BLACK_ON_WHITE)).unwrap();
+        let black = "0;0;0m";
+        let white = "255;255;255m";
+
+        // The first cell has both pixels on, the second only the bottom one, and the rest are off
+        let first_row = frame.split("\u{1b}[2;1H").next().unwrap();
+        assert!(first_row.starts_with("\u{1b}[1;1H"));
+        assert!(first_row.contains(&format!("\u{1b}[38;2;{}\u{1b}[48;2;{}▀", black, black)));
+        assert!(first_row.contains(&format!("\u{1b}[38;2;{}\u{1b}[48;2;{}▀", white, black)));
+        assert!(first_row.contains(&format!("\u{1b}[38;2;{}\u{1b}[48;2;{}▀", white, white)));
+        assert_eq!(64, first_row.matches('▀').count());
+        assert_eq!(16 * 64, frame.matches('▀').count());
+    }
+
+    #[test]
+    fn held_keys() {
+        let io = TerminalIO::new(BLACK_ON_WHITE);
+        let mut internal = io.internal.lock().unwrap();
+        let press = KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE);
+        let age = |internal: &mut TerminalIOInternal, by: Duration| {
+            internal
+                .held_keys
+                .get_mut(&Chip8Key::D0)
+                .unwrap()
+                .last_reported -= by;
+        };
+
+        internal.handle_key_event(press);
+        internal.release_expired_keys();
+        assert!(internal.keypad.is_pressed(&Chip8Key::D0));
+
+        // A key stays held while the terminal waits to start repeating it
+        age(&mut internal, KEY_HOLD_TIME);
+        internal.release_expired_keys();
+        assert!(internal.keypad.is_pressed(&Chip8Key::D0));
+
+        // Without release events a key lets go once it stops repeating
+        internal.handle_key_event(press);
+        age(&mut internal, KEY_HOLD_TIME);
+        internal.release_expired_keys();
+        assert!(!internal.keypad.is_pressed(&Chip8Key::D0));
+
+        // A key that never repeats lets go after the first repeat would have come
+        internal.handle_key_event(press);
+        age(&mut internal, FIRST_REPEAT_DELAY);
+        internal.release_expired_keys();
+        assert!(!internal.keypad.is_pressed(&Chip8Key::D0));
+
+        // Terminals that report releases never let go on their own
+        internal.reports_releases = true;
+        internal.handle_key_event(press);
+        age(&mut internal, FIRST_REPEAT_DELAY);
+        internal.release_expired_keys();
+        assert!(internal.keypad.is_pressed(&Chip8Key::D0));
+    }
+}
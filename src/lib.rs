@@ -1,8 +1,16 @@
+pub mod analysis;
 pub mod cli;
+pub mod cosmac;
+pub mod decode_cache;
+pub mod font;
 pub mod instruction;
 pub mod io;
 pub mod machine;
 pub mod memory;
+pub mod metadata;
+pub mod monitor;
+pub mod platform;
+pub mod profiler;
 pub mod random;
 pub mod register;
 pub mod settings;
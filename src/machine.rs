@@ -1,15 +1,49 @@
+use crate::decode_cache::DecodeCache;
+use crate::font::FontError;
 use crate::instruction::{Instruction, InstructionError};
 use crate::io::chip8_io;
 use crate::io::input::MapKey;
+use crate::metadata;
 use crate::random;
+use crate::register::Registers;
 use crate::{memory, settings};
 use crate::{register, timer};
+use std::collections::HashMap;
 use std::thread;
 
+mod threaded;
+
+/// Observer called with every instruction just before it is executed
+pub trait StepHook: Send {
+    fn before_step(&mut self, instruction: &Instruction, registers: &Registers, ram: &memory::RAM);
+}
+
+/// Native stand-in for a machine-language routine that programs call with 0NNN
+pub trait NativeRoutine: Send {
+    fn call(&mut self, registers: &mut Registers, ram: &mut memory::RAM);
+}
+
+impl<F: FnMut(&mut Registers, &mut memory::RAM) + Send> NativeRoutine for F {
+    fn call(&mut self, registers: &mut Registers, ram: &mut memory::RAM) {
+        self(registers, ram)
+    }
+}
+
 pub struct Machine<G: chip8_io::Chip8IO, R: random::RandomSource, T: timer::Timer> {
     ram: memory::RAM,
     registers: register::Registers,
     settings: settings::Settings,
+    decode_cache: Option<DecodeCache>,
+    blocks: threaded::BlockCache<G, R, T>,
+    hooks: Vec<Box<dyn StepHook>>,
+    native_routines: HashMap<memory::Address, Box<dyn NativeRoutine>>,
+    // Timer ticks since the machine started
+    frames: u64,
+    // Frames on which the display changed, and the display generation at the end of the last frame
+    changed_frames: u64,
+    shown_generation: u64,
+    // Machine cycles used in the current frame when running at VIP speed
+    frame_cycles: u32,
 
     graphics: G,
     random: R,
@@ -30,15 +64,30 @@ where
     Tmr: timer::Timer,
 {
     pub fn new(
-        graphics: G,
+        mut graphics: G,
         random: R,
         timer: Tmr,
         settings: settings::Settings,
     ) -> Machine<G, R, Tmr> {
+        let ram = memory::RAM::from_settings(&settings);
+        let decode_cache = new_decode_cache(&settings, &ram);
+
+        // Starting in a resolution is not a change the first frame should count
+        graphics.set_resolution(settings.resolution);
+        let shown_generation = graphics.display_generation();
+
         Machine {
-            ram: memory::RAM::new(),
-            registers: register::Registers::new(),
+            ram,
+            registers: register::Registers::from_settings(&settings),
             settings,
+            decode_cache,
+            blocks: threaded::BlockCache::new(),
+            hooks: Vec::new(),
+            native_routines: HashMap::new(),
+            frames: 0,
+            changed_frames: 0,
+            shown_generation,
+            frame_cycles: 0,
             graphics,
             random,
             timer,
@@ -52,43 +101,223 @@ where
         self.ram.load_program(loader)
     }
 
+    /// Replace the built-in font with one loaded at the configured font address
+    pub fn load_font(&mut self, font: &[u8]) -> Result<(), FontError> {
+        self.ram.load_font(font, self.settings.font_address)
+    }
+
+    pub fn add_hook(&mut self, hook: Box<dyn StepHook>) {
+        self.hooks.push(hook);
+    }
+
+    /// Run the routine in place of 0NNN calls to the address when machine calls are native
+    pub fn add_native_routine(
+        &mut self,
+        address: memory::Address,
+        routine: Box<dyn NativeRoutine>,
+    ) {
+        self.native_routines.insert(address, routine);
+    }
+
     pub fn run_program(&mut self) -> RunResult {
-        loop {
-            self.step_program()?;
+        match self.settings.engine {
+            settings::Engine::Interpreter => loop {
+                self.step_program()?;
+            },
+            settings::Engine::Threaded => loop {
+                self.step_block()?;
+            },
+        }
+    }
+
+    /// Run until the timers have ticked the given number of frames since the machine started
+    pub fn run_until_frame(&mut self, frame: u64) -> RunResult {
+        while self.frames < frame {
+            match self.settings.engine {
+                settings::Engine::Interpreter => self.step_program()?,
+                settings::Engine::Threaded => {
+                    self.step_block()?;
+                }
+            }
         }
+
+        Ok(())
     }
 
-    fn step_program(&mut self) -> RunResult {
-        let instruction_bytes = self.ram.get_instruction(self.registers.pc);
-        let instruction = Instruction::from_bytes(instruction_bytes)?;
+    pub fn graphics(&self) -> &G {
+        &self.graphics
+    }
+
+    pub fn step_program(&mut self) -> RunResult {
+        let instruction = self.fetch_instruction()?;
+
+        self.tick(&instruction);
+        self.step(&instruction)
+    }
+
+    // Work done between fetching an instruction and executing it
+    fn tick(&mut self, instruction: &Instruction) {
+        if let settings::ClockSpeed::VipCycles = self.settings.clock_speed {
+            self.spend_vip_cycles(instruction);
+            return;
+        }
 
         if self.timer.should_tick() {
-            self.registers.tick_timers();
+            self.tick_frame();
         }
 
         // TODO use the same functionality as the delay timers to apply real clock speed accounting for execution time
         if let settings::ClockSpeed::Limited { instruction_time } = self.settings.clock_speed {
             thread::sleep(instruction_time);
         }
+    }
 
-        self.step(&instruction);
-        Ok(())
+    fn tick_frame(&mut self) {
+        self.registers.tick_timers();
+        self.frames += 1;
+        self.graphics.end_frame();
+
+        let generation = self.graphics.display_generation();
+        if generation != self.shown_generation {
+            self.changed_frames += 1;
+            self.shown_generation = generation;
+        }
+    }
+
+    /// Number of frames the timers have ticked since the machine started
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Number of frames on which the display changed
+    pub fn changed_frames(&self) -> u64 {
+        self.changed_frames
+    }
+
+    // Run the instruction in the current frame if it has the cycles left, or wait for the next one
+    fn spend_vip_cycles(&mut self, instruction: &Instruction) {
+        let info = instruction.info();
+        let cycles = match instruction {
+            Instruction::DrawXYN {
+                x_register, bytes, ..
+            } => {
+                // Drawing waits for the start of the next frame
+                self.frame_cycles = self.frame_cycles.max(metadata::VIP_FRAME_CYCLES);
+                metadata::vip_draw_cycles(*bytes, self.registers.get_register(*x_register))
+            }
+            Instruction::StoreDecimal { register } => {
+                metadata::vip_decimal_cycles(info, self.registers.get_register(*register))
+            }
+            Instruction::WriteToMemory { max_register }
+            | Instruction::ReadFromMemory { max_register } => {
+                metadata::vip_memory_cycles(info, *max_register)
+            }
+            _ if instruction.flow().is_skip() => {
+                metadata::vip_skip_cycles(info, self.will_skip(instruction))
+            }
+            _ => info.vip_cycles,
+        };
+
+        // Instructions that run past the end of a frame carry the excess into the next one
+        while self.frame_cycles > 0 && self.frame_cycles + cycles > metadata::VIP_FRAME_CYCLES {
+            self.timer.wait_for_tick();
+            self.tick_frame();
+            self.frame_cycles = self.frame_cycles.saturating_sub(metadata::VIP_FRAME_CYCLES);
+        }
+
+        self.frame_cycles += cycles;
+    }
+
+    // Whether a skip instruction is about to skip the next instruction
+    fn will_skip(&mut self, instruction: &Instruction) -> bool {
+        let v = self.registers.v;
+
+        match instruction {
+            Instruction::SkipEqXNN { register, value } => v[*register as usize] == *value,
+            Instruction::SkipNeXNN { register, value } => v[*register as usize] != *value,
+            Instruction::SkipEqXY {
+                register_x,
+                register_y,
+            } => v[*register_x as usize] == v[*register_y as usize],
+            Instruction::SkipNeXY {
+                register_x,
+                register_y,
+            } => v[*register_x as usize] != v[*register_y as usize],
+            // Values that are not keys never skip
+            Instruction::SkipPressedX { register } => {
+                self.register_key_pressed(*register) == Some(true)
+            }
+            Instruction::SkipNotPressedX { register } => {
+                self.register_key_pressed(*register) == Some(false)
+            }
+            _ => false,
+        }
+    }
+
+    fn register_key_pressed(&mut self, register: u8) -> Option<bool> {
+        let key = self.registers.get_register(register).map_key()?;
+
+        Some(self.graphics.key_pressed(key))
+    }
+
+    fn fetch_instruction(&mut self) -> Result<Instruction, InstructionError> {
+        let pc = self.registers.pc;
+
+        if self.decode_cache.is_none() {
+            return Instruction::from_bytes(&self.ram.get_instruction(pc));
+        }
+
+        self.invalidate_written();
+        let cache = self.decode_cache.as_mut().unwrap();
+
+        if let Some(instruction) = cache.get(pc) {
+            return Ok(instruction);
+        }
+
+        let instruction = Instruction::from_bytes(&self.ram.get_instruction(pc))?;
+        cache.insert(pc, instruction);
+
+        Ok(instruction)
+    }
+
+    // Anything written since the last fetch may have replaced cached instructions
+    fn invalidate_written(&mut self) {
+        if let Some(written) = self.ram.take_written() {
+            if let Some(cache) = &mut self.decode_cache {
+                cache.invalidate(written.clone());
+            }
+            self.blocks.invalidate(written);
+        }
     }
 
-    fn step(&mut self, instruction: &Instruction) {
+    fn step(&mut self, instruction: &Instruction) -> RunResult {
+        self.notify_hooks(instruction);
+        self.execute(instruction)
+    }
+
+    fn notify_hooks(&mut self, instruction: &Instruction) {
+        for hook in &mut self.hooks {
+            hook.before_step(instruction, &self.registers, &self.ram);
+        }
+    }
+
+    fn execute(&mut self, instruction: &Instruction) -> RunResult {
         match instruction {
             Instruction::ClearScreen => {
                 self.graphics.clear();
                 self.registers.advance_pc();
             }
             Instruction::Return => {
-                self.registers.stack_return();
+                self.ret()?;
+            }
+            Instruction::Sys { address } => {
+                self.machine_call(instruction, *address)?;
             }
             Instruction::JumpNNN { address } => {
-                self.registers.pc = *address;
+                self.registers.jump(*address);
             }
             Instruction::CallNNN { address } => {
-                self.registers.stack_call(*address);
+                self.call(*address)?;
             }
             Instruction::SkipEqXNN { register, value } => {
                 let register_value = self.registers.get_register(*register);
@@ -191,15 +420,18 @@ where
                 }
             }
             Instruction::StoreNNN { value } => {
-                self.registers.i = *value;
+                self.registers.set_i(*value);
                 self.registers.advance_pc();
             }
             Instruction::JumpV0 { address } => {
                 let offset = self.registers.get_register(0);
-                self.registers.pc = *address + offset as u16;
+                self.registers.jump(address.wrapping_add(offset as u16));
             }
             Instruction::Rand { register, mask } => {
-                let random_number = self.random.gen();
+                let context = random::RandomContext {
+                    frames: self.frames,
+                };
+                let random_number = self.random.gen(Some(&context));
                 let random_number = random_number & mask;
 
                 self.registers.set_register(*register, random_number);
@@ -217,7 +449,7 @@ where
                 let sprite_address = self.registers.i;
                 let sprite = self.ram.get_sprite_at_address(sprite_address, *bytes);
 
-                let flipped = self.graphics.draw(x, y, sprite);
+                let flipped = self.graphics.draw(x, y, &sprite, self.settings.draw_mode);
 
                 self.registers.set_flag(if flipped { 1 } else { 0 });
                 self.registers.advance_pc();
@@ -267,13 +499,14 @@ where
                 self.registers.advance_pc();
             }
             Instruction::AddIX { register } => {
-                self.registers.i += self.registers.get_register(*register) as u16;
+                let offset = self.registers.get_register(*register) as u16;
+                self.registers.set_i(self.registers.i.wrapping_add(offset));
                 self.registers.advance_pc();
             }
             Instruction::StoreSpriteX { register } => {
                 let value = self.registers.get_register(*register);
                 let address = self.ram.get_address_of_sprite(value);
-                self.registers.i = address;
+                self.registers.set_i(address);
 
                 self.registers.advance_pc();
             }
@@ -282,41 +515,91 @@ where
                 let address = self.registers.i;
 
                 let (high, mid, low) = to_decimal_digits(value);
-                let memory = self.ram.address_mut(address);
-
-                memory[0] = high;
-                memory[1] = mid;
-                memory[2] = low;
+                self.ram.write(address, &[high, mid, low]);
 
                 self.registers.advance_pc();
             }
             Instruction::WriteToMemory { max_register } => {
-                let address = &mut self.registers.i;
-                let target_memory = &mut self.ram.address_mut(*address)[0..=*max_register as usize];
+                let address = self.registers.i;
                 let source_registers = &self.registers.v[0..=*max_register as usize];
 
-                target_memory.copy_from_slice(source_registers);
+                self.ram.write(address, source_registers);
 
                 if let settings::MemoryMode::Advance = self.settings.memory_mode {
-                    *address += *max_register as u16 + 1;
+                    self.registers
+                        .set_i(address.wrapping_add(*max_register as u16 + 1));
                 }
 
                 self.registers.advance_pc();
             }
             Instruction::ReadFromMemory { max_register } => {
-                let address = &mut self.registers.i;
-                let source_memory = &self.ram.address(*address)[0..=*max_register as usize];
+                let address = self.registers.i;
+                let source_memory = self.ram.read(address, *max_register as usize + 1);
                 let target_registers = &mut self.registers.v[0..=*max_register as usize];
 
-                target_registers.copy_from_slice(source_memory);
+                target_registers.copy_from_slice(&source_memory);
 
                 if let settings::MemoryMode::Advance = self.settings.memory_mode {
-                    *address += *max_register as u16 + 1;
+                    self.registers
+                        .set_i(address.wrapping_add(*max_register as u16 + 1));
                 }
 
                 self.registers.advance_pc();
             }
         };
+
+        Ok(())
+    }
+
+    fn machine_call(&mut self, instruction: &Instruction, address: memory::Address) -> RunResult {
+        let fault = InstructionError::UnsupportedInstruction(instruction.to_u16());
+
+        match self.settings.machine_call_mode {
+            settings::MachineCallMode::Ignore => {}
+            settings::MachineCallMode::Fault => return Err(fault),
+            settings::MachineCallMode::Native => match self.native_routines.get_mut(&address) {
+                Some(routine) => routine.call(&mut self.registers, &mut self.ram),
+                None => return Err(fault),
+            },
+        }
+
+        self.registers.advance_pc();
+        Ok(())
+    }
+
+    fn call(&mut self, address: memory::Address) -> RunResult {
+        match self.settings.stack_location {
+            settings::StackLocation::Registers => self.registers.stack_call(address),
+            settings::StackLocation::Memory { address: stack } => {
+                // Programs expect the return address in memory, so there is nothing to advance past on return
+                let level = self.registers.push_level()?;
+                let return_address = self.registers.pc.wrapping_add(2);
+
+                self.ram.write(
+                    stack.wrapping_add(2 * level as memory::Address),
+                    &return_address.to_be_bytes(),
+                );
+
+                self.registers.jump(address);
+                Ok(())
+            }
+        }
+    }
+
+    fn ret(&mut self) -> RunResult {
+        match self.settings.stack_location {
+            settings::StackLocation::Registers => self.registers.stack_return(),
+            settings::StackLocation::Memory { address: stack } => {
+                let level = self.registers.pop_level()?;
+                let bytes = self
+                    .ram
+                    .read(stack.wrapping_add(2 * level as memory::Address), 2);
+
+                self.registers
+                    .jump(u16::from_be_bytes([bytes[0], bytes[1]]));
+                Ok(())
+            }
+        }
     }
 
     fn op<T>(&mut self, target: &u8, source: &u8, op: T)
@@ -345,6 +628,13 @@ where
     }
 }
 
+fn new_decode_cache(settings: &settings::Settings, ram: &memory::RAM) -> Option<DecodeCache> {
+    match settings.decode_mode {
+        settings::DecodeMode::Uncached => None,
+        settings::DecodeMode::Cached => Some(DecodeCache::new(ram.size())),
+    }
+}
+
 fn to_decimal_digits(value: u8) -> (u8, u8, u8) {
     let high = (value / 100) % 10;
     let mid = (value / 10) % 10;
@@ -357,7 +647,9 @@ fn to_decimal_digits(value: u8) -> (u8, u8, u8) {
 mod tests {
     use super::*;
     use crate::instruction::Instruction::*;
+    use crate::io::graphics::Resolution;
     use crate::io::headless_io::HeadlessIO;
+    use crate::platform::Platform;
 
     // Convenience constructors for test machines
     impl Machine<HeadlessIO, random::FixedRandomSource, timer::InstructionTimer> {
@@ -373,14 +665,12 @@ mod tests {
             random: random::FixedRandomSource,
             settings: settings::Settings,
         ) -> Machine<HeadlessIO, random::FixedRandomSource, timer::InstructionTimer> {
-            Machine {
-                ram: memory::RAM::new(),
-                registers: register::Registers::new(),
-                graphics: HeadlessIO::new(),
-                timer: timer::InstructionTimer::new(),
+            Machine::new(
+                HeadlessIO::new(),
                 random,
+                timer::InstructionTimer::new(),
                 settings,
-            }
+            )
         }
 
         pub fn test_program_with_gas(
@@ -524,28 +814,32 @@ mod tests {
         assert_eq!(0, machine.registers.get_register(register));
 
         // A first add should work and not overflow
-        machine.step(&AddXNN {
-            register,
-            value: 106,
-        });
+        machine
+            .step(&AddXNN {
+                register,
+                value: 106,
+            })
+            .unwrap();
         assert_eq!(106, machine.registers.get_register(register));
         assert_eq!(expected_flag, machine.registers.get_flag());
 
         // A second add just below the limit should also not overflow
-        machine.step(&Instruction::AddXNN {
-            register,
-            value: 149,
-        });
+        machine
+            .step(&Instruction::AddXNN {
+                register,
+                value: 149,
+            })
+            .unwrap();
         assert_eq!(255, machine.registers.get_register(register));
         assert_eq!(expected_flag, machine.registers.get_flag());
 
         // Add one more and it should overflow
-        machine.step(&AddXNN { register, value: 1 });
+        machine.step(&AddXNN { register, value: 1 }).unwrap();
         assert_eq!(0, machine.registers.get_register(register));
         assert_eq!(expected_flag, machine.registers.get_flag());
 
         // Add one last time and the flag should reset
-        machine.step(&AddXNN { register, value: 3 });
+        machine.step(&AddXNN { register, value: 3 }).unwrap();
         assert_eq!(3, machine.registers.get_register(register));
         assert_eq!(expected_flag, machine.registers.get_flag());
     }
@@ -580,14 +874,18 @@ mod tests {
         assert_eq!(0x0, machine.registers.get_flag());
 
         // Make a few more additions and overflow will occur
-        machine.step(&AddXY {
-            target: 0x0,
-            source: 0x0,
-        });
-        machine.step(&AddXY {
-            target: 0x0,
-            source: 0x0,
-        });
+        machine
+            .step(&AddXY {
+                target: 0x0,
+                source: 0x0,
+            })
+            .unwrap();
+        machine
+            .step(&AddXY {
+                target: 0x0,
+                source: 0x0,
+            })
+            .unwrap();
 
         assert_eq!([16, 45], machine.registers.v[0..2]);
         assert_eq!(0x1, machine.registers.get_flag());
@@ -602,18 +900,22 @@ mod tests {
         machine.registers.set_flag(0xDD);
 
         // Subtraction without wrapping sets the flag to 1
-        machine.step(&SubXY {
-            target: 0x1,
-            source: 0x0,
-        });
+        machine
+            .step(&SubXY {
+                target: 0x1,
+                source: 0x0,
+            })
+            .unwrap();
         assert_eq!([17, 13], machine.registers.v[0..2]);
         assert_eq!(0x1, machine.registers.get_flag());
 
         // Subtraction with wrapping sets the flag to 0
-        machine.step(&SubXY {
-            target: 0x1,
-            source: 0x0,
-        });
+        machine
+            .step(&SubXY {
+                target: 0x1,
+                source: 0x0,
+            })
+            .unwrap();
         assert_eq!([17, 252], machine.registers.v[0..2]);
         assert_eq!(0x0, machine.registers.get_flag());
     }
@@ -673,26 +975,32 @@ mod tests {
 
         machine.registers.set_register(1, 0x2D);
         machine.registers.set_register(2, 0x4B);
-        machine.step(&OrXY {
-            target: 0x1,
-            source: 0x2,
-        });
+        machine
+            .step(&OrXY {
+                target: 0x1,
+                source: 0x2,
+            })
+            .unwrap();
         assert_eq!([0x6F, 0x4B], machine.registers.v[1..3]);
 
         machine.registers.set_register(3, 0x2D);
         machine.registers.set_register(4, 0x4B);
-        machine.step(&AndXY {
-            target: 0x3,
-            source: 0x4,
-        });
+        machine
+            .step(&AndXY {
+                target: 0x3,
+                source: 0x4,
+            })
+            .unwrap();
         assert_eq!([0x09, 0x4B], machine.registers.v[3..5]);
 
         machine.registers.set_register(5, 0x2D);
         machine.registers.set_register(6, 0x4B);
-        machine.step(&XorXY {
-            target: 0x5,
-            source: 0x6,
-        });
+        machine
+            .step(&XorXY {
+                target: 0x5,
+                source: 0x6,
+            })
+            .unwrap();
         assert_eq!([0x66, 0x4B], machine.registers.v[5..7]);
 
         assert_eq!(0xBB, machine.registers.get_flag());
@@ -733,7 +1041,7 @@ mod tests {
             machine.registers.set_flag(0xFF);
             machine.registers.set_register(target, target_value);
             machine.registers.set_register(source, source_value);
-            machine.step(instruction);
+            machine.step(instruction).unwrap();
 
             assert_eq!(expected_output, machine.registers.get_register(target));
             assert_eq!(source_value, machine.registers.get_register(source));
@@ -747,7 +1055,7 @@ mod tests {
 
         assert_eq!(0x0, machine.registers.i);
 
-        machine.step(&StoreNNN { value: 0x409 });
+        machine.step(&StoreNNN { value: 0x409 }).unwrap();
 
         assert_eq!([0u8; 16], machine.registers.v);
         assert_eq!(0x409, machine.registers.i)
@@ -788,6 +1096,37 @@ mod tests {
         assert_eq!(5 * 0xA, machine.registers.i);
     }
 
+    #[test]
+    fn font_at_the_end_of_memory() {
+        let settings = settings::Settings::default().with_font_address(0xFF0);
+        let mut machine =
+            Machine::new_headless_with_settings(random::FixedRandomSource::new(vec![0]), settings);
+
+        // The glyph for 3 straddles the end of memory: 6003 F029 D115
+        machine
+            .test_program_linear(&vec![
+                StoreXNN {
+                    register: 0,
+                    value: 3,
+                },
+                StoreSpriteX { register: 0 },
+                DrawXYN {
+                    x_register: 1,
+                    y_register: 1,
+                    bytes: 5,
+                },
+            ])
+            .unwrap();
+
+        assert_eq!(0xFFF, machine.registers.i);
+        let graphics = &machine.graphics.graphics_buffer;
+        for (y, row) in ["####", "...#", "####", "...#", "####"].iter().enumerate() {
+            for (x, pixel) in row.chars().enumerate() {
+                assert_eq!(Some(pixel == '#'), graphics.get_pixel(x as u8, y as u8));
+            }
+        }
+    }
+
     #[test]
     fn store_decimal() {
         let mut machine = Machine::new_headless();
@@ -863,8 +1202,7 @@ mod tests {
             .settings
             .with_memory_mode(settings::MemoryMode::Advance);
 
-        let memory = machine.ram.address_mut(start_memory);
-        memory[0..5].copy_from_slice(&[1, 3, 5, 7, 9]);
+        machine.ram.write(start_memory, &[1, 3, 5, 7, 9]);
 
         let program = vec![
             StoreNNN {
@@ -1001,6 +1339,466 @@ mod tests {
         assert_eq!(0x20C, machine.registers.pc);
     }
 
+    #[test]
+    fn load_address() {
+        let settings = settings::Settings::for_platform(Platform::Eti660);
+        let mut machine =
+            Machine::new_headless_with_settings(random::FixedRandomSource::new(vec![0]), settings);
+
+        machine
+            .test_program_with_gas(
+                2,
+                &vec![
+                    StoreXNN {
+                        register: 0,
+                        value: 0x12,
+                    },
+                    JumpNNN { address: 0x600 },
+                ],
+            )
+            .unwrap();
+
+        assert_eq!(0x12, machine.registers.v[0]);
+        assert_eq!(0x600, machine.registers.pc);
+        assert_eq!([0x60, 0x12], machine.ram.address(0x600)[..2]);
+    }
+
+    #[test]
+    fn stack_in_memory() {
+        let settings = settings::Settings::for_platform(Platform::CosmacVip);
+        let mut machine =
+            Machine::new_headless_with_settings(random::FixedRandomSource::new(vec![0]), settings);
+
+        machine
+            .test_program_with_gas(
+                2,
+                &vec![
+                    CallNNN { address: 0x204 },
+                    ClearScreen,
+                    CallNNN { address: 0x208 },
+                    ClearScreen,
+                    Return,
+                ],
+            )
+            .unwrap();
+
+        // Return addresses are kept in memory, where programs can read them
+        assert_eq!(2, machine.registers.sp);
+        assert_eq!([0x02, 0x02, 0x02, 0x06], machine.ram.address(0xEA0)[..4]);
+
+        // Rewriting the stack changes where the return goes
+        machine.ram.write(0xEA2, &[0x02, 0x00]);
+        machine.step_program().unwrap();
+        assert_eq!(0x200, machine.registers.pc);
+    }
+
+    #[test]
+    fn vip_cycles() {
+        let settings =
+            settings::Settings::default().with_clock_speed(settings::ClockSpeed::VipCycles);
+        let mut machine =
+            Machine::new_headless_with_settings(random::FixedRandomSource::new(vec![0]), settings);
+        let store = StoreXNN {
+            register: 0,
+            value: 3,
+        };
+
+        // Each frame fits as many instructions as its cycles allow
+        let per_frame = (metadata::VIP_FRAME_CYCLES / store.info().vip_cycles) as usize;
+        machine
+            .test_program_with_gas(per_frame, &vec![store; per_frame + 1])
+            .unwrap();
+        assert_eq!(0, machine.frames);
+
+        machine.step_program().unwrap();
+        assert_eq!(1, machine.frames);
+        assert_eq!(store.info().vip_cycles, machine.frame_cycles);
+
+        // Drawing waits for the next frame and costs more for unaligned sprites
+        machine.load_program(&vec![
+            store,
+            DrawXYN {
+                x_register: 0,
+                y_register: 0,
+                bytes: 5,
+            },
+        ]);
+        machine.registers.pc = 0x200;
+        machine.step_program().unwrap();
+        machine.step_program().unwrap();
+        assert_eq!(2, machine.frames);
+        assert_eq!(metadata::vip_draw_cycles(5, 3), machine.frame_cycles);
+
+        // A draw longer than what is left of the frame carries the excess into the next one
+        machine.load_program(&vec![
+            StoreXNN {
+                register: 0,
+                value: 7,
+            },
+            DrawXYN {
+                x_register: 0,
+                y_register: 0,
+                bytes: 15,
+            },
+            store,
+        ]);
+        machine.registers.pc = 0x200;
+        for _ in 0..3 {
+            machine.step_program().unwrap();
+        }
+        assert_eq!(4, machine.frames);
+        assert_eq!(
+            metadata::vip_draw_cycles(15, 7) - metadata::VIP_FRAME_CYCLES + store.info().vip_cycles,
+            machine.frame_cycles
+        );
+
+        // Skips cost more when they skip, and FX55 more for each register it stores
+        let skip = SkipEqXNN {
+            register: 0,
+            value: 3,
+        };
+        let write = WriteToMemory { max_register: 3 };
+        machine.load_program(&vec![skip, store, write]);
+        machine.registers.pc = 0x200;
+        machine.frame_cycles = 0;
+        for _ in 0..2 {
+            machine.step_program().unwrap();
+        }
+        assert_eq!(
+            metadata::vip_skip_cycles(skip.info(), true)
+                + metadata::vip_memory_cycles(write.info(), 3),
+            machine.frame_cycles
+        );
+    }
+
+    #[test]
+    fn changed_frames() {
+        let mut machine = Machine::new_headless();
+        let store = StoreXNN {
+            register: 0,
+            value: 3,
+        };
+        let draw = DrawXYN {
+            x_register: 0,
+            y_register: 0,
+            bytes: 5,
+        };
+
+        // Frames end every 8 instructions, and only the first and third draw anything
+        let mut program = vec![store; 24];
+        program[1] = draw;
+        program[17] = draw;
+        machine.test_program_linear(&program).unwrap();
+
+        assert_eq!(3, machine.frames);
+        assert_eq!(2, machine.changed_frames());
+
+        // Running to a frame stops as soon as it is reached
+        machine.load_program(&vec![JumpNNN { address: 0x200 }]);
+        machine.registers.pc = 0x200;
+        machine.run_until_frame(5).unwrap();
+        assert_eq!(5, machine.frames);
+        assert_eq!(2, machine.changed_frames());
+    }
+
+    #[test]
+    fn machine_calls() {
+        let program = vec![Sys { address: 0x0AB }, Sys { address: 0x0CD }];
+
+        let mut machine = Machine::new_headless();
+        assert_eq!(
+            Err(InstructionError::UnsupportedInstruction(0x00AB)),
+            machine.test_program_linear(&program)
+        );
+        assert_eq!(0x200, machine.registers.pc);
+
+        let settings =
+            settings::Settings::default().with_machine_call_mode(settings::MachineCallMode::Ignore);
+        let mut machine =
+            Machine::new_headless_with_settings(random::FixedRandomSource::new(vec![0]), settings);
+        machine.test_program_linear(&program).unwrap();
+        assert_eq!(0x204, machine.registers.pc);
+
+        // Only routines that are registered can be called
+        let settings =
+            settings::Settings::default().with_machine_call_mode(settings::MachineCallMode::Native);
+        let mut machine =
+            Machine::new_headless_with_settings(random::FixedRandomSource::new(vec![0]), settings);
+        machine.add_native_routine(
+            0x0AB,
+            Box::new(|registers: &mut Registers, ram: &mut memory::RAM| {
+                registers.v[0] = 0x42;
+                ram.write(0x300, &[0x24]);
+            }),
+        );
+        assert_eq!(
+            Err(InstructionError::UnsupportedInstruction(0x00CD)),
+            machine.test_program_linear(&program)
+        );
+        assert_eq!(0x202, machine.registers.pc);
+        assert_eq!(0x42, machine.registers.v[0]);
+        assert_eq!(0x24, machine.ram.address(0x300)[0]);
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let mut machine = Machine::new_headless();
+
+        machine.load_program(&vec![CallNNN { address: 0x200 }]);
+        for _ in 0..16 {
+            machine.step_program().unwrap();
+        }
+
+        assert_eq!(
+            Err(InstructionError::StackOverflow(0x200)),
+            machine.step_program()
+        );
+    }
+
+    #[test]
+    fn memory_wraps_around() {
+        for size in [
+            settings::MemorySize::Vip,
+            settings::MemorySize::Standard,
+            settings::MemorySize::XoChip,
+        ] {
+            let settings = settings::Settings::default().with_memory_size(size);
+            let mut machine = Machine::new_headless_with_settings(
+                random::FixedRandomSource::new(vec![0]),
+                settings,
+            );
+            let last = (size.bytes() - 1) as memory::Address;
+
+            // Every access starting at the last byte continues at the start of memory
+            machine.registers.i = last;
+            machine
+                .test_program_linear(&vec![
+                    StoreXNN {
+                        register: 0,
+                        value: 128,
+                    },
+                    StoreDecimal { register: 0 },
+                ])
+                .unwrap();
+            assert_eq!([1, 2, 8], *machine.ram.read(last, 3));
+
+            machine.registers.pc = 0x200;
+            machine
+                .test_program_linear(&vec![
+                    StoreXNN {
+                        register: 1,
+                        value: 0x01,
+                    },
+                    StoreXNN {
+                        register: 2,
+                        value: 0xFF,
+                    },
+                    WriteToMemory { max_register: 2 },
+                    DrawXYN {
+                        x_register: 3,
+                        y_register: 3,
+                        bytes: 3,
+                    },
+                    StoreXNN {
+                        register: 0,
+                        value: 0,
+                    },
+                    StoreXNN {
+                        register: 2,
+                        value: 0,
+                    },
+                    ReadFromMemory { max_register: 2 },
+                ])
+                .unwrap();
+            assert_eq!([0x80, 0x01, 0xFF], *machine.ram.read(last, 3));
+            assert_eq!([0x80, 0x01, 0xFF], machine.registers.v[0..3]);
+            assert_eq!(last, machine.registers.i);
+
+            let graphics = &machine.graphics.graphics_buffer;
+            assert_eq!(Some(true), graphics.get_pixel(0, 0));
+            assert_eq!(Some(false), graphics.get_pixel(1, 0));
+            assert_eq!(Some(true), graphics.get_pixel(7, 1));
+            assert!((0..8).all(|x| graphics.get_pixel(x, 2) == Some(true)));
+
+            // An instruction at the last byte is completed by the first, here 01FF
+            machine.ram.write(last, &[0x62]);
+            machine.registers.pc = last;
+            machine.step_program().unwrap();
+            assert_eq!(0x01, machine.registers.v[2]);
+            assert_eq!(0x001, machine.registers.pc);
+        }
+
+        // Jumps wrap to the size of memory, which can leave them at the last byte
+        let settings = settings::Settings::default().with_memory_size(settings::MemorySize::Vip);
+        let mut machine =
+            Machine::new_headless_with_settings(random::FixedRandomSource::new(vec![0]), settings);
+        machine
+            .test_program_linear(&vec![
+                StoreXNN {
+                    register: 0,
+                    value: 0xFF,
+                },
+                JumpV0 { address: 0xF00 },
+            ])
+            .unwrap();
+        assert_eq!(0x7FF, machine.registers.pc);
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        let program = vec![
+            StoreXNN {
+                register: 0,
+                value: 0x62,
+            },
+            StoreXNN {
+                register: 1,
+                value: 0x22,
+            },
+            StoreNNN { value: 0x20C },
+            // Run the routine once so its instruction is decoded
+            CallNNN { address: 0x20C },
+            // Overwrite the routine's first instruction with 6222
+            WriteToMemory { max_register: 1 },
+            CallNNN { address: 0x20C },
+            StoreXNN {
+                register: 2,
+                value: 0x11,
+            },
+            Return,
+        ];
+
+        for decode_mode in [settings::DecodeMode::Uncached, settings::DecodeMode::Cached] {
+            let mut machine = Machine::new_headless_with_settings(
+                random::FixedRandomSource::new(vec![0]),
+                settings::Settings::default().with_decode_mode(decode_mode),
+            );
+
+            machine.test_program_with_gas(6, &program).unwrap();
+            assert_eq!(0x11, machine.registers.get_register(2));
+
+            for _ in 0..4 {
+                machine.step_program().unwrap();
+            }
+            assert_eq!(0x22, machine.registers.get_register(2));
+        }
+    }
+
+    #[test]
+    fn draw_modes() {
+        for (draw_mode, wrapped) in [
+            (settings::DrawMode::Clip, false),
+            (settings::DrawMode::Wrap, true),
+        ] {
+            let settings = settings::Settings::default().with_draw_mode(draw_mode);
+            let mut machine = Machine::new_headless_with_settings(
+                random::FixedRandomSource::new(vec![0]),
+                settings,
+            );
+
+            // Draw the 0 glyph starting at (126, 62), which wraps onto the screen at (62, 30)
+            machine
+                .test_program_linear(&vec![
+                    StoreXNN {
+                        register: 0,
+                        value: 126,
+                    },
+                    StoreXNN {
+                        register: 1,
+                        value: 62,
+                    },
+                    StoreSpriteX { register: 2 },
+                    DrawXYN {
+                        x_register: 0,
+                        y_register: 1,
+                        bytes: 5,
+                    },
+                ])
+                .unwrap();
+
+            let graphics = &machine.graphics.graphics_buffer;
+            assert_eq!(Some(true), graphics.get_pixel(62, 30));
+            assert_eq!(Some(true), graphics.get_pixel(62, 31));
+            assert_eq!(Some(wrapped), graphics.get_pixel(0, 30));
+            assert_eq!(Some(wrapped), graphics.get_pixel(62, 0));
+            assert_eq!(Some(wrapped), graphics.get_pixel(1, 0));
+        }
+    }
+
+    #[test]
+    fn resolutions() {
+        let settings = settings::Settings::default().with_resolution(Resolution::High);
+        let mut machine =
+            Machine::new_headless_with_settings(random::FixedRandomSource::new(vec![0]), settings);
+
+        // The machine starts in the configured resolution without counting it as a change
+        assert_eq!(
+            Resolution::High,
+            machine.graphics.graphics_buffer.resolution()
+        );
+
+        // Sprites can be drawn past the low resolution display
+        machine
+            .test_program_linear(&vec![
+                StoreXNN {
+                    register: 0,
+                    value: 120,
+                },
+                StoreXNN {
+                    register: 1,
+                    value: 60,
+                },
+                StoreSpriteX { register: 2 },
+                DrawXYN {
+                    x_register: 0,
+                    y_register: 1,
+                    bytes: 1,
+                },
+                JumpNNN { address: 0x208 },
+            ])
+            .unwrap();
+        machine.run_until_frame(1).unwrap();
+
+        let graphics = &machine.graphics.graphics_buffer;
+        assert_eq!(Some(true), graphics.get_pixel(123, 60));
+        assert_eq!(Some(false), graphics.get_pixel(124, 60));
+        assert_eq!(1, machine.changed_frames());
+    }
+
+    #[test]
+    fn test_opcode_rom() {
+        // The ROM draws a grid of opcodes with OK beside each one that passes
+        let ok = ["###.#.#", "#.#.##.", "#.#.#.#", "###.#.#"];
+
+        for draw_mode in [settings::DrawMode::Clip, settings::DrawMode::Wrap] {
+            let settings = settings::Settings::default().with_draw_mode(draw_mode);
+            let mut machine = Machine::new_headless_with_settings(
+                random::FixedRandomSource::new(vec![0]),
+                settings,
+            );
+            machine
+                .load_program(std::fs::File::open("test_roms/test_opcode.ch8").unwrap())
+                .unwrap();
+
+            for _ in 0..2_000 {
+                machine.step_program().unwrap();
+            }
+
+            let graphics = &machine.graphics.graphics_buffer;
+            for cell_x in [10, 32, 52] {
+                for cell_y in (1..30).step_by(5) {
+                    for (row, expected) in ok.iter().enumerate() {
+                        let pixels: String = (0..7)
+                            .map(|x| graphics.get_pixel(cell_x + x, cell_y + row as u8).unwrap())
+                            .map(|pixel| if pixel { '#' } else { '.' })
+                            .collect();
+                        assert_eq!(*expected, pixels, "cell at ({}, {})", cell_x, cell_y);
+                    }
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_to_decimal_digits() {
         assert_eq!((0, 0, 0), to_decimal_digits(0));
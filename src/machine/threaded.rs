@@ -0,0 +1,352 @@
+use super::{Machine, RunResult};
+use crate::instruction::{Instruction, InstructionError};
+use crate::io::chip8_io;
+use crate::memory::Address;
+use crate::metadata::Flow;
+use crate::{random, timer};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+// Longest run of instructions translated into a single block
+const MAX_BLOCK_LENGTH: usize = 64;
+
+type Op<G, R, T> = Box<dyn Fn(&mut Machine<G, R, T>) -> RunResult + Send + Sync>;
+
+/// A straight-line run of instructions translated into operations on the machine
+/// Only the last instruction of a block may branch, skip or write memory
+struct Block<G: chip8_io::Chip8IO, R: random::RandomSource, T: timer::Timer> {
+    // Addresses of the instruction bytes the block was translated from
+    source: Range<usize>,
+    instructions: Vec<Instruction>,
+    ops: Vec<Op<G, R, T>>,
+}
+
+pub struct BlockCache<G: chip8_io::Chip8IO, R: random::RandomSource, T: timer::Timer> {
+    blocks: HashMap<Address, Arc<Block<G, R, T>>>,
+}
+
+impl<G, R, T> BlockCache<G, R, T>
+where
+    G: chip8_io::Chip8IO,
+    R: random::RandomSource,
+    T: timer::Timer,
+{
+    pub fn new() -> Self {
+        BlockCache {
+            blocks: HashMap::new(),
+        }
+    }
+
+    /// Forget every block translated from any of the written bytes
+    pub fn invalidate(&mut self, written: Range<usize>) {
+        if self.blocks.is_empty() {
+            return;
+        }
+
+        self.blocks.retain(|_, block| {
+            block.source.end <= written.start || written.end <= block.source.start
+        });
+    }
+}
+
+impl<G, R, Tmr> Machine<G, R, Tmr>
+where
+    G: chip8_io::Chip8IO,
+    R: random::RandomSource,
+    Tmr: timer::Timer,
+{
+    /// Run the block starting at PC, translating it first if needed
+    /// Stops early once a frame ends, like stepping one instruction at a time would
+    /// Returns how many instructions were executed
+    pub fn step_block(&mut self) -> Result<usize, InstructionError> {
+        self.invalidate_written();
+
+        let pc = self.registers.pc;
+        let block = match self.blocks.blocks.get(&pc) {
+            Some(block) => Arc::clone(block),
+            None => {
+                let block = Arc::new(self.translate_block(pc)?);
+                self.blocks.blocks.insert(pc, Arc::clone(&block));
+                block
+            }
+        };
+
+        let frame = self.frames;
+        let mut executed = 0;
+        for (instruction, op) in block.instructions.iter().zip(&block.ops) {
+            self.tick(instruction);
+            self.notify_hooks(instruction);
+            op(self)?;
+            executed += 1;
+
+            // Each op leaves PC at the next instruction, so the rest of the block can start from there
+            if self.frames != frame {
+                break;
+            }
+        }
+
+        Ok(executed)
+    }
+
+    fn translate_block(&self, start: Address) -> Result<Block<G, R, Tmr>, InstructionError> {
+        let mut instructions = Vec::new();
+        let mut ops = Vec::new();
+        let mut address = start as usize;
+
+        while ops.len() < MAX_BLOCK_LENGTH && address + 2 <= self.ram.size() {
+            let instruction =
+                match Instruction::from_bytes(&self.ram.get_instruction(address as Address)) {
+                    Ok(instruction) => instruction,
+                    // Stop the block before an invalid instruction so the error surfaces when it is reached
+                    Err(e) if ops.is_empty() => return Err(e),
+                    Err(_) => break,
+                };
+
+            instructions.push(instruction);
+            ops.push(translate(instruction));
+            address += 2;
+
+            if ends_block(&instruction) {
+                break;
+            }
+        }
+
+        Ok(Block {
+            source: start as usize..address,
+            instructions,
+            ops,
+        })
+    }
+}
+
+fn ends_block(instruction: &Instruction) -> bool {
+    instruction.writes().memory
+        || instruction.flow() != Flow::Next
+        || matches!(instruction, Instruction::StorePressX { .. })
+}
+
+// Common register operations are specialised, everything else goes through the interpreter
+fn translate<G, R, T>(instruction: Instruction) -> Op<G, R, T>
+where
+    G: chip8_io::Chip8IO,
+    R: random::RandomSource,
+    T: timer::Timer,
+{
+    match instruction {
+        Instruction::StoreXNN { register, value } => {
+            let register = register as usize;
+            Box::new(move |m| {
+                m.registers.v[register] = value;
+                m.registers.advance_pc();
+                Ok(())
+            })
+        }
+        Instruction::AddXNN { register, value } => {
+            let register = register as usize;
+            Box::new(move |m| {
+                m.registers.v[register] = m.registers.v[register].wrapping_add(value);
+                m.registers.advance_pc();
+                Ok(())
+            })
+        }
+        Instruction::StoreXY { target, source } => {
+            let (target, source) = (target as usize, source as usize);
+            Box::new(move |m| {
+                m.registers.v[target] = m.registers.v[source];
+                m.registers.advance_pc();
+                Ok(())
+            })
+        }
+        Instruction::OrXY { target, source } => logic(target, source, |t, s| t | s),
+        Instruction::AndXY { target, source } => logic(target, source, |t, s| t & s),
+        Instruction::XorXY { target, source } => logic(target, source, |t, s| t ^ s),
+        Instruction::AddXY { target, source } => {
+            let (target, source) = (target as usize, source as usize);
+            Box::new(move |m| {
+                let (value, carry) = m.registers.v[target].overflowing_add(m.registers.v[source]);
+                m.registers.v[target] = value;
+                m.registers.set_flag(carry as u8);
+                m.registers.advance_pc();
+                Ok(())
+            })
+        }
+        Instruction::SubXY { target, source } => {
+            let (target, source) = (target as usize, source as usize);
+            Box::new(move |m| {
+                let (value, borrow) = m.registers.v[target].overflowing_sub(m.registers.v[source]);
+                m.registers.v[target] = value;
+                m.registers.set_flag(!borrow as u8);
+                m.registers.advance_pc();
+                Ok(())
+            })
+        }
+        Instruction::StoreNNN { value } => Box::new(move |m| {
+            m.registers.set_i(value);
+            m.registers.advance_pc();
+            Ok(())
+        }),
+        _ => Box::new(move |m| m.execute(&instruction)),
+    }
+}
+
+fn logic<G, R, T>(target: u8, source: u8, op: fn(u8, u8) -> u8) -> Op<G, R, T>
+where
+    G: chip8_io::Chip8IO,
+    R: random::RandomSource,
+    T: timer::Timer,
+{
+    let (target, source) = (target as usize, source as usize);
+
+    Box::new(move |m| {
+        m.registers.v[target] = op(m.registers.v[target], m.registers.v[source]);
+        m.registers.advance_pc();
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction::*;
+    use crate::io::headless_io::HeadlessIO;
+    use crate::settings;
+    use std::fs;
+
+    type TestMachine = Machine<HeadlessIO, random::FixedRandomSource, timer::InstructionTimer>;
+
+    fn new_machine(engine: settings::Engine) -> TestMachine {
+        Machine::new_headless_with_settings(
+            random::FixedRandomSource::new(vec![0x12, 0xF0, 0x0F, 0x99]),
+            settings::Settings::default().with_engine(engine),
+        )
+    }
+
+    fn assert_same_state(reference: &TestMachine, threaded: &TestMachine) {
+        assert_eq!(reference.registers.v, threaded.registers.v);
+        assert_eq!(reference.registers.i, threaded.registers.i);
+        assert_eq!(reference.registers.pc, threaded.registers.pc);
+        assert_eq!(reference.registers.sp, threaded.registers.sp);
+        assert_eq!(reference.registers.stack, threaded.registers.stack);
+        assert_eq!(reference.registers.dt, threaded.registers.dt);
+        assert_eq!(reference.registers.st, threaded.registers.st);
+        assert_eq!(reference.ram.address(0), threaded.ram.address(0));
+
+        let reference_display = &reference.graphics.graphics_buffer;
+        let threaded_display = &threaded.graphics.graphics_buffer;
+        for y in 0..reference_display.height() {
+            for x in 0..reference_display.width() {
+                assert_eq!(
+                    reference_display.get_pixel(x as u8, y as u8),
+                    threaded_display.get_pixel(x as u8, y as u8)
+                );
+            }
+        }
+    }
+
+    // Run both engines over the same program and compare them after every block
+    fn differential(load: impl Fn(&mut TestMachine), instructions: usize) {
+        let mut reference = new_machine(settings::Engine::Interpreter);
+        let mut threaded = new_machine(settings::Engine::Threaded);
+        load(&mut reference);
+        load(&mut threaded);
+
+        let mut executed = 0;
+        while executed < instructions {
+            let block_length = threaded.step_block().unwrap();
+            for _ in 0..block_length {
+                reference.step_program().unwrap();
+            }
+
+            executed += block_length;
+            assert_same_state(&reference, &threaded);
+        }
+    }
+
+    #[test]
+    fn differential_test_roms() {
+        for rom in ["test_roms/test_opcode.ch8", "test_roms/c8_test.c8"] {
+            differential(
+                |machine| {
+                    machine.load_program(fs::File::open(rom).unwrap()).unwrap();
+                },
+                20_000,
+            );
+        }
+    }
+
+    #[test]
+    fn frames_end_at_the_same_instruction() {
+        let mut reference = new_machine(settings::Engine::Interpreter);
+        let mut threaded = new_machine(settings::Engine::Threaded);
+        for machine in [&mut reference, &mut threaded] {
+            let rom = fs::File::open("test_roms/test_opcode.ch8").unwrap();
+            machine.load_program(rom).unwrap();
+        }
+
+        for frame in 1..500 {
+            reference.run_until_frame(frame).unwrap();
+            threaded.run_until_frame(frame).unwrap();
+            assert_same_state(&reference, &threaded);
+        }
+    }
+
+    #[test]
+    fn differential_self_modifying() {
+        // A loop that rewrites the instruction after its own store every time round
+        let program = vec![
+            StoreNNN { value: 0x20A },
+            StoreXNN {
+                register: 0,
+                value: 0x73,
+            },
+            AddXNN {
+                register: 2,
+                value: 1,
+            },
+            StoreXY {
+                target: 1,
+                source: 2,
+            },
+            WriteToMemory { max_register: 1 },
+            // Rewritten into 73NN with an increasing NN
+            AddXNN {
+                register: 3,
+                value: 0,
+            },
+            JumpNNN { address: 0x202 },
+        ];
+
+        differential(|machine| machine.load_program(&program), 1_000);
+    }
+
+    #[test]
+    fn invalid_instruction() {
+        let mut machine = new_machine(settings::Engine::Threaded);
+        machine.load_program(&[0x00u8, 0xE0, 0xF1, 0x31] as &[u8]);
+
+        // The block stops short of the invalid instruction that follows
+        assert_eq!(Ok(1), machine.step_block());
+        assert_eq!(
+            Err(InstructionError::UnsupportedInstruction(0xF131)),
+            machine.step_block()
+        );
+        assert_eq!(0x202, machine.registers.pc);
+    }
+
+    #[test]
+    fn invalidate_overlapping_blocks() {
+        let mut machine = new_machine(settings::Engine::Threaded);
+        machine.load_program(&vec![ClearScreen, JumpNNN { address: 0x200 }]);
+
+        assert_eq!(Ok(2), machine.step_block());
+        assert_eq!(1, machine.blocks.blocks.len());
+
+        // Writes next to the block leave it alone
+        machine.blocks.invalidate(0x204..0x210);
+        assert_eq!(1, machine.blocks.blocks.len());
+
+        machine.blocks.invalidate(0x203..0x204);
+        assert!(machine.blocks.blocks.is_empty());
+    }
+}
@@ -1,44 +1,540 @@
 use clap::Parser;
-use crust_8::io::piston_io;
+use crust_8::analysis::cfg::ControlFlowGraph;
+use crust_8::analysis::lint;
+use crust_8::cosmac::vip::Vip;
+use crust_8::io::chip8_io::Chip8IO;
+use crust_8::io::color::ColorScheme;
+use crust_8::io::graphics::GraphicsBuffer;
+use crust_8::io::headless_io::HeadlessIO;
+use crust_8::io::recorder::Recorder;
+use crust_8::io::terminal_io::TerminalIO;
+use crust_8::io::{piston_io, screenshot};
+use crust_8::monitor::StateMonitor;
+use crust_8::profiler::Profiler;
 use crust_8::{cli, machine, random, settings, timer};
 use std::error;
-use std::sync::mpsc;
-use std::{thread, time};
+use std::io::{Read, Write};
+use std::sync::{mpsc, Arc, Mutex};
+use std::{fs, io, thread, time};
 
 fn main() -> Result<(), Box<dyn error::Error>> {
     let cli = cli::Cli::parse();
 
-    // Create two handles to the graphics implementation
-    let window_io = piston_io::PistonIO::new(cli.color_scheme.into());
-    let machine_io = window_io.clone();
+    match cli.command {
+        Some(cli::Command::Cfg(args)) => write_cfg(args),
+        Some(cli::Command::Lint(args)) => write_lint(args),
+        None => run(cli),
+    }
+}
 
-    let settings = settings::Settings::default().with_clock_speed(settings::ClockSpeed::Limited {
-        instruction_time: time::Duration::from_millis(2),
-    });
+// A machine as configured from the command line
+type CliMachine<G, T = timer::WallTimer> =
+    machine::Machine<G, Box<dyn random::RandomSource + Send>, T>;
 
-    let mut machine = machine::Machine::new(
-        machine_io,
-        random::ThreadRandomSource,
-        timer::WallTimer::new(),
-        settings,
-    );
+// Headless runs count frames in instructions so the same ROM and seed always give the same frames
+type HeadlessMachine = CliMachine<HeadlessIO, timer::InstructionTimer>;
+type HeadlessVip = Vip<HeadlessIO, timer::InstructionTimer>;
+
+// The user interface, which runs on the main thread while the machine runs on another
+trait Frontend: Chip8IO + Clone + Send + 'static {
+    fn open<F: FnOnce()>(self, on_ready: F) -> Result<(), Box<dyn error::Error>>;
+}
+
+impl Frontend for piston_io::PistonIO {
+    fn open<F: FnOnce()>(self, on_ready: F) -> Result<(), Box<dyn error::Error>> {
+        self.open_window(on_ready);
+        Ok(())
+    }
+}
+
+impl Frontend for TerminalIO {
+    fn open<F: FnOnce()>(self, on_ready: F) -> Result<(), Box<dyn error::Error>> {
+        Ok(self.open_terminal(on_ready)?)
+    }
+}
+
+fn run(mut cli: cli::Cli) -> Result<(), Box<dyn error::Error>> {
+    // Clap requires a ROM whenever no subcommand is given
+    let rom = cli.rom.take().unwrap();
+    let settings = settings_from_cli(&cli);
+    let palettes = cli.palettes()?;
+    let color_scheme = cli.color_scheme(&palettes)?;
+
+    // The VIP backend runs the interpreter's machine code, so there are no CHIP-8 instructions to count
+    if cli.profile.is_some() && matches!(cli.backend, cli::BackendName::Vip) {
+        return Err("--profile needs the chip8 backend".into());
+    }
+
+    if cli.headless {
+        return run_headless(&cli, &color_scheme, settings, rom);
+    }
 
-    machine.load_program(cli.rom)?;
+    match cli.frontend {
+        cli::FrontendName::Window => {
+            let monitor = StateMonitor::new();
+            let window_io = piston_io::PistonIO::new(color_scheme, cli.persistence_mode())
+                .with_palettes(palettes.schemes())
+                .with_monitor(monitor.clone())
+                .with_window_options(cli.window_options());
+            run_frontend(cli, settings, rom, window_io, Some(monitor))
+        }
+        cli::FrontendName::Terminal => {
+            let terminal_io = TerminalIO::new(color_scheme).with_palettes(palettes.schemes());
+            run_frontend(cli, settings, rom, terminal_io, None)
+        }
+    }
+}
+
+fn run_frontend<G: Frontend>(
+    cli: cli::Cli,
+    settings: settings::Settings,
+    rom: fs::File,
+    frontend: G,
+    monitor: Option<StateMonitor>,
+) -> Result<(), Box<dyn error::Error>> {
+    // Create two handles to the graphics implementation
+    let machine_io = frontend.clone();
 
     let (tx, rx) = mpsc::channel();
-    thread::spawn(move || {
-        // Wait to get the ready message from the UI thread
-        rx.recv().unwrap();
+    // The frontend may own the screen, so the machine's result is printed once it closes
+    let (result_tx, result_rx) = mpsc::channel();
+    let profiler = Arc::new(Mutex::new(Profiler::new()));
 
-        let completion_message = match machine.run_program() {
-            Ok(()) => String::from("Machine completed successfully"),
-            Err(e) => format!("Machine completed exceptionally: {:?}", e),
-        };
+    match cli.backend {
+        cli::BackendName::Chip8 => {
+            let stack_location = settings.stack_location;
+            let mut machine =
+                new_machine(&cli, machine_io, timer::WallTimer::new(), settings, rom)?;
+
+            if let Some(monitor) = monitor {
+                machine.add_hook(Box::new(monitor.hook(stack_location)));
+            }
+
+            if cli.profile.is_some() {
+                machine.add_hook(Box::new(Arc::clone(&profiler)));
+            }
+
+            thread::spawn(move || {
+                // Wait to get the ready message from the UI thread
+                rx.recv().unwrap();
+
+                let completion_message = match machine.run_program() {
+                    Ok(()) => String::from("Machine completed successfully"),
+                    Err(e) => format!("Machine completed exceptionally: {:?}", e),
+                };
+                // Nobody is left to print it if the frontend has already closed
+                let _ = result_tx.send(completion_message);
+            });
+        }
+        cli::BackendName::Vip => {
+            let mut vip = new_vip(&cli, machine_io, timer::WallTimer::new(), settings, rom)?;
+
+            thread::spawn(move || {
+                rx.recv().unwrap();
+                vip.run();
+            });
+        }
+    }
+
+    // Open the frontend and post a ready message
+    frontend.open(|| tx.send(()).unwrap())?;
+
+    if let Ok(completion_message) = result_rx.try_recv() {
         println!("{}", completion_message);
+    }
+
+    let profiler = profiler.lock().unwrap();
+    write_profile(&cli, &profiler)
+}
+
+fn write_profile(cli: &cli::Cli, profiler: &Profiler) -> Result<(), Box<dyn error::Error>> {
+    if let Some(format) = &cli.profile {
+        let format = format.clone().into();
+
+        match &cli.profile_output {
+            Some(path) => profiler.write_report(format, &mut fs::File::create(path)?)?,
+            None => profiler.write_report(format, &mut io::stdout())?,
+        }
+    }
+
+    Ok(())
+}
+
+// What a headless run needs from either backend
+trait Headless {
+    fn run_frame(&mut self) -> Result<(), Box<dyn error::Error>>;
+
+    fn run_forever(&mut self) -> Result<(), Box<dyn error::Error>>;
+
+    fn display(&self) -> &GraphicsBuffer;
+}
+
+impl Headless for HeadlessMachine {
+    fn run_frame(&mut self) -> Result<(), Box<dyn error::Error>> {
+        Ok(self.run_until_frame(self.frames() + 1)?)
+    }
+
+    fn run_forever(&mut self) -> Result<(), Box<dyn error::Error>> {
+        Ok(self.run_program()?)
+    }
+
+    fn display(&self) -> &GraphicsBuffer {
+        &self.graphics().graphics_buffer
+    }
+}
+
+impl Headless for HeadlessVip {
+    fn run_frame(&mut self) -> Result<(), Box<dyn error::Error>> {
+        Vip::run_frame(self);
+        Ok(())
+    }
+
+    fn run_forever(&mut self) -> Result<(), Box<dyn error::Error>> {
+        self.run();
+        Ok(())
+    }
+
+    fn display(&self) -> &GraphicsBuffer {
+        &self.graphics().graphics_buffer
+    }
+}
+
+fn run_headless(
+    cli: &cli::Cli,
+    color_scheme: &ColorScheme,
+    settings: settings::Settings,
+    rom: fs::File,
+) -> Result<(), Box<dyn error::Error>> {
+    match cli.backend {
+        cli::BackendName::Chip8 => {
+            let profiler = Arc::new(Mutex::new(Profiler::new()));
+            let machine = new_headless_machine(cli, settings, rom, &profiler)?;
+
+            // The report covers whatever ran, even when the program fails
+            let result = run_headless_frames(cli, color_scheme, machine);
+            write_profile(cli, &profiler.lock().unwrap())?;
+            result
+        }
+        cli::BackendName::Vip => run_headless_frames(
+            cli,
+            color_scheme,
+            new_vip(
+                cli,
+                HeadlessIO::new(),
+                timer::InstructionTimer::new(),
+                settings,
+                rom,
+            )?,
+        ),
+    }
+}
+
+fn new_headless_machine(
+    cli: &cli::Cli,
+    settings: settings::Settings,
+    rom: fs::File,
+    profiler: &Arc<Mutex<Profiler>>,
+) -> Result<HeadlessMachine, Box<dyn error::Error>> {
+    // Only VIP timing limits a headless run, which is otherwise as fast as it can go
+    let settings = match settings.clock_speed {
+        settings::ClockSpeed::VipCycles => settings,
+        _ => settings.with_clock_speed(settings::ClockSpeed::Unlimited),
+    };
+
+    let mut machine = new_machine(
+        cli,
+        HeadlessIO::new(),
+        timer::InstructionTimer::new(),
+        settings,
+        rom,
+    )?;
+    if cli.profile.is_some() {
+        machine.add_hook(Box::new(Arc::clone(profiler)));
+    }
+
+    Ok(machine)
+}
+
+// Run until the screenshot and the recording are done, or forever if there are neither
+fn run_headless_frames<H: Headless>(
+    cli: &cli::Cli,
+    color_scheme: &ColorScheme,
+    mut backend: H,
+) -> Result<(), Box<dyn error::Error>> {
+    let record_frames = cli.record.as_ref().and(cli.record_frames).unwrap_or(0);
+    let last_frame = match (cli.screenshot_at_frame, record_frames) {
+        (None, 0) => return backend.run_forever(),
+        (screenshot_frame, record_frames) => screenshot_frame.unwrap_or(0).max(record_frames),
+    };
+
+    let mut recorder = Recorder::new();
+    for frame in 0..=last_frame {
+        if frame > 0 {
+            backend.run_frame()?;
+        }
+
+        if cli.screenshot_at_frame == Some(frame) {
+            save_screenshot(cli, color_scheme, backend.display())?;
+        }
+        // Record the frames as presented at the end of each of the first frames
+        if frame > 0 && frame <= record_frames {
+            recorder.capture(backend.display());
+        }
+    }
+
+    if let Some(path) = &cli.record {
+        recorder.save_gif(color_scheme, cli.record_scale, path)?;
+        println!(
+            "Saved {} frames of recording to {}",
+            recorder.frames(),
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+fn save_screenshot(
+    cli: &cli::Cli,
+    color_scheme: &ColorScheme,
+    graphics: &GraphicsBuffer,
+) -> Result<(), Box<dyn error::Error>> {
+    screenshot::save_image(
+        graphics,
+        color_scheme,
+        cli.screenshot_scale,
+        &cli.screenshot_output,
+    )?;
+    println!("Saved screenshot to {}", cli.screenshot_output.display());
+
+    Ok(())
+}
+
+fn settings_from_cli(cli: &cli::Cli) -> settings::Settings {
+    let settings = match &cli.platform {
+        Some(platform) => settings::Settings::for_platform(platform.clone().into()),
+        None => settings::Settings::default(),
+    };
+    let mut settings = settings.with_clock_speed(if cli.vip_timing {
+        settings::ClockSpeed::VipCycles
+    } else {
+        settings::ClockSpeed::Limited {
+            instruction_time: time::Duration::from_millis(2),
+        }
     });
+    if let Some(load_address) = cli.load_address {
+        settings = settings.with_program_start(load_address);
+    }
+    if let Some(font) = &cli.font {
+        settings = settings.with_font(font.clone().into());
+    }
+    if let Some(font_address) = cli.font_address {
+        settings = settings.with_font_address(font_address);
+    }
+    if let Some(resolution) = &cli.resolution {
+        settings = settings.with_resolution(resolution.clone().into());
+    }
+    settings = settings.with_engine(cli.engine.clone().into());
+    if cli.decode_cache {
+        settings = settings.with_decode_mode(settings::DecodeMode::Cached);
+    }
+    if let Some(machine_calls) = &cli.machine_calls {
+        settings = settings.with_machine_call_mode(machine_calls.clone().into());
+    }
+
+    settings
+}
+
+fn new_machine<G: Chip8IO, T: timer::Timer>(
+    cli: &cli::Cli,
+    graphics: G,
+    timer: T,
+    settings: settings::Settings,
+    rom: fs::File,
+) -> Result<CliMachine<G, T>, Box<dyn error::Error>> {
+    let random: Box<dyn random::RandomSource + Send> = match cli.random {
+        cli::RandomName::Seeded => Box::new(random::SeededRandomSource::new(seed(cli.seed))),
+        cli::RandomName::Vip => {
+            // Clap requires an interpreter for the VIP generator
+            let interpreter = fs::read(cli.vip_interpreter.as_ref().unwrap())?;
+            let mut random = random::VipRandomSource::from_interpreter(&interpreter)
+                .ok_or("the VIP interpreter must cover its code page at 0x100-0x1FF")?;
+            random.restore(seed(cli.seed) as u16);
+            Box::new(random)
+        }
+    };
+
+    let mut machine = machine::Machine::new(graphics, random, timer, settings);
+
+    if let Some(path) = &cli.font_file {
+        machine.load_font(&fs::read(path)?)?;
+    }
+    machine.load_program(rom)?;
+
+    Ok(machine)
+}
+
+fn new_vip<G: Chip8IO, T: timer::Timer>(
+    cli: &cli::Cli,
+    graphics: G,
+    timer: T,
+    settings: settings::Settings,
+    rom: fs::File,
+) -> Result<Vip<G, T>, Box<dyn error::Error>> {
+    let mut vip = Vip::new(graphics, timer, settings.memory_size);
+
+    if let Some(path) = &cli.vip_monitor {
+        vip.load_monitor(&fs::read(path)?)?;
+    }
+
+    // Without an interpreter the ROM is a machine-language program that starts at 0x000
+    let mut program = Vec::new();
+    (&rom).read_to_end(&mut program)?;
+    match &cli.vip_interpreter {
+        Some(path) => {
+            vip.load(0x000, &fs::read(path)?)?;
+            vip.load(settings.program_start, &program)?;
+        }
+        None => vip.load(cli.load_address.unwrap_or(0x000), &program)?,
+    }
+
+    Ok(vip)
+}
+
+fn seed(seed: Option<u64>) -> u64 {
+    match seed {
+        Some(seed) => seed,
+        None => {
+            // Print the seed so the run can be reproduced
+            let seed = rand::random();
+            println!("Random seed: {}", seed);
+            seed
+        }
+    }
+}
+
+fn write_cfg(mut args: cli::CfgArgs) -> Result<(), Box<dyn error::Error>> {
+    let mut rom = Vec::new();
+    args.rom.read_to_end(&mut rom)?;
 
-    // Open the window and post a ready message
-    window_io.open_window(|| tx.send(()).unwrap());
+    let graph = ControlFlowGraph::from_rom(&rom, args.load_address);
+
+    let mut output: Box<dyn Write> = match args.output {
+        Some(path) => Box::new(fs::File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+
+    if args.per_subroutine {
+        graph.write_dot_per_subroutine(&mut output)?;
+    } else {
+        graph.write_dot(&mut output)?;
+    }
+
+    Ok(())
+}
+
+fn write_lint(mut args: cli::LintArgs) -> Result<(), Box<dyn error::Error>> {
+    let mut rom = Vec::new();
+    args.rom.read_to_end(&mut rom)?;
+
+    let report = lint::lint(&rom, args.load_address);
+    println!("{}", report);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crust_8::io::color;
+    use std::path::{Path, PathBuf};
+
+    // Draw the 0 glyph at random positions as fast as possible
+    fn random_sprites_rom(name: &str) -> PathBuf {
+        let rom = [0xC0, 0x3F, 0xC1, 0x1F, 0xA0, 0x00, 0xD0, 0x15, 0x12, 0x00];
+        let path =
+            std::env::temp_dir().join(format!("crust_8_{}_{}.ch8", std::process::id(), name));
+        fs::write(&path, rom).unwrap();
+        path
+    }
+
+    fn headless_cli(args: &[&str], rom: &Path) -> cli::Cli {
+        let mut all_args = vec!["crust_8", "--headless", "--seed", "7"];
+        all_args.extend(args);
+        all_args.push(rom.to_str().unwrap());
+
+        cli::Cli::parse_from(all_args)
+    }
+
+    #[test]
+    fn headless_runs_are_deterministic() {
+        let path = random_sprites_rom("run");
+
+        let run = |args: &[&str]| {
+            let mut cli = headless_cli(args, &path);
+            let rom = cli.rom.take().unwrap();
+            let profiler = Arc::new(Mutex::new(Profiler::new()));
+            let mut machine =
+                new_headless_machine(&cli, settings_from_cli(&cli), rom, &profiler).unwrap();
+            for _ in 0..30 {
+                machine.run_frame().unwrap();
+            }
+
+            let display = machine.display();
+            (0..display.height() as u8)
+                .flat_map(|y| (0..display.width() as u8).map(move |x| display.get_pixel(x, y)))
+                .collect::<Vec<_>>()
+        };
+
+        let first = run(&[]);
+        assert_eq!(first, run(&[]));
+        assert!(first.contains(&Some(true)));
+
+        // The threaded engine and the decode cache don't change what is drawn
+        assert_eq!(first, run(&["--engine", "threaded", "--decode-cache"]));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn headless_recordings_are_deterministic() {
+        let path = random_sprites_rom("record");
+        let gif = path.with_extension("gif");
+
+        let record = || {
+            let args = ["--record", gif.to_str().unwrap(), "--record-frames", "20"];
+            let mut cli = headless_cli(&args, &path);
+            let rom = cli.rom.take().unwrap();
+            let settings = settings_from_cli(&cli);
+            run_headless(&cli, &color::BLACK_ON_WHITE, settings, rom).unwrap();
+            fs::read(&gif).unwrap()
+        };
+
+        assert_eq!(record(), record());
+        fs::remove_file(path).unwrap();
+        fs::remove_file(gif).unwrap();
+    }
+
+    #[test]
+    fn headless_profiles_are_written_when_the_program_fails() {
+        let path = std::env::temp_dir().join(format!("crust_8_{}_profile.ch8", std::process::id()));
+        fs::write(&path, [0x60, 0x01, 0x12, 0x04, 0xF1, 0x31]).unwrap();
+        let report = path.with_extension("csv");
+
+        let args = [
+            "--profile",
+            "csv",
+            "--profile-output",
+            report.to_str().unwrap(),
+        ];
+        let mut cli = headless_cli(&args, &path);
+        let rom = cli.rom.take().unwrap();
+        let settings = settings_from_cli(&cli);
+        assert!(run_headless(&cli, &color::BLACK_ON_WHITE, settings, rom).is_err());
+
+        let report_text = fs::read_to_string(&report).unwrap();
+        assert!(report_text.contains("address,0x200,\"LD V0, 0x01\",1"));
+        assert!(report_text.contains("address,0x202,\"JP 0x204\",1"));
+        fs::remove_file(path).unwrap();
+        fs::remove_file(report).unwrap();
+    }
+}
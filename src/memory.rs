@@ -1,44 +1,24 @@
+use crate::font::{FontError, FONT_SIZE, GLYPH_SIZE};
+use crate::settings::{MemorySize, Settings};
+use std::borrow::Cow;
 use std::fmt::{Debug, Formatter};
 use std::io::Read;
+use std::ops::Range;
 use std::{fs, io};
 
 const ADDRESS_INTERPRETER_START: usize = 0x0;
-const ADDRESS_PROGRAM_START: usize = 0x200;
-const ADDRESS_MAX: usize = 0xFFF;
-
-const INTERPRETER_MEMORY_SIZE: usize = ADDRESS_PROGRAM_START - ADDRESS_INTERPRETER_START;
-const PROGRAM_MEMORY_SIZE: usize = ADDRESS_MAX - ADDRESS_PROGRAM_START + 1;
-const MEMORY_SIZE: usize = INTERPRETER_MEMORY_SIZE + PROGRAM_MEMORY_SIZE;
-
-const SPRITE_SIZE: u8 = 5;
-
-type HexSprite = [u8; SPRITE_SIZE as usize];
-
-const HEX_SPRITES: [HexSprite; 0x10] = [
-    // Numerical 0-9
-    [0xF0, 0x90, 0x90, 0x90, 0xF0],
-    [0x20, 0x60, 0x20, 0x20, 0x70],
-    [0xF0, 0x10, 0xF0, 0x80, 0xF0],
-    [0xF0, 0x10, 0xF0, 0x10, 0xF0],
-    [0x90, 0x90, 0xF0, 0x10, 0x10],
-    [0xF0, 0x80, 0xF0, 0x10, 0xF0],
-    [0xF0, 0x80, 0xF0, 0x90, 0xF0],
-    [0xF0, 0x10, 0x20, 0x40, 0x40],
-    [0xF0, 0x90, 0xF0, 0x90, 0xF0],
-    [0xF0, 0x90, 0xF0, 0x10, 0xF0],
-    // Alpha A-F
-    [0xF0, 0x90, 0xF0, 0x90, 0x90],
-    [0xE0, 0x90, 0xE0, 0x90, 0xE0],
-    [0xF0, 0x80, 0x80, 0x80, 0xF0],
-    [0xE0, 0x90, 0x90, 0x90, 0xE0],
-    [0xF0, 0x80, 0xF0, 0x80, 0xF0],
-    [0xF0, 0x80, 0xF0, 0x80, 0x80],
-];
+pub const ADDRESS_PROGRAM_START: usize = 0x200;
 
 pub type Address = u16;
 
 pub struct RAM {
-    value: [u8; MEMORY_SIZE],
+    value: Vec<u8>,
+    // Address of the glyph for 0 in the hexadecimal font
+    font_address: Address,
+    // Address programs are loaded at
+    program_start: usize,
+    // Bytes written since the last call to take_written
+    written: Option<Range<usize>>,
 }
 
 pub trait ProgramLoader {
@@ -70,49 +50,117 @@ impl ProgramLoader for fs::File {
 
 impl RAM {
     pub fn new() -> RAM {
-        // Start with zeroed RAM
-        let mut ram = RAM {
-            value: [0; MEMORY_SIZE],
-        };
+        RAM::with_size(MemorySize::Standard)
+    }
+
+    pub fn with_size(size: MemorySize) -> RAM {
+        RAM::from_settings(&Settings::default().with_memory_size(size))
+    }
 
-        // Initialize the start of the RAM with the interpreter memory
-        let mut write_start = ADDRESS_INTERPRETER_START;
+    pub fn from_settings(settings: &Settings) -> RAM {
+        let mut ram = RAM::zeroed(settings.memory_size);
+        ram.program_start = settings.program_start as usize % ram.size();
 
         // Hexadecimal sprites have real addresses in interpreter memory
-        for sprite in HEX_SPRITES {
-            let write_end = write_start + sprite.len();
+        ram.load_font(settings.font.bytes(), settings.font_address)
+            .expect("built-in fonts fit in memory");
+        ram.take_written();
+
+        ram
+    }
 
-            ram.value[write_start..write_end].copy_from_slice(&sprite);
-            write_start = write_end;
+    fn zeroed(size: MemorySize) -> RAM {
+        RAM {
+            value: vec![0; size.bytes()],
+            font_address: ADDRESS_INTERPRETER_START as Address,
+            program_start: ADDRESS_PROGRAM_START,
+            written: None,
         }
+    }
 
-        ram
+    /// Load a font at an address, wrapping around the end of memory
+    /// The font must start with the 16 glyphs of the hexadecimal font
+    pub fn load_font(&mut self, font: &[u8], address: Address) -> Result<(), FontError> {
+        if font.len() < FONT_SIZE || font.len() > self.size() {
+            return Err(FontError::InvalidSize(font.len()));
+        }
+
+        self.write(address, font);
+
+        self.font_address = (address as usize % self.size()) as Address;
+        Ok(())
     }
 
     pub fn program_memory(&self) -> &[u8] {
-        &self.value[ADDRESS_PROGRAM_START..]
+        &self.value[self.program_start..]
     }
 
     pub fn program_memory_mut(&mut self) -> &mut [u8] {
-        &mut self.value[ADDRESS_PROGRAM_START..]
+        self.mark_written(self.program_start..self.size());
+
+        &mut self.value[self.program_start..]
     }
 
+    /// Memory from an address to the end, without wrapping
     pub fn address(&self, address: Address) -> &[u8] {
-        let address = address as usize;
+        let address = address as usize % self.size();
 
         &self.value[address..]
     }
 
-    pub fn address_mut(&mut self, address: Address) -> &mut [u8] {
-        let address = address as usize;
+    /// Read bytes from an address, wrapping around the end of memory
+    pub fn read(&self, address: Address, bytes: usize) -> Cow<'_, [u8]> {
+        let size = self.size();
+        let start = address as usize % size;
+
+        if start + bytes <= size {
+            Cow::Borrowed(&self.value[start..start + bytes])
+        } else {
+            Cow::Owned(
+                (start..start + bytes)
+                    .map(|a| self.value[a % size])
+                    .collect(),
+            )
+        }
+    }
+
+    /// Write bytes to an address, wrapping around the end of memory
+    pub fn write(&mut self, address: Address, bytes: &[u8]) {
+        let size = self.size();
+        let start = address as usize % size;
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.value[(start + offset) % size] = *byte;
+        }
 
-        &mut self.value[address..]
+        let end = start + bytes.len();
+        if end > size {
+            self.mark_written(0..size);
+        } else {
+            self.mark_written(start..end);
+        }
     }
 
-    pub fn get_instruction(&self, address: Address) -> &[u8] {
-        let address = address as usize;
+    pub fn size(&self) -> usize {
+        self.value.len()
+    }
 
-        &self.value[address..address + 2]
+    /// Take the range of addresses that may have been written since the last call
+    pub fn take_written(&mut self) -> Option<Range<usize>> {
+        self.written.take()
+    }
+
+    fn mark_written(&mut self, range: Range<usize>) {
+        self.written = match self.written.take() {
+            Some(written) => Some(written.start.min(range.start)..written.end.max(range.end)),
+            None => Some(range),
+        };
+    }
+
+    pub fn get_instruction(&self, address: Address) -> [u8; 2] {
+        let size = self.size();
+        let address = address as usize % size;
+
+        [self.value[address], self.value[(address + 1) % size]]
     }
 
     pub fn get_address_of_sprite(&self, value: u8) -> Address {
@@ -120,14 +168,13 @@ impl RAM {
             panic!("Cannot provide address of sprite with value {:#X}", value);
         }
 
-        5 * value as u16
-    }
+        let address = self.font_address as usize + value as usize * GLYPH_SIZE as usize;
 
-    pub fn get_sprite_at_address(&self, address: Address, bytes: u8) -> &[u8] {
-        let address = address as usize;
-        let bytes = bytes as usize;
+        (address % self.size()) as Address
+    }
 
-        &self.value[address..address + bytes]
+    pub fn get_sprite_at_address(&self, address: Address, bytes: u8) -> Cow<'_, [u8]> {
+        self.read(address, bytes as usize)
     }
 
     pub fn load_program<T, U>(&mut self, loader: T) -> U
@@ -136,14 +183,6 @@ impl RAM {
     {
         loader.load_into_ram(self.program_memory_mut())
     }
-
-    pub fn sprite_address(sprite_number: u8) -> Address {
-        if sprite_number > 0xF {
-            panic!("Invalid sprite number {}", sprite_number);
-        }
-
-        sprite_number as Address * SPRITE_SIZE as Address
-    }
 }
 
 impl Debug for RAM {
@@ -152,7 +191,7 @@ impl Debug for RAM {
 
         let mut bytes_written = 0;
 
-        for byte in self.value {
+        for byte in &self.value {
             if bytes_written % bytes_per_line == 0 {
                 write!(f, "{:#05X}: ", bytes_written)?;
             }
@@ -174,12 +213,41 @@ impl Debug for RAM {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::font::FontSet;
+    use crate::platform::Platform;
 
     #[test]
     fn program_memory() {
         let memory = RAM::new();
 
-        assert_eq!(PROGRAM_MEMORY_SIZE, memory.program_memory().len())
+        assert_eq!(0x1000 - 0x200, memory.program_memory().len());
+
+        // Program memory follows the load address
+        let settings = Settings::default().with_program_start(0x600);
+        let mut memory = RAM::from_settings(&settings);
+        assert_eq!(0x1000 - 0x600, memory.program_memory().len());
+
+        memory.load_program(&[0x12, 0x34][..]);
+        assert_eq!([0x12, 0x34], memory.address(0x600)[..2]);
+        assert_eq!(Some(0x600..0x1000), memory.take_written());
+    }
+
+    #[test]
+    fn memory_sizes() {
+        for (size, bytes) in [
+            (MemorySize::Vip, 0x800),
+            (MemorySize::Standard, 0x1000),
+            (MemorySize::XoChip, 0x10000),
+        ] {
+            let memory = RAM::with_size(size);
+
+            assert_eq!(bytes, memory.size());
+            assert_eq!(bytes - ADDRESS_PROGRAM_START, memory.program_memory().len());
+            assert_eq!(
+                FontSet::Standard.bytes()[0..5],
+                *memory.get_sprite_at_address(0, 5)
+            );
+        }
     }
 
     #[test]
@@ -197,6 +265,36 @@ mod tests {
         assert_eq!(program, memory.program_memory()[0..2]);
     }
 
+    #[test]
+    fn track_written() {
+        let mut memory = RAM::new();
+        assert_eq!(None, memory.take_written());
+
+        // Reading does not count as a write
+        memory.address(0x300);
+        assert_eq!(None, memory.take_written());
+
+        // Writes are merged into one range covering all of them
+        memory.write(0x300, &[1, 2, 3]);
+        memory.write(0x280, &[0, 0]);
+        assert_eq!(Some(0x280..0x303), memory.take_written());
+        assert_eq!(None, memory.take_written());
+
+        // A write past the end wraps around to the start
+        memory.write(0xFFF, &[4, 5]);
+        assert_eq!([4], memory.address(0xFFF)[..]);
+        assert_eq!([5], memory.address(0)[..1]);
+        assert_eq!([4, 5], memory.get_instruction(0xFFF));
+        assert_eq!(Some(0..memory.size()), memory.take_written());
+
+        // Loading a program covers the whole of program memory
+        memory.load_program(&[0x12, 0x00][..]);
+        assert_eq!(
+            Some(ADDRESS_PROGRAM_START..memory.size()),
+            memory.take_written()
+        );
+    }
+
     #[test]
     fn sprite_addressing() {
         let memory = RAM::new();
@@ -204,10 +302,50 @@ mod tests {
         for i in 0..0xF {
             let address = memory.get_address_of_sprite(i);
             let sprite = memory.get_sprite_at_address(address, 5);
-            assert_eq!(HEX_SPRITES[i as usize], sprite);
+            assert_eq!(FontSet::Standard.bytes()[i as usize * 5..][..5], *sprite);
         }
     }
 
+    #[test]
+    fn font_location() {
+        let settings = Settings::default()
+            .with_font(FontSet::Vip)
+            .with_font_address(0x50);
+        let mut memory = RAM::from_settings(&settings);
+
+        assert_eq!([0; 0x50], memory.address(0)[..0x50]);
+        assert_eq!(0x50 + 5 * 0xB, memory.get_address_of_sprite(0xB));
+        assert_eq!(
+            [0xF0, 0x50, 0x70, 0x50, 0xF0],
+            *memory.get_sprite_at_address(0x50 + 5 * 0xB, 5)
+        );
+
+        // A font loaded at the end of memory wraps around to the start
+        let font: Vec<u8> = (0..FONT_SIZE as u8).collect();
+        memory.take_written();
+        memory.load_font(&font, 0xFF0).unwrap();
+
+        assert_eq!(font[..0x10], memory.address(0xFF0)[..0x10]);
+        assert_eq!(font[0x10..], memory.address(0)[..FONT_SIZE - 0x10]);
+        assert_eq!(0xFF5, memory.get_address_of_sprite(1));
+        assert_eq!(0x004, memory.get_address_of_sprite(4));
+        assert_eq!(Some(0..memory.size()), memory.take_written());
+
+        // The glyph for 3 starts at the last byte and continues at the start
+        let address = memory.get_address_of_sprite(3);
+        assert_eq!(0xFFF, address);
+        assert_eq!(font[15..20], *memory.get_sprite_at_address(address, 5));
+
+        assert_eq!(
+            Err(FontError::InvalidSize(FONT_SIZE - 1)),
+            memory.load_font(&font[1..], 0)
+        );
+
+        // Platforms put the font where their programs expect it
+        let memory = RAM::from_settings(&Settings::for_platform(Platform::SuperChip));
+        assert_eq!(0x050 + 5, memory.get_address_of_sprite(1));
+    }
+
     #[test]
     #[should_panic(expected = "Cannot provide address of sprite with value 0x10")]
     fn sprite_addressing_invalid() {
@@ -0,0 +1,630 @@
+use crate::cosmac::video;
+use crate::instruction::Instruction;
+use crate::instruction::Instruction::*;
+use crate::memory::Address;
+use crate::platform::{Platform, ALL_PLATFORMS, RCA_1802_PLATFORMS};
+use std::fmt::{Display, Formatter};
+
+/// Static description of one opcode of the instruction set
+#[derive(Debug, PartialEq)]
+pub struct OpcodeInfo {
+    pub mnemonic: &'static str,
+    // The opcode as it is usually written, for example 8XY4
+    pub pattern: &'static str,
+    // An opcode belongs to this entry when opcode & mask == value
+    pub mask: u16,
+    pub value: u16,
+    pub platforms: &'static [Platform],
+    // Nominal cost on the COSMAC VIP in 1802 machine cycles, including fetch and decode
+    // Instructions with data-dependent timing report their cheapest case, see the vip_*_cycles functions
+    pub vip_cycles: u32,
+}
+
+impl OpcodeInfo {
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+const fn opcode(
+    mnemonic: &'static str,
+    pattern: &'static str,
+    mask: u16,
+    value: u16,
+    vip_cycles: u32,
+) -> OpcodeInfo {
+    OpcodeInfo {
+        mnemonic,
+        pattern,
+        mask,
+        value,
+        platforms: ALL_PLATFORMS,
+        vip_cycles,
+    }
+}
+
+/// Every opcode understood by Instruction::from_bytes, in opcode order
+/// An entry comes before any more general entry that also matches its opcodes
+pub static OPCODES: &[OpcodeInfo] = &[
+    opcode("CLS", "00E0", 0xFFFF, 0x00E0, 3078),
+    opcode("RET", "00EE", 0xFFFF, 0x00EE, 50),
+    // Only the interpreter's share of the call, the routine itself costs extra
+    // Machine-language routines can only be called where the interpreter runs on an 1802
+    OpcodeInfo {
+        platforms: RCA_1802_PLATFORMS,
+        ..opcode("SYS", "0NNN", 0xF000, 0x0000, 40)
+    },
+    opcode("JP", "1NNN", 0xF000, 0x1000, 52),
+    opcode("CALL", "2NNN", 0xF000, 0x2000, 66),
+    opcode("SE", "3XNN", 0xF000, 0x3000, 56),
+    opcode("SNE", "4XNN", 0xF000, 0x4000, 56),
+    opcode("SE", "5XY0", 0xF00F, 0x5000, 60),
+    opcode("LD", "6XNN", 0xF000, 0x6000, 48),
+    opcode("ADD", "7XNN", 0xF000, 0x7000, 52),
+    opcode("LD", "8XY0", 0xF00F, 0x8000, 88),
+    opcode("OR", "8XY1", 0xF00F, 0x8001, 88),
+    opcode("AND", "8XY2", 0xF00F, 0x8002, 88),
+    opcode("XOR", "8XY3", 0xF00F, 0x8003, 88),
+    opcode("ADD", "8XY4", 0xF00F, 0x8004, 88),
+    opcode("SUB", "8XY5", 0xF00F, 0x8005, 88),
+    opcode("SHR", "8XY6", 0xF00F, 0x8006, 88),
+    opcode("SUBN", "8XY7", 0xF00F, 0x8007, 88),
+    opcode("SHL", "8XYE", 0xF00F, 0x800E, 88),
+    opcode("SNE", "9XY0", 0xF00F, 0x9000, 60),
+    opcode("LD", "ANNN", 0xF000, 0xA000, 48),
+    opcode("JP", "BNNN", 0xF000, 0xB000, 64),
+    opcode("RND", "CXNN", 0xF000, 0xC000, 72),
+    opcode("DRW", "DXYN", 0xF000, 0xD000, 940),
+    opcode("SKP", "EX9E", 0xF0FF, 0xE09E, 56),
+    opcode("SKNP", "EXA1", 0xF0FF, 0xE0A1, 56),
+    opcode("LD", "FX07", 0xF0FF, 0xF007, 52),
+    opcode("LD", "FX0A", 0xF0FF, 0xF00A, 52),
+    opcode("LD", "FX15", 0xF0FF, 0xF015, 52),
+    opcode("LD", "FX18", 0xF0FF, 0xF018, 52),
+    opcode("ADD", "FX1E", 0xF0FF, 0xF01E, 60),
+    opcode("LD", "FX29", 0xF0FF, 0xF029, 56),
+    opcode("LD", "FX33", 0xF0FF, 0xF033, 84),
+    opcode("LD", "FX55", 0xF0FF, 0xF055, 86),
+    opcode("LD", "FX65", 0xF0FF, 0xF065, 86),
+];
+
+/// Machine cycles left for CHIP-8 instructions in each frame on the COSMAC VIP
+/// The interpreter's interrupt routine holds the CPU from the interrupt until the display ends
+pub const VIP_FRAME_CYCLES: u32 = video::FRAME_CYCLES
+    - (video::DISPLAY_LINES.end - video::DISPLAY_LINES.start + 2) * video::LINE_CYCLES;
+
+// DXYN copies each row of the sprite shifted right by X % 8 one bit at a time,
+// then XORs it into one display byte, or two when the shift splits it
+const DRAW_SETUP_CYCLES: u32 = 68;
+const DRAW_ROW_CYCLES: u32 = 46;
+const DRAW_SHIFT_CYCLES: u32 = 8;
+const DRAW_SPLIT_ROW_CYCLES: u32 = 28;
+
+/// Cost on the COSMAC VIP of drawing a sprite of some rows at a horizontal position
+pub fn vip_draw_cycles(rows: u8, x: u8) -> u32 {
+    let shift = (x % 8) as u32;
+    let split = if shift == 0 { 0 } else { DRAW_SPLIT_ROW_CYCLES };
+
+    DRAW_SETUP_CYCLES + rows as u32 * (DRAW_ROW_CYCLES + shift * DRAW_SHIFT_CYCLES + split)
+}
+
+// Skips that are taken run two more INC R5 instructions
+const SKIP_TAKEN_CYCLES: u32 = 4;
+
+/// Cost on the COSMAC VIP of a skip instruction, which depends on whether it skips
+pub fn vip_skip_cycles(info: &OpcodeInfo, skipped: bool) -> u32 {
+    info.vip_cycles + if skipped { SKIP_TAKEN_CYCLES } else { 0 }
+}
+
+// FX55 and FX65 copy one register per pass of a loop of seven instructions
+const MEMORY_REGISTER_CYCLES: u32 = 14;
+
+/// Cost on the COSMAC VIP of storing or loading the registers V0 to VX
+pub fn vip_memory_cycles(info: &OpcodeInfo, max_register: u8) -> u32 {
+    info.vip_cycles + (max_register as u32 + 1) * MEMORY_REGISTER_CYCLES
+}
+
+// FX33 finds each digit by subtracting its place value until it would go negative
+const DECIMAL_COUNT_CYCLES: u32 = 24;
+
+/// Cost on the COSMAC VIP of storing the decimal digits of a value
+pub fn vip_decimal_cycles(info: &OpcodeInfo, value: u8) -> u32 {
+    let digits = value / 100 + value / 10 % 10 + value % 10;
+
+    info.vip_cycles + (digits as u32 + 3) * DECIMAL_COUNT_CYCLES
+}
+
+/// Find the opcode entry that a raw 16-bit instruction belongs to
+pub fn lookup(opcode: u16) -> Option<&'static OpcodeInfo> {
+    OPCODES.iter().find(|info| info.matches(opcode))
+}
+
+/// The machine state an instruction reads or writes
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Access {
+    // Bit N is set when register VN is accessed
+    pub v: u16,
+    pub i: bool,
+    pub memory: bool,
+    pub delay_timer: bool,
+    pub sound_timer: bool,
+}
+
+impl Access {
+    fn none() -> Access {
+        Access::default()
+    }
+
+    // Everything, for machine-language routines that can change any of it
+    fn all() -> Access {
+        Access {
+            v: 0xFFFF,
+            i: true,
+            memory: true,
+            delay_timer: true,
+            sound_timer: true,
+        }
+    }
+
+    fn v(registers: &[u8]) -> Access {
+        Access::none().with_v(registers)
+    }
+
+    fn v_through(max_register: u8) -> Access {
+        Access {
+            v: (0..=max_register).fold(0, |mask, register| mask | 1 << register),
+            ..Access::none()
+        }
+    }
+
+    fn with_v(mut self, registers: &[u8]) -> Access {
+        for register in registers {
+            self.v |= 1 << (register & 0xF);
+        }
+        self
+    }
+
+    fn with_i(mut self) -> Access {
+        self.i = true;
+        self
+    }
+
+    fn with_memory(mut self) -> Access {
+        self.memory = true;
+        self
+    }
+
+    pub fn uses_v(&self, register: u8) -> bool {
+        self.v & (1 << (register & 0xF)) != 0
+    }
+
+    pub fn v_registers(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..0x10).filter(|register| self.uses_v(*register))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Access::none()
+    }
+}
+
+/// How an instruction chooses the next instruction to run
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Flow {
+    // Continue with the following instruction
+    Next,
+    // Either continue with the following instruction or skip over it
+    Skip,
+    // Continue at a fixed address
+    Jump(Address),
+    // Continue at a base address offset by V0
+    JumpIndirect(Address),
+    // Push the return address and continue at a fixed address
+    Call(Address),
+    // Continue at the address on top of the stack
+    Return,
+}
+
+impl Flow {
+    pub fn is_branch(&self) -> bool {
+        !matches!(self, Flow::Next | Flow::Skip)
+    }
+
+    pub fn is_skip(&self) -> bool {
+        matches!(self, Flow::Skip)
+    }
+}
+
+impl Instruction {
+    pub fn info(&self) -> &'static OpcodeInfo {
+        let index = match self {
+            ClearScreen => 0,
+            Return => 1,
+            Sys { .. } => 2,
+            JumpNNN { .. } => 3,
+            CallNNN { .. } => 4,
+            SkipEqXNN { .. } => 5,
+            SkipNeXNN { .. } => 6,
+            SkipEqXY { .. } => 7,
+            StoreXNN { .. } => 8,
+            AddXNN { .. } => 9,
+            StoreXY { .. } => 10,
+            OrXY { .. } => 11,
+            AndXY { .. } => 12,
+            XorXY { .. } => 13,
+            AddXY { .. } => 14,
+            SubXY { .. } => 15,
+            ShrXY { .. } => 16,
+            SUBXYReverse { .. } => 17,
+            ShlXY { .. } => 18,
+            SkipNeXY { .. } => 19,
+            StoreNNN { .. } => 20,
+            JumpV0 { .. } => 21,
+            Rand { .. } => 22,
+            DrawXYN { .. } => 23,
+            SkipPressedX { .. } => 24,
+            SkipNotPressedX { .. } => 25,
+            StoreDelayInX { .. } => 26,
+            StorePressX { .. } => 27,
+            SetDelayToX { .. } => 28,
+            SetSoundToX { .. } => 29,
+            AddIX { .. } => 30,
+            StoreSpriteX { .. } => 31,
+            StoreDecimal { .. } => 32,
+            WriteToMemory { .. } => 33,
+            ReadFromMemory { .. } => 34,
+        };
+
+        &OPCODES[index]
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        self.info().mnemonic
+    }
+
+    /// State read by the instruction
+    /// Quirk-dependent reads, such as the source of a bit shift, are always included
+    /// Machine-language calls may read anything
+    pub fn reads(&self) -> Access {
+        match self {
+            Sys { .. } => Access::all(),
+            ClearScreen | Return | JumpNNN { .. } | CallNNN { .. } => Access::none(),
+            SkipEqXNN { register, .. } | SkipNeXNN { register, .. } => Access::v(&[*register]),
+            SkipEqXY {
+                register_x,
+                register_y,
+            }
+            | SkipNeXY {
+                register_x,
+                register_y,
+            } => Access::v(&[*register_x, *register_y]),
+            StoreXNN { .. } => Access::none(),
+            AddXNN { register, .. } => Access::v(&[*register]),
+            StoreXY { source, .. } => Access::v(&[*source]),
+            OrXY { target, source }
+            | AndXY { target, source }
+            | XorXY { target, source }
+            | AddXY { target, source }
+            | SubXY { target, source }
+            | ShrXY { target, source }
+            | SUBXYReverse { target, source }
+            | ShlXY { target, source } => Access::v(&[*target, *source]),
+            StoreNNN { .. } => Access::none(),
+            JumpV0 { .. } => Access::v(&[0]),
+            Rand { .. } => Access::none(),
+            DrawXYN {
+                x_register,
+                y_register,
+                ..
+            } => Access::v(&[*x_register, *y_register])
+                .with_i()
+                .with_memory(),
+            SkipPressedX { register } | SkipNotPressedX { register } => Access::v(&[*register]),
+            StoreDelayInX { .. } => Access {
+                delay_timer: true,
+                ..Access::none()
+            },
+            StorePressX { .. } => Access::none(),
+            SetDelayToX { register } | SetSoundToX { register } => Access::v(&[*register]),
+            AddIX { register } => Access::v(&[*register]).with_i(),
+            StoreSpriteX { register } => Access::v(&[*register]),
+            StoreDecimal { register } => Access::v(&[*register]).with_i(),
+            WriteToMemory { max_register } => Access::v_through(*max_register).with_i(),
+            ReadFromMemory { .. } => Access::none().with_i().with_memory(),
+        }
+    }
+
+    /// State written by the instruction
+    /// Quirk-dependent writes, such as I advancing after FX55 and FX65, are always included
+    /// Machine-language calls may write anything
+    pub fn writes(&self) -> Access {
+        match self {
+            Sys { .. } => Access::all(),
+            ClearScreen | Return | JumpNNN { .. } | CallNNN { .. } => Access::none(),
+            SkipEqXNN { .. } | SkipNeXNN { .. } | SkipEqXY { .. } | SkipNeXY { .. } => {
+                Access::none()
+            }
+            StoreXNN { register, .. } | AddXNN { register, .. } => Access::v(&[*register]),
+            StoreXY { target, .. }
+            | OrXY { target, .. }
+            | AndXY { target, .. }
+            | XorXY { target, .. } => Access::v(&[*target]),
+            AddXY { target, .. }
+            | SubXY { target, .. }
+            | ShrXY { target, .. }
+            | SUBXYReverse { target, .. }
+            | ShlXY { target, .. } => Access::v(&[*target, 0xF]),
+            StoreNNN { .. } => Access::none().with_i(),
+            JumpV0 { .. } => Access::none(),
+            Rand { register, .. } => Access::v(&[*register]),
+            DrawXYN { .. } => Access::v(&[0xF]),
+            SkipPressedX { .. } | SkipNotPressedX { .. } => Access::none(),
+            StoreDelayInX { register } | StorePressX { register } => Access::v(&[*register]),
+            SetDelayToX { .. } => Access {
+                delay_timer: true,
+                ..Access::none()
+            },
+            SetSoundToX { .. } => Access {
+                sound_timer: true,
+                ..Access::none()
+            },
+            AddIX { .. } | StoreSpriteX { .. } => Access::none().with_i(),
+            StoreDecimal { .. } => Access::none().with_memory(),
+            WriteToMemory { .. } => Access::none().with_i().with_memory(),
+            ReadFromMemory { max_register } => Access::v_through(*max_register).with_i(),
+        }
+    }
+
+    pub fn flow(&self) -> Flow {
+        match self {
+            Return => Flow::Return,
+            JumpNNN { address } => Flow::Jump(*address),
+            CallNNN { address } => Flow::Call(*address),
+            JumpV0 { address } => Flow::JumpIndirect(*address),
+            SkipEqXNN { .. }
+            | SkipNeXNN { .. }
+            | SkipEqXY { .. }
+            | SkipNeXY { .. }
+            | SkipPressedX { .. }
+            | SkipNotPressedX { .. } => Flow::Skip,
+            _ => Flow::Next,
+        }
+    }
+
+    pub fn supported_on(&self, platform: Platform) -> bool {
+        self.info().platforms.contains(&platform)
+    }
+}
+
+/// Disassembles the instruction using the mnemonics from Cowgod's Chip-8 technical reference
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mnemonic = self.mnemonic();
+
+        match self {
+            ClearScreen | Return => write!(f, "{}", mnemonic),
+            Sys { address } | JumpNNN { address } | CallNNN { address } => {
+                write!(f, "{} {:#05X}", mnemonic, address)
+            }
+            SkipEqXNN { register, value }
+            | SkipNeXNN { register, value }
+            | StoreXNN { register, value }
+            | AddXNN { register, value } => {
+                write!(f, "{} V{:X}, {:#04X}", mnemonic, register, value)
+            }
+            SkipEqXY {
+                register_x,
+                register_y,
+            }
+            | SkipNeXY {
+                register_x,
+                register_y,
+            } => write!(f, "{} V{:X}, V{:X}", mnemonic, register_x, register_y),
+            StoreXY { target, source }
+            | OrXY { target, source }
+            | AndXY { target, source }
+            | XorXY { target, source }
+            | AddXY { target, source }
+            | SubXY { target, source }
+            | ShrXY { target, source }
+            | SUBXYReverse { target, source }
+            | ShlXY { target, source } => write!(f, "{} V{:X}, V{:X}", mnemonic, target, source),
+            StoreNNN { value } => write!(f, "{} I, {:#05X}", mnemonic, value),
+            JumpV0 { address } => write!(f, "{} V0, {:#05X}", mnemonic, address),
+            Rand { register, mask } => write!(f, "{} V{:X}, {:#04X}", mnemonic, register, mask),
+            DrawXYN {
+                x_register,
+                y_register,
+                bytes,
+            } => write!(
+                f,
+                "{} V{:X}, V{:X}, {}",
+                mnemonic, x_register, y_register, bytes
+            ),
+            SkipPressedX { register } | SkipNotPressedX { register } => {
+                write!(f, "{} V{:X}", mnemonic, register)
+            }
+            StoreDelayInX { register } => write!(f, "{} V{:X}, DT", mnemonic, register),
+            StorePressX { register } => write!(f, "{} V{:X}, K", mnemonic, register),
+            SetDelayToX { register } => write!(f, "{} DT, V{:X}", mnemonic, register),
+            SetSoundToX { register } => write!(f, "{} ST, V{:X}", mnemonic, register),
+            AddIX { register } => write!(f, "{} I, V{:X}", mnemonic, register),
+            StoreSpriteX { register } => write!(f, "{} F, V{:X}", mnemonic, register),
+            StoreDecimal { register } => write!(f, "{} B, V{:X}", mnemonic, register),
+            WriteToMemory { max_register } => write!(f, "{} [I], V{:X}", mnemonic, max_register),
+            ReadFromMemory { max_register } => {
+                write!(f, "{} V{:X}, [I]", mnemonic, max_register)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_are_unique() {
+        for (index, info) in OPCODES.iter().enumerate() {
+            // The value must be reachable through its own mask
+            assert_eq!(info.value, info.value & info.mask);
+
+            // Later entries may only overlap an earlier one when they are more general
+            for other in &OPCODES[index + 1..] {
+                assert!(
+                    !info.matches(other.value)
+                        && (!other.matches(info.value) || other.mask < info.mask),
+                    "{} overlaps {}",
+                    info.pattern,
+                    other.pattern
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn info_matches_decoded_instruction() {
+        // Every opcode pattern with its variable nibbles filled in should decode to its own entry
+        for info in OPCODES {
+            let opcode = info.value | (!info.mask & 0x5A5A);
+            let instruction = Instruction::from_bytes(&opcode.to_be_bytes()).unwrap();
+
+            assert_eq!(info, instruction.info());
+            assert_eq!(Some(info), lookup(opcode));
+            assert!(info.matches(instruction.to_u16()));
+        }
+    }
+
+    #[test]
+    fn platform_support() {
+        let sys = Sys { address: 0x1F0 };
+        assert!(sys.supported_on(Platform::CosmacVip));
+        assert!(sys.supported_on(Platform::Eti660));
+        assert!(!sys.supported_on(Platform::Chip48));
+        assert!(!sys.supported_on(Platform::SuperChip));
+        assert!(!sys.supported_on(Platform::XoChip));
+
+        let draw = DrawXYN {
+            x_register: 0,
+            y_register: 1,
+            bytes: 5,
+        };
+        assert!(ALL_PLATFORMS
+            .iter()
+            .all(|platform| draw.supported_on(*platform)));
+    }
+
+    #[test]
+    fn lookup_unknown_opcode() {
+        assert_eq!(None, lookup(0xE123));
+        assert_eq!(None, lookup(0x5121));
+        assert_eq!(None, lookup(0xF131));
+    }
+
+    #[test]
+    fn draw_cycles() {
+        // Aligned sprites skip the shifting and the second byte
+        assert_eq!(68 + 5 * 46, vip_draw_cycles(5, 8));
+        assert_eq!(68 + 5 * (46 + 3 * 8 + 28), vip_draw_cycles(5, 11));
+        assert!(vip_draw_cycles(15, 7) > vip_draw_cycles(15, 1));
+        assert_eq!(68, vip_draw_cycles(0, 3));
+    }
+
+    #[test]
+    fn operand_cycles() {
+        let skip = SkipEqXNN {
+            register: 0,
+            value: 0,
+        };
+        assert_eq!(56, vip_skip_cycles(skip.info(), false));
+        assert_eq!(60, vip_skip_cycles(skip.info(), true));
+
+        let store = WriteToMemory { max_register: 0 };
+        assert_eq!(86 + 14, vip_memory_cycles(store.info(), 0));
+        assert_eq!(86 + 16 * 14, vip_memory_cycles(store.info(), 0xF));
+
+        // One pass for each count of a digit and one more to find it is done
+        let decimal = StoreDecimal { register: 0 };
+        assert_eq!(84 + 3 * 24, vip_decimal_cycles(decimal.info(), 0));
+        assert_eq!(
+            84 + (2 + 5 + 5 + 3) * 24,
+            vip_decimal_cycles(decimal.info(), 255)
+        );
+    }
+
+    #[test]
+    fn register_access() {
+        let add = AddXY {
+            target: 0x3,
+            source: 0xA,
+        };
+        assert_eq!(
+            vec![0x3, 0xA],
+            add.reads().v_registers().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![0x3, 0xF],
+            add.writes().v_registers().collect::<Vec<_>>()
+        );
+
+        let store = WriteToMemory { max_register: 0x2 };
+        assert_eq!(0b111, store.reads().v);
+        assert!(store.reads().i);
+        assert!(!store.reads().memory);
+        assert!(store.writes().memory);
+
+        let load = ReadFromMemory { max_register: 0xF };
+        assert_eq!(0xFFFF, load.writes().v);
+        assert!(load.reads().memory);
+
+        let delay = StoreDelayInX { register: 0x4 };
+        assert!(delay.reads().delay_timer);
+        assert!(!delay.writes().delay_timer);
+        assert!(SetSoundToX { register: 0x1 }.writes().sound_timer);
+
+        assert!(ClearScreen.reads().is_empty());
+        assert!(ClearScreen.writes().is_empty());
+
+        // A machine-language routine may touch anything
+        let sys = Sys { address: 0x1F0 };
+        assert_eq!(Access::all(), sys.reads());
+        assert_eq!(0xFFFF, sys.writes().v);
+        assert!(sys.writes().i && sys.writes().memory);
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(Flow::Next, ClearScreen.flow());
+        assert_eq!(Flow::Return, Return.flow());
+        assert_eq!(Flow::Jump(0x345), JumpNNN { address: 0x345 }.flow());
+        assert_eq!(Flow::Call(0x456), CallNNN { address: 0x456 }.flow());
+        assert_eq!(Flow::JumpIndirect(0x300), JumpV0 { address: 0x300 }.flow());
+        assert_eq!(Flow::Skip, SkipPressedX { register: 1 }.flow());
+
+        assert!(Flow::Return.is_branch());
+        assert!(!Flow::Skip.is_branch());
+        assert!(Flow::Skip.is_skip());
+    }
+
+    #[test]
+    fn disassembly() {
+        let cases = [
+            (0x00E0, "CLS"),
+            (0x0123, "SYS 0x123"),
+            (0x1234, "JP 0x234"),
+            (0x3A0F, "SE VA, 0x0F"),
+            (0x8126, "SHR V1, V2"),
+            (0xA123, "LD I, 0x123"),
+            (0xB400, "JP V0, 0x400"),
+            (0xD125, "DRW V1, V2, 5"),
+            (0xF30A, "LD V3, K"),
+            (0xF533, "LD B, V5"),
+            (0xF255, "LD [I], V2"),
+            (0xF265, "LD V2, [I]"),
+        ];
+
+        for (opcode, expected) in cases {
+            let instruction = Instruction::from_bytes(&u16::to_be_bytes(opcode)).unwrap();
+            assert_eq!(expected, instruction.to_string());
+        }
+    }
+}
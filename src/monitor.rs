@@ -0,0 +1,158 @@
+use crate::instruction::Instruction;
+use crate::machine::StepHook;
+use crate::memory::{Address, RAM};
+use crate::register::Registers;
+use crate::settings::StackLocation;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// How often the machine's state is copied out, a little more often than the display is drawn
+const PUBLISH_INTERVAL: Duration = Duration::from_micros(8333);
+
+/// Copy of the machine's registers as they were just before an instruction ran
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MachineState {
+    pub v: [u8; 16],
+    pub i: Address,
+    pub pc: Address,
+    pub sp: usize,
+    // Return addresses on the stack from the bottom up
+    pub stack: Vec<Address>,
+    pub dt: u8,
+    pub st: u8,
+    // The instruction at PC
+    pub instruction: Option<Instruction>,
+    // Instructions executed since the machine started
+    pub instructions: u64,
+}
+
+/// Shares the state of a running machine with another thread, such as the one drawing the window
+#[derive(Clone, Default)]
+pub struct StateMonitor {
+    latest: Arc<Mutex<Option<MachineState>>>,
+}
+
+impl StateMonitor {
+    pub fn new() -> StateMonitor {
+        StateMonitor::default()
+    }
+
+    /// A hook for the machine that publishes its state to this monitor
+    pub fn hook(&self, stack_location: StackLocation) -> MonitorHook {
+        MonitorHook {
+            latest: Arc::clone(&self.latest),
+            stack_location,
+            instructions: 0,
+            published: None,
+        }
+    }
+
+    /// The state the machine last published, if it has started
+    pub fn latest(&self) -> Option<MachineState> {
+        self.latest.lock().unwrap().clone()
+    }
+}
+
+/// Step hook that copies the machine's state to a monitor every so often
+/// The monitor's lock is only taken to swap in a new copy, so readers never hold up the machine for long
+pub struct MonitorHook {
+    latest: Arc<Mutex<Option<MachineState>>>,
+    // Where the machine keeps its return addresses
+    stack_location: StackLocation,
+    instructions: u64,
+    published: Option<Instant>,
+}
+
+impl StepHook for MonitorHook {
+    fn before_step(&mut self, instruction: &Instruction, registers: &Registers, ram: &RAM) {
+        self.instructions += 1;
+
+        // Always publish before waiting for a key, which can stop the machine for a long time
+        let waits_for_key = matches!(instruction, Instruction::StorePressX { .. });
+        let now = Instant::now();
+        let recently_published = self
+            .published
+            .is_some_and(|published| now.saturating_duration_since(published) < PUBLISH_INTERVAL);
+        if recently_published && !waits_for_key {
+            return;
+        }
+
+        let state = MachineState {
+            v: registers.v,
+            i: registers.i,
+            pc: registers.pc,
+            sp: registers.sp,
+            stack: self.stack(registers, ram),
+            dt: registers.dt,
+            st: registers.st,
+            instruction: Some(*instruction),
+            instructions: self.instructions,
+        };
+
+        *self.latest.lock().unwrap() = Some(state);
+        self.published = Some(now);
+    }
+}
+
+impl MonitorHook {
+    fn stack(&self, registers: &Registers, ram: &RAM) -> Vec<Address> {
+        match self.stack_location {
+            StackLocation::Registers => {
+                let depth = registers.sp.min(registers.stack.len());
+                registers.stack[..depth].to_vec()
+            }
+            // Each level is a 2-byte big-endian return address, as the machine writes them
+            StackLocation::Memory { address } => (0..registers.sp)
+                .map(|level| {
+                    let bytes = ram.read(address.wrapping_add(2 * level as Address), 2);
+                    u16::from_be_bytes([bytes[0], bytes[1]])
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction::*;
+
+    #[test]
+    fn publishes_state() {
+        let monitor = StateMonitor::new();
+        let mut hook = monitor.hook(StackLocation::Registers);
+        let mut registers = Registers::new();
+        let mut ram = RAM::new();
+        assert_eq!(None, monitor.latest());
+
+        registers.set_register(0xA, 0x42);
+        registers.stack_call(0x300).unwrap();
+        hook.before_step(&ClearScreen, &registers, &ram);
+
+        let state = monitor.latest().unwrap();
+        assert_eq!(0x42, state.v[0xA]);
+        assert_eq!(0x300, state.pc);
+        assert_eq!(vec![0x200], state.stack);
+        assert_eq!(Some(ClearScreen), state.instruction);
+        assert_eq!(1, state.instructions);
+
+        // Instructions right after a publish are counted but not copied out
+        // Moving the last publish ahead keeps a slow test run from looking like time has passed
+        hook.published = Some(Instant::now() + PUBLISH_INTERVAL);
+        hook.before_step(&Return, &registers, &ram);
+        assert_eq!(Some(ClearScreen), monitor.latest().unwrap().instruction);
+
+        // Unless the machine is about to wait for a key
+        hook.before_step(&StorePressX { register: 0 }, &registers, &ram);
+        let state = monitor.latest().unwrap();
+        assert_eq!(Some(StorePressX { register: 0 }), state.instruction);
+        assert_eq!(3, state.instructions);
+
+        // A stack in memory is read back from where the machine keeps it
+        let mut hook = monitor.hook(StackLocation::Memory { address: 0xEA0 });
+        ram.write(0xEA0, &[0x02, 0x04, 0x03, 0x46, 0xFF, 0xFF]);
+        registers.push_level().unwrap();
+        hook.before_step(&ClearScreen, &registers, &ram);
+        assert_eq!(vec![0x204, 0x346], monitor.latest().unwrap().stack);
+    }
+}
@@ -0,0 +1,41 @@
+use std::fmt::{Display, Formatter};
+
+/// Historical machines and interpreters that ran Chip8 programs
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Platform {
+    // The original interpreter on the RCA COSMAC VIP
+    CosmacVip,
+    // The ETI-660 kit computer, which loads programs at 0x600
+    Eti660,
+    // The HP48 calculator interpreter
+    Chip48,
+    // SUPER-CHIP 1.1 on the HP48
+    SuperChip,
+    // Octo's XO-CHIP extensions
+    XoChip,
+}
+
+pub const ALL_PLATFORMS: &[Platform] = &[
+    Platform::CosmacVip,
+    Platform::Eti660,
+    Platform::Chip48,
+    Platform::SuperChip,
+    Platform::XoChip,
+];
+
+// Platforms whose interpreter runs on an RCA 1802 and can call machine-language routines
+pub const RCA_1802_PLATFORMS: &[Platform] = &[Platform::CosmacVip, Platform::Eti660];
+
+impl Display for Platform {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Platform::CosmacVip => "COSMAC VIP",
+            Platform::Eti660 => "ETI-660",
+            Platform::Chip48 => "CHIP-48",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        };
+
+        write!(f, "{}", name)
+    }
+}
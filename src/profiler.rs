@@ -0,0 +1,448 @@
+use crate::instruction::Instruction;
+use crate::machine::StepHook;
+use crate::memory::{Address, RAM};
+use crate::metadata::OpcodeInfo;
+use crate::register::Registers;
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+// Hotspots listed in the text report
+const TEXT_REPORT_HOTSPOTS: usize = 20;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ReportFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SubroutineCounts {
+    pub calls: u64,
+    // Instructions executed while the subroutine or anything it called was running
+    pub inclusive: u64,
+    // Instructions executed by the subroutine itself
+    pub exclusive: u64,
+}
+
+/// Counts executed instructions per address, per opcode and per subroutine
+#[derive(Default)]
+pub struct Profiler {
+    total: u64,
+    addresses: HashMap<Address, (Instruction, u64)>,
+    opcodes: HashMap<&'static str, (&'static OpcodeInfo, u64)>,
+    subroutines: HashMap<Address, SubroutineCounts>,
+    // The entry point followed by the target of every call that has not returned
+    call_stack: Vec<Address>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    pub fn record(&mut self, pc: Address, instruction: &Instruction) {
+        self.total += 1;
+        self.addresses.entry(pc).or_insert((*instruction, 0)).1 += 1;
+
+        let info = instruction.info();
+        self.opcodes.entry(info.pattern).or_insert((info, 0)).1 += 1;
+
+        // The first instruction executed belongs to the entry point routine
+        if self.call_stack.is_empty() {
+            self.call_stack.push(pc);
+            self.subroutines.entry(pc).or_default().calls += 1;
+        }
+
+        let current = *self.call_stack.last().unwrap();
+        self.subroutines.entry(current).or_default().exclusive += 1;
+
+        // Recursive routines only count once per instruction
+        for (depth, routine) in self.call_stack.iter().enumerate() {
+            if !self.call_stack[..depth].contains(routine) {
+                self.subroutines.entry(*routine).or_default().inclusive += 1;
+            }
+        }
+
+        match instruction {
+            Instruction::CallNNN { address } => {
+                self.call_stack.push(*address);
+                self.subroutines.entry(*address).or_default().calls += 1;
+            }
+            // Never pop the entry point, even if the program returns from it
+            Instruction::Return if self.call_stack.len() > 1 => {
+                self.call_stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn address_count(&self, address: Address) -> u64 {
+        self.addresses.get(&address).map_or(0, |(_, count)| *count)
+    }
+
+    pub fn opcode_count(&self, pattern: &str) -> u64 {
+        self.opcodes.get(pattern).map_or(0, |(_, count)| *count)
+    }
+
+    pub fn subroutine(&self, address: Address) -> Option<SubroutineCounts> {
+        self.subroutines.get(&address).copied()
+    }
+
+    pub fn write_report<W: Write>(&self, format: ReportFormat, writer: &mut W) -> io::Result<()> {
+        match format {
+            ReportFormat::Text => self.write_text(writer),
+            ReportFormat::Csv => self.write_csv(writer),
+            ReportFormat::Json => self.write_json(writer),
+        }
+    }
+
+    // Most executed first, ties broken by address
+    fn sorted_addresses(&self) -> Vec<(Address, Instruction, u64)> {
+        let mut addresses: Vec<_> = self
+            .addresses
+            .iter()
+            .map(|(address, (instruction, count))| (*address, *instruction, *count))
+            .collect();
+
+        addresses.sort_by_key(|(address, _, count)| (u64::MAX - count, *address));
+        addresses
+    }
+
+    fn sorted_opcodes(&self) -> Vec<(&'static OpcodeInfo, u64)> {
+        let mut opcodes: Vec<_> = self.opcodes.values().copied().collect();
+
+        opcodes.sort_by_key(|(info, count)| (u64::MAX - count, info.value));
+        opcodes
+    }
+
+    fn sorted_subroutines(&self) -> Vec<(Address, SubroutineCounts)> {
+        let mut subroutines: Vec<_> = self
+            .subroutines
+            .iter()
+            .map(|(address, counts)| (*address, *counts))
+            .collect();
+
+        subroutines.sort_by_key(|(address, counts)| (u64::MAX - counts.inclusive, *address));
+        subroutines
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            100.0 * count as f64 / self.total as f64
+        }
+    }
+
+    fn write_text<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "Instructions executed: {}", self.total)?;
+
+        writeln!(writer)?;
+        writeln!(writer, "Hotspots")?;
+        for (address, instruction, count) in self
+            .sorted_addresses()
+            .into_iter()
+            .take(TEXT_REPORT_HOTSPOTS)
+        {
+            writeln!(
+                writer,
+                "  {:#05X}  {:>12}  {:>6.2}%  {}",
+                address,
+                count,
+                self.percent(count),
+                instruction
+            )?;
+        }
+
+        writeln!(writer)?;
+        writeln!(writer, "Opcodes")?;
+        for (info, count) in self.sorted_opcodes() {
+            writeln!(
+                writer,
+                "  {}  {:<4}  {:>12}  {:>6.2}%",
+                info.pattern,
+                info.mnemonic,
+                count,
+                self.percent(count)
+            )?;
+        }
+
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "Subroutines  {:>8}  {:>12}  {:>12}",
+            "calls", "inclusive", "exclusive"
+        )?;
+        for (address, counts) in self.sorted_subroutines() {
+            writeln!(
+                writer,
+                "  {:#05X}      {:>8}  {:>12}  {:>12}",
+                address, counts.calls, counts.inclusive, counts.exclusive
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "kind,key,description,count,calls,inclusive,exclusive"
+        )?;
+
+        for (address, instruction, count) in self.sorted_addresses() {
+            // Disassembly contains commas, so it is always quoted
+            writeln!(
+                writer,
+                "address,{:#05X},\"{}\",{},,,",
+                address, instruction, count
+            )?;
+        }
+
+        for (info, count) in self.sorted_opcodes() {
+            writeln!(
+                writer,
+                "opcode,{},{},{},,,",
+                info.pattern, info.mnemonic, count
+            )?;
+        }
+
+        // Subroutines have their own count columns, so the plain count is left empty
+        for (address, counts) in self.sorted_subroutines() {
+            writeln!(
+                writer,
+                "subroutine,{:#05X},,,{},{},{}",
+                address, counts.calls, counts.inclusive, counts.exclusive
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn write_json<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"total\": {},", self.total)?;
+
+        let addresses: Vec<String> = self
+            .sorted_addresses()
+            .into_iter()
+            .map(|(address, instruction, count)| {
+                format!(
+                    "{{\"address\": {}, \"instruction\": \"{}\", \"count\": {}}}",
+                    address, instruction, count
+                )
+            })
+            .collect();
+        write_json_array(writer, "addresses", &addresses, ",")?;
+
+        let opcodes: Vec<String> = self
+            .sorted_opcodes()
+            .into_iter()
+            .map(|(info, count)| {
+                format!(
+                    "{{\"opcode\": \"{}\", \"mnemonic\": \"{}\", \"count\": {}}}",
+                    info.pattern, info.mnemonic, count
+                )
+            })
+            .collect();
+        write_json_array(writer, "opcodes", &opcodes, ",")?;
+
+        let subroutines: Vec<String> = self
+            .sorted_subroutines()
+            .into_iter()
+            .map(|(address, counts)| {
+                format!(
+                    "{{\"address\": {}, \"calls\": {}, \"inclusive\": {}, \"exclusive\": {}}}",
+                    address, counts.calls, counts.inclusive, counts.exclusive
+                )
+            })
+            .collect();
+        write_json_array(writer, "subroutines", &subroutines, "")?;
+
+        writeln!(writer, "}}")
+    }
+}
+
+fn write_json_array<W: Write>(
+    writer: &mut W,
+    name: &str,
+    entries: &[String],
+    trailer: &str,
+) -> io::Result<()> {
+    if entries.is_empty() {
+        return writeln!(writer, "  \"{}\": []{}", name, trailer);
+    }
+
+    writeln!(writer, "  \"{}\": [", name)?;
+    for (index, entry) in entries.iter().enumerate() {
+        let separator = if index + 1 < entries.len() { "," } else { "" };
+        writeln!(writer, "    {}{}", entry, separator)?;
+    }
+    writeln!(writer, "  ]{}", trailer)
+}
+
+impl StepHook for Arc<Mutex<Profiler>> {
+    fn before_step(&mut self, instruction: &Instruction, registers: &Registers, _ram: &RAM) {
+        self.lock().unwrap().record(registers.pc, instruction);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction::*;
+    use crate::machine::Machine;
+    use crate::{random, settings};
+
+    // A main routine at 0x200 calling 0x300 twice, which calls 0x400 once each time
+    fn profile_nested_calls() -> Profiler {
+        let mut profiler = Profiler::new();
+        let trace = [
+            (0x200, ClearScreen),
+            (0x202, CallNNN { address: 0x300 }),
+            (0x300, StoreNNN { value: 0x123 }),
+            (0x302, CallNNN { address: 0x400 }),
+            (0x400, Return),
+            (0x304, Return),
+            (0x204, CallNNN { address: 0x300 }),
+            (0x300, StoreNNN { value: 0x123 }),
+            (0x302, CallNNN { address: 0x400 }),
+            (0x400, Return),
+            (0x304, Return),
+            (0x206, JumpNNN { address: 0x206 }),
+        ];
+
+        for (pc, instruction) in trace {
+            profiler.record(pc, &instruction);
+        }
+
+        profiler
+    }
+
+    #[test]
+    fn count_addresses_and_opcodes() {
+        let profiler = profile_nested_calls();
+
+        assert_eq!(12, profiler.total());
+        assert_eq!(1, profiler.address_count(0x200));
+        assert_eq!(2, profiler.address_count(0x300));
+        assert_eq!(0, profiler.address_count(0x208));
+
+        assert_eq!(4, profiler.opcode_count("2NNN"));
+        assert_eq!(4, profiler.opcode_count("00EE"));
+        assert_eq!(0, profiler.opcode_count("DXYN"));
+    }
+
+    #[test]
+    fn count_subroutines() {
+        let profiler = profile_nested_calls();
+
+        let main = profiler.subroutine(0x200).unwrap();
+        assert_eq!(1, main.calls);
+        assert_eq!(12, main.inclusive);
+        assert_eq!(4, main.exclusive);
+
+        let outer = profiler.subroutine(0x300).unwrap();
+        assert_eq!(2, outer.calls);
+        assert_eq!(8, outer.inclusive);
+        assert_eq!(6, outer.exclusive);
+
+        let inner = profiler.subroutine(0x400).unwrap();
+        assert_eq!(2, inner.calls);
+        assert_eq!(2, inner.inclusive);
+        assert_eq!(2, inner.exclusive);
+    }
+
+    #[test]
+    fn recursion_counts_once() {
+        let mut profiler = Profiler::new();
+        profiler.record(0x200, &CallNNN { address: 0x300 });
+        profiler.record(0x300, &CallNNN { address: 0x300 });
+        profiler.record(0x300, &Return);
+
+        let recursive = profiler.subroutine(0x300).unwrap();
+        assert_eq!(2, recursive.calls);
+        assert_eq!(2, recursive.inclusive);
+        assert_eq!(2, recursive.exclusive);
+    }
+
+    #[test]
+    fn csv_report() {
+        let mut report = Vec::new();
+        profile_nested_calls()
+            .write_report(ReportFormat::Csv, &mut report)
+            .unwrap();
+        let report = String::from_utf8(report).unwrap();
+
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(
+            "kind,key,description,count,calls,inclusive,exclusive",
+            lines[0]
+        );
+        assert!(lines.contains(&"address,0x300,\"LD I, 0x123\",2,,,"));
+        assert!(lines.contains(&"opcode,2NNN,CALL,4,,,"));
+        assert!(lines.contains(&"subroutine,0x300,,,2,8,6"));
+        assert!(lines.contains(&"subroutine,0x400,,,2,2,2"));
+    }
+
+    #[test]
+    fn json_report() {
+        let mut report = Vec::new();
+        profile_nested_calls()
+            .write_report(ReportFormat::Json, &mut report)
+            .unwrap();
+        let report = String::from_utf8(report).unwrap();
+
+        assert!(report.starts_with("{\n  \"total\": 12,\n"));
+        assert!(report.contains("{\"opcode\": \"00EE\", \"mnemonic\": \"RET\", \"count\": 4}"));
+        assert!(report.contains(
+            "{\"address\": 1024, \"calls\": 2, \"inclusive\": 2, \"exclusive\": 2}\n  ]\n}"
+        ));
+    }
+
+    #[test]
+    fn profile_machine() {
+        let program = vec![
+            CallNNN { address: 0x204 },
+            JumpNNN { address: 0x200 },
+            ClearScreen,
+            StoreXNN {
+                register: 0,
+                value: 1,
+            },
+            Return,
+        ];
+
+        // Both engines report every executed instruction to the hook
+        for engine in [settings::Engine::Interpreter, settings::Engine::Threaded] {
+            let profiler = Arc::new(Mutex::new(Profiler::new()));
+            let mut machine = Machine::new_headless_with_settings(
+                random::FixedRandomSource::new(vec![0]),
+                settings::Settings::default().with_engine(engine),
+            );
+            machine.add_hook(Box::new(Arc::clone(&profiler)));
+            machine.load_program(&program);
+
+            let mut executed = 0;
+            while executed < 50 {
+                executed += match engine {
+                    settings::Engine::Interpreter => machine.step_program().map(|_| 1).unwrap(),
+                    settings::Engine::Threaded => machine.step_block().unwrap(),
+                };
+            }
+
+            let profiler = profiler.lock().unwrap();
+            assert_eq!(50, profiler.total());
+            assert_eq!(10, profiler.address_count(0x200));
+            assert_eq!(10, profiler.subroutine(0x204).unwrap().calls);
+            assert_eq!(30, profiler.subroutine(0x204).unwrap().exclusive);
+        }
+    }
+}
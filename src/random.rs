@@ -1,7 +1,19 @@
 use rand::Rng;
 
+/// Machine state available to random sources that derive their numbers from it
+pub struct RandomContext {
+    // Frames of 60Hz timer ticks since the machine started
+    pub frames: u64,
+}
+
 pub trait RandomSource {
-    fn gen(&mut self) -> u8;
+    fn gen(&mut self, context: Option<&RandomContext>) -> u8;
+}
+
+impl<R: RandomSource + ?Sized> RandomSource for Box<R> {
+    fn gen(&mut self, context: Option<&RandomContext>) -> u8 {
+        (**self).gen(context)
+    }
 }
 
 pub struct FixedRandomSource {
@@ -20,21 +32,119 @@ impl FixedRandomSource {
 }
 
 impl RandomSource for FixedRandomSource {
-    fn gen(&mut self) -> u8 {
+    fn gen(&mut self, _context: Option<&RandomContext>) -> u8 {
         let number = *self.numbers.get(self.index).unwrap();
         self.index = (self.index + 1) % self.numbers.len();
         number
     }
 }
 
+/// Deterministic source seeded with a 64-bit value, using the SplitMix64 generator
+/// The whole state is one u64, so it can be saved and restored to replay a run
+pub struct SeededRandomSource {
+    state: u64,
+}
+
+impl SeededRandomSource {
+    pub fn new(seed: u64) -> SeededRandomSource {
+        SeededRandomSource { state: seed }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn restore(&mut self, state: u64) {
+        self.state = state;
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl RandomSource for SeededRandomSource {
+    fn gen(&mut self, _context: Option<&RandomContext>) -> u8 {
+        // The high bits are the best mixed
+        (self.next_u64() >> 56) as u8
+    }
+}
+
 pub struct ThreadRandomSource;
 
 impl RandomSource for ThreadRandomSource {
-    fn gen(&mut self) -> u8 {
+    fn gen(&mut self, _context: Option<&RandomContext>) -> u8 {
         rand::thread_rng().gen()
     }
 }
 
+/// The COSMAC VIP interpreter's CXNN routine
+/// R9 counts up once per frame and once per call, and its low byte picks a byte of the interpreter's
+/// own code page at 0x100-0x1FF to mix into the previous result kept in its high byte
+pub struct VipRandomSource {
+    // The interpreter's code page at 0x100-0x1FF
+    page: [u8; 0x100],
+    r9: u16,
+    frames: u64,
+}
+
+impl VipRandomSource {
+    pub fn new(page: [u8; 0x100]) -> VipRandomSource {
+        VipRandomSource {
+            page,
+            r9: 0,
+            frames: 0,
+        }
+    }
+
+    /// Take the code page from an image of the interpreter as loaded at 0x000
+    pub fn from_interpreter(interpreter: &[u8]) -> Option<VipRandomSource> {
+        let page = interpreter.get(0x100..0x200)?.try_into().ok()?;
+
+        Some(VipRandomSource::new(page))
+    }
+
+    pub fn state(&self) -> u16 {
+        self.r9
+    }
+
+    pub fn restore(&mut self, r9: u16) {
+        self.r9 = r9;
+    }
+}
+
+impl RandomSource for VipRandomSource {
+    fn gen(&mut self, context: Option<&RandomContext>) -> u8 {
+        // The interrupt routine increments R9 once every frame
+        if let Some(context) = context {
+            let elapsed = context.frames.wrapping_sub(self.frames);
+            self.r9 = self.r9.wrapping_add(elapsed as u16);
+            self.frames = context.frames;
+        }
+
+        // INC R9, then read the byte of the code page at R9.0
+        self.r9 = self.r9.wrapping_add(1);
+        let page_byte = self.page[(self.r9 & 0xFF) as usize];
+
+        // ADD: D = R9.1 + M(0x100 + R9.0), kept in VX
+        let (sum, carry) = ((self.r9 >> 8) as u8).overflowing_add(page_byte);
+
+        // SHRC: shift right, with the carry from the add shifted into the top bit
+        let shifted = (sum >> 1) | ((carry as u8) << 7);
+
+        // ADD: D = D + VX, which becomes the new R9.1
+        let result = shifted.wrapping_add(sum);
+        self.r9 = (self.r9 & 0x00FF) | ((result as u16) << 8);
+
+        result
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -43,11 +153,68 @@ mod tests {
     pub fn fixed_random_source() {
         let mut rand = FixedRandomSource::new(vec![12, 34, 56]);
 
-        assert_eq!(12, rand.gen());
-        assert_eq!(34, rand.gen());
-        assert_eq!(56, rand.gen());
-        assert_eq!(12, rand.gen());
-        assert_eq!(34, rand.gen());
-        assert_eq!(56, rand.gen());
+        assert_eq!(12, rand.gen(None));
+        assert_eq!(34, rand.gen(None));
+        assert_eq!(56, rand.gen(None));
+        assert_eq!(12, rand.gen(None));
+        assert_eq!(34, rand.gen(None));
+        assert_eq!(56, rand.gen(None));
+    }
+
+    #[test]
+    pub fn seeded_random_source() {
+        // The first output of SplitMix64 seeded with 0 is 0xE220A8397B1DCDAF
+        let mut rand = SeededRandomSource::new(0);
+        assert_eq!(0xE2, rand.gen(None));
+
+        // The same seed gives the same sequence
+        let first: Vec<u8> = (0..16).map(|_| rand.gen(None)).collect();
+        let mut rand = SeededRandomSource::new(0);
+        rand.gen(None);
+        let second: Vec<u8> = (0..16).map(|_| rand.gen(None)).collect();
+        assert_eq!(first, second);
+
+        // Restoring a saved state replays the numbers generated after it was saved
+        let state = rand.state();
+        let expected: Vec<u8> = (0..16).map(|_| rand.gen(None)).collect();
+        rand.restore(state);
+        let replayed: Vec<u8> = (0..16).map(|_| rand.gen(None)).collect();
+        assert_eq!(expected, replayed);
+
+        // Different seeds give different sequences
+        let mut other = SeededRandomSource::new(1);
+        let other: Vec<u8> = (0..16).map(|_| other.gen(None)).collect();
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    pub fn vip_random_source() {
+        let mut interpreter = vec![0; 0x200];
+        interpreter[0x101] = 0x35;
+        interpreter[0x102] = 0xF0;
+        interpreter[0x106] = 0x81;
+
+        let mut rand = VipRandomSource::from_interpreter(&interpreter).unwrap();
+        let mut context = RandomContext { frames: 0 };
+
+        // 0x00 + 0x35 = 0x35, shifted to 0x1A, plus 0x35
+        assert_eq!(0x4F, rand.gen(Some(&context)));
+        assert_eq!(0x4F01, rand.state());
+
+        // 0x4F + 0xF0 = 0x3F with carry, shifted to 0x9F, plus 0x3F
+        assert_eq!(0xDE, rand.gen(Some(&context)));
+
+        // Three frames later R9.0 has moved on by four
+        context.frames = 3;
+        // 0xDE + 0x81 = 0x5F with carry, shifted to 0xAF, plus 0x5F
+        assert_eq!(0x0E, rand.gen(Some(&context)));
+        assert_eq!(0x0E06, rand.state());
+
+        // Restoring the state replays the sequence
+        rand.restore(0x4F01);
+        assert_eq!(0xDE, rand.gen(Some(&context)));
+
+        // The interpreter must reach the end of its code page
+        assert!(VipRandomSource::from_interpreter(&interpreter[..0x1FF]).is_none());
     }
 }
@@ -1,7 +1,8 @@
+use crate::instruction::InstructionError;
 use crate::memory;
+use crate::settings::{Settings, StackLocation, StackOverflow};
 
 const FLAG_REGISTER: u8 = 0xF;
-const STACK_SIZE: u8 = 16;
 
 pub struct Registers {
     pub v: [u8; 16],
@@ -11,23 +12,64 @@ pub struct Registers {
     pub dt: u8,
     pub st: u8,
 
-    pub sp: u8,
-    pub stack: [memory::Address; STACK_SIZE as usize],
+    pub sp: usize,
+    // Unused when the stack is kept in memory
+    pub stack: Vec<memory::Address>,
+
+    // Keeps PC and I within the size of memory
+    address_mask: memory::Address,
+    // Number of stack levels, if limited
+    stack_depth: Option<usize>,
+    stack_overflow: StackOverflow,
 }
 
 impl Registers {
     pub fn new() -> Registers {
+        Registers::from_settings(&Settings::default())
+    }
+
+    pub fn from_settings(settings: &Settings) -> Registers {
+        let memory_size = settings.memory_size.bytes();
+        let address_mask = (memory_size - 1) as memory::Address;
+
+        // A stack in memory can be no deeper than the memory above its start
+        let stack_depth = match settings.stack_location {
+            StackLocation::Registers => settings.stack_depth.levels(),
+            StackLocation::Memory { address } => {
+                let available = memory_size.saturating_sub(address as usize) / 2;
+                Some(
+                    settings
+                        .stack_depth
+                        .levels()
+                        .map_or(available, |levels| levels.min(available)),
+                )
+            }
+        };
+
         Registers {
             v: [0; 16],
             i: 0,
-            pc: 0x200,
+            pc: settings.program_start & address_mask,
             sp: 0,
             dt: 0,
             st: 0,
-            stack: [0; 16],
+            stack: vec![0; stack_depth.unwrap_or(0)],
+            address_mask,
+            stack_depth,
+            stack_overflow: settings.stack_overflow,
         }
     }
 
+    /// Continue execution at an address, wrapped to the size of memory
+    pub fn jump(&mut self, address: memory::Address) {
+        self.pc = address & self.address_mask;
+    }
+
+    /// Set I to an address, wrapped to the size of memory
+    pub fn set_i(&mut self, address: memory::Address) {
+        self.i = address & self.address_mask;
+    }
+
     pub fn set_register(&mut self, index: u8, value: u8) {
         self.v[index as usize] = value;
     }
@@ -46,7 +88,7 @@ impl Registers {
 
     pub fn advance_pc(&mut self) {
         // Instructions are 2-byte aligned, so advance by 2
-        self.pc += 2;
+        self.jump(self.pc.wrapping_add(2));
     }
 
     pub fn tick_timers(&mut self) {
@@ -54,40 +96,65 @@ impl Registers {
         self.st = if self.st >= 1 { self.st - 1 } else { self.st };
     }
 
-    pub fn stack_call(&mut self, address: memory::Address) {
-        // Put the PC on top of the stack
-        self.stack[self.sp as usize] = self.pc;
+    /// Take the next stack level for a call, returning its index
+    pub fn push_level(&mut self) -> Result<usize, InstructionError> {
+        let level = self.sp;
+
+        self.sp = match self.stack_depth {
+            Some(depth) if level + 1 >= depth => match self.stack_overflow {
+                _ if level >= depth => return Err(InstructionError::StackOverflow(self.pc)),
+                StackOverflow::Wrap => 0,
+                StackOverflow::Fault => depth,
+            },
+            _ => level + 1,
+        };
+
+        Ok(level)
+    }
+
+    /// Release the top stack level for a return, returning its index
+    pub fn pop_level(&mut self) -> Result<usize, InstructionError> {
+        self.sp = match (self.sp, self.stack_depth, self.stack_overflow) {
+            (0, Some(depth), StackOverflow::Wrap) if depth > 0 => depth - 1,
+            (0, _, _) => return Err(InstructionError::StackUnderflow(self.pc)),
+            (sp, _, _) => sp - 1,
+        };
 
-        // Increment the stack pointer
-        self.sp = self.sp + 1 % STACK_SIZE;
+        Ok(self.sp)
+    }
+
+    pub fn stack_call(&mut self, address: memory::Address) -> Result<(), InstructionError> {
+        // Put the PC on top of the stack
+        let level = self.push_level()?;
+        if level >= self.stack.len() {
+            self.stack.resize(level + 1, 0);
+        }
+        self.stack[level] = self.pc;
 
         // Jump to the called routine
-        self.pc = address & 0xFFF;
+        self.jump(address);
 
         // Do not advance the PC for calls
         // The called address is the first instruction of the routine
+        Ok(())
     }
 
-    pub fn stack_return(&mut self) {
-        // Decrement the stack pointer and wrap if needed
-        self.sp = if self.sp == 0 {
-            STACK_SIZE - 1
-        } else {
-            self.sp - 1
-        };
-
+    pub fn stack_return(&mut self) -> Result<(), InstructionError> {
         // Set the program counter to the top of the stack
-        self.pc = self.stack[self.sp as usize];
+        let level = self.pop_level()?;
+        self.pc = self.stack[level];
 
         // Returning always advances the PC
         // The address on top of the stack will always be that of the CALL
         self.advance_pc();
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::settings::MemorySize;
 
     #[test]
     fn advance_pc() {
@@ -104,6 +171,33 @@ mod tests {
         assert_eq!(0x548, registers.pc);
     }
 
+    #[test]
+    fn address_wrapping() {
+        let settings = Settings::default().with_memory_size(MemorySize::Vip);
+        let mut registers = Registers::from_settings(&settings);
+
+        registers.pc = 0x7FE;
+        registers.advance_pc();
+        assert_eq!(0x000, registers.pc);
+
+        registers.jump(0xA04);
+        assert_eq!(0x204, registers.pc);
+
+        registers.set_i(0xFFF);
+        assert_eq!(0x7FF, registers.i);
+
+        // 64 KiB covers every address
+        let settings = Settings::default().with_memory_size(MemorySize::XoChip);
+        let mut registers = Registers::from_settings(&settings);
+
+        registers.set_i(0xFFFF);
+        assert_eq!(0xFFFF, registers.i);
+
+        registers.pc = 0xFFFE;
+        registers.advance_pc();
+        assert_eq!(0x0000, registers.pc);
+    }
+
     #[test]
     fn tick_timers() {
         let mut registers = Registers::new();
@@ -138,14 +232,67 @@ mod tests {
 
         let start_pc = registers.pc;
 
-        registers.stack_call(0x500);
+        registers.stack_call(0x500).unwrap();
         assert_eq!(start_pc, registers.stack[0]);
         assert_eq!(1, registers.sp);
         assert_eq!(0x500, registers.pc);
 
-        registers.stack_return();
+        registers.stack_return().unwrap();
         assert_eq!(start_pc, registers.stack[0]);
         assert_eq!(0, registers.sp);
         assert_eq!(start_pc + 2, registers.pc);
     }
+
+    #[test]
+    fn stack_limits() {
+        use crate::settings::StackDepth;
+
+        // Faulting stacks stop at their depth
+        let settings = Settings::default().with_stack_depth(StackDepth::Vip);
+        let mut registers = Registers::from_settings(&settings);
+
+        for level in 0..12 {
+            registers.pc = 0x300 + level * 2;
+            registers.stack_call(0x400).unwrap();
+        }
+        assert_eq!(
+            Err(InstructionError::StackOverflow(0x400)),
+            registers.stack_call(0x400)
+        );
+        assert_eq!(12, registers.sp);
+
+        registers.stack_return().unwrap();
+        assert_eq!(0x318, registers.pc);
+
+        let mut registers = Registers::new();
+        assert_eq!(
+            Err(InstructionError::StackUnderflow(0x200)),
+            registers.stack_return()
+        );
+
+        // Wrapping stacks overwrite their oldest levels
+        let settings = settings.with_stack_overflow(StackOverflow::Wrap);
+        let mut registers = Registers::from_settings(&settings);
+
+        for level in 0..13 {
+            registers.pc = 0x300 + level * 2;
+            registers.stack_call(0x400).unwrap();
+        }
+        assert_eq!(1, registers.sp);
+        assert_eq!(0x318, registers.stack[0]);
+
+        registers.stack_return().unwrap();
+        registers.stack_return().unwrap();
+        assert_eq!(11, registers.sp);
+        assert_eq!(0x318, registers.pc);
+
+        // Unlimited stacks grow as needed
+        let settings = Settings::default().with_stack_depth(StackDepth::Unlimited);
+        let mut registers = Registers::from_settings(&settings);
+
+        for _ in 0..1000 {
+            registers.stack_call(0x400).unwrap();
+        }
+        assert_eq!(1000, registers.stack.len());
+    }
 }
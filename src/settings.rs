@@ -1,6 +1,10 @@
+use crate::font::FontSet;
+use crate::io::graphics::Resolution;
+use crate::memory::{Address, ADDRESS_PROGRAM_START};
+use crate::platform::Platform;
 use std::time;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 pub enum BitShiftMode {
     // Bit shift the Y register and store the result in X
     // This is the documented bit shift mode
@@ -10,13 +14,70 @@ pub enum BitShiftMode {
     OneRegister,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 pub enum ClockSpeed {
     Unlimited,
     Limited { instruction_time: time::Duration },
+    // Charge each instruction its COSMAC VIP machine-cycle cost against the cycles of a 60 Hz frame
+    // DXYN waits for the next frame before drawing, like the VIP interpreter waits for the vertical interrupt
+    VipCycles,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
+pub enum DecodeMode {
+    // Decode the instruction at PC every time it is executed
+    Uncached,
+    // Keep decoded instructions by address until the memory under them is written
+    Cached,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DrawMode {
+    // Clip the pixels of a sprite that run past the edge of the screen
+    Clip,
+    // Wrap the pixels of a sprite that run past the edge of the screen around to the other side
+    Wrap,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Engine {
+    // Decode and execute one instruction at a time
+    Interpreter,
+    // Translate straight-line runs of instructions into blocks of pre-compiled operations
+    Threaded,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum MachineCallMode {
+    // Treat 0NNN as a no-op
+    Ignore,
+    // Stop the machine with an error when a 0NNN call is reached
+    Fault,
+    // Run the native routine registered for the called address, failing if there is none
+    Native,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum MemorySize {
+    // 2 KiB, the smallest COSMAC VIP
+    Vip,
+    // 4 KiB, the address range of a 12-bit address
+    Standard,
+    // 64 KiB, the full range of XO-CHIP's 16-bit addresses
+    XoChip,
+}
+
+impl MemorySize {
+    pub fn bytes(&self) -> usize {
+        match self {
+            MemorySize::Vip => 0x800,
+            MemorySize::Standard => 0x1000,
+            MemorySize::XoChip => 0x10000,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
 pub enum MemoryMode {
     // Advance the I register on store and load instructions
     Advance,
@@ -24,14 +85,107 @@ pub enum MemoryMode {
     NoAdvance,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
+pub enum StackDepth {
+    // 12 levels, as on the COSMAC VIP
+    Vip,
+    // 16 levels, as on most later interpreters
+    Standard,
+    // Limited only by available memory
+    Unlimited,
+}
+
+impl StackDepth {
+    pub fn levels(&self) -> Option<usize> {
+        match self {
+            StackDepth::Vip => Some(12),
+            StackDepth::Standard => Some(16),
+            StackDepth::Unlimited => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum StackLocation {
+    // Keep the call stack in registers outside of emulated memory
+    Registers,
+    // Keep the call stack in emulated memory starting at an address, where programs can read and write it
+    // Each level holds a 2-byte big-endian return address
+    Memory { address: Address },
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum StackOverflow {
+    // Stop the machine with an error when the stack overflows or underflows
+    Fault,
+    // Wrap the stack pointer around to the other end of the stack
+    Wrap,
+}
+
+#[derive(Copy, Clone, Debug)]
 pub struct Settings {
     pub bit_shift_mode: BitShiftMode,
     pub clock_speed: ClockSpeed,
+    pub decode_mode: DecodeMode,
+    // How sprites are drawn past the edge of the screen
+    // The starting position always wraps onto the screen
+    pub draw_mode: DrawMode,
+    pub engine: Engine,
+    pub font: FontSet,
+    pub font_address: Address,
+    pub machine_call_mode: MachineCallMode,
     pub memory_mode: MemoryMode,
+    pub memory_size: MemorySize,
+    // Address programs are loaded at and start running from
+    pub program_start: Address,
+    // Display resolution the machine starts in
+    pub resolution: Resolution,
+    pub stack_depth: StackDepth,
+    pub stack_location: StackLocation,
+    pub stack_overflow: StackOverflow,
 }
 
 impl Settings {
+    /// Settings matching the quirks of a platform's interpreter
+    pub fn for_platform(platform: Platform) -> Self {
+        let settings = Settings::default();
+
+        match platform {
+            // The 1802 machines keep their fonts in ROM, so they go where the interpreter would be
+            Platform::CosmacVip => settings
+                .with_bit_shift_mode(BitShiftMode::TwoRegister)
+                .with_memory_mode(MemoryMode::Advance)
+                .with_font(FontSet::Vip)
+                .with_font_address(0x000)
+                .with_stack_depth(StackDepth::Vip)
+                .with_stack_location(StackLocation::Memory { address: 0xEA0 })
+                .with_stack_overflow(StackOverflow::Wrap),
+            Platform::Eti660 => settings
+                .with_bit_shift_mode(BitShiftMode::TwoRegister)
+                .with_memory_mode(MemoryMode::Advance)
+                .with_font(FontSet::Eti660)
+                .with_font_address(0x000)
+                .with_program_start(0x600),
+            // The HP48 fonts are in ROM too, and emulators conventionally put them at 0x050
+            Platform::Chip48 => settings
+                .with_bit_shift_mode(BitShiftMode::OneRegister)
+                .with_memory_mode(MemoryMode::Advance)
+                .with_font_address(0x050),
+            Platform::SuperChip => settings
+                .with_bit_shift_mode(BitShiftMode::OneRegister)
+                .with_memory_mode(MemoryMode::NoAdvance)
+                .with_font(FontSet::SuperChip)
+                .with_font_address(0x050),
+            // Octo loads its fonts at the start of memory
+            Platform::XoChip => settings
+                .with_bit_shift_mode(BitShiftMode::TwoRegister)
+                .with_memory_mode(MemoryMode::Advance)
+                .with_memory_size(MemorySize::XoChip)
+                .with_draw_mode(DrawMode::Wrap)
+                .with_font_address(0x000),
+        }
+    }
+
     pub fn with_bit_shift_mode(mut self, bit_shift_mode: BitShiftMode) -> Self {
         self.bit_shift_mode = bit_shift_mode;
         self
@@ -42,10 +196,70 @@ impl Settings {
         self
     }
 
+    pub fn with_decode_mode(mut self, decode_mode: DecodeMode) -> Self {
+        self.decode_mode = decode_mode;
+        self
+    }
+
+    pub fn with_draw_mode(mut self, draw_mode: DrawMode) -> Self {
+        self.draw_mode = draw_mode;
+        self
+    }
+
+    pub fn with_engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    pub fn with_font(mut self, font: FontSet) -> Self {
+        self.font = font;
+        self
+    }
+
+    pub fn with_font_address(mut self, font_address: Address) -> Self {
+        self.font_address = font_address;
+        self
+    }
+
+    pub fn with_machine_call_mode(mut self, machine_call_mode: MachineCallMode) -> Self {
+        self.machine_call_mode = machine_call_mode;
+        self
+    }
+
     pub fn with_memory_mode(mut self, memory_mode: MemoryMode) -> Self {
         self.memory_mode = memory_mode;
         self
     }
+
+    pub fn with_memory_size(mut self, memory_size: MemorySize) -> Self {
+        self.memory_size = memory_size;
+        self
+    }
+
+    pub fn with_program_start(mut self, program_start: Address) -> Self {
+        self.program_start = program_start;
+        self
+    }
+
+    pub fn with_resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    pub fn with_stack_depth(mut self, stack_depth: StackDepth) -> Self {
+        self.stack_depth = stack_depth;
+        self
+    }
+
+    pub fn with_stack_location(mut self, stack_location: StackLocation) -> Self {
+        self.stack_location = stack_location;
+        self
+    }
+
+    pub fn with_stack_overflow(mut self, stack_overflow: StackOverflow) -> Self {
+        self.stack_overflow = stack_overflow;
+        self
+    }
 }
 
 impl Default for Settings {
@@ -54,7 +268,19 @@ impl Default for Settings {
         Settings {
             bit_shift_mode: BitShiftMode::OneRegister,
             clock_speed: ClockSpeed::Unlimited,
+            decode_mode: DecodeMode::Uncached,
+            draw_mode: DrawMode::Clip,
+            engine: Engine::Interpreter,
+            font: FontSet::Standard,
+            font_address: 0x000,
+            machine_call_mode: MachineCallMode::Fault,
             memory_mode: MemoryMode::NoAdvance,
+            memory_size: MemorySize::Standard,
+            program_start: ADDRESS_PROGRAM_START as Address,
+            resolution: Resolution::Low,
+            stack_depth: StackDepth::Standard,
+            stack_location: StackLocation::Registers,
+            stack_overflow: StackOverflow::Fault,
         }
     }
 }
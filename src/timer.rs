@@ -1,7 +1,15 @@
+use std::thread;
 use std::time::{Duration, Instant};
 
 pub trait Timer {
     fn should_tick(&mut self) -> bool;
+
+    /// Wait until the timer ticks
+    fn wait_for_tick(&mut self) {
+        while !self.should_tick() {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
 }
 
 // Timer implementation based on number of instructions executed
@@ -32,6 +40,11 @@ impl Timer for InstructionTimer {
 
         should_tick
     }
+
+    // Instructions aren't running while waiting, so the next tick starts straight away
+    fn wait_for_tick(&mut self) {
+        self.counter = InstructionTimer::INSTRUCTIONS_PER_TICK;
+    }
 }
 
 pub struct WallTimer {
@@ -95,6 +108,13 @@ mod tests {
             assert!(timer.should_tick());
         }
         assert!(!timer.should_tick());
+
+        // Waiting for a tick starts a full tick's worth of instructions
+        timer.wait_for_tick();
+        for _ in 0..7 {
+            assert!(!timer.should_tick());
+        }
+        assert!(timer.should_tick());
     }
 
     #[test]